@@ -0,0 +1,7 @@
+#!/usr/bin/env python3
+
+# Hands sending the response body over to the server
+print("Content-Type: text/plain")
+print("X-Accel-Redirect: /fallback/app.html")
+print()
+print("This body is discarded")
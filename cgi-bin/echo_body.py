@@ -0,0 +1,10 @@
+#!/usr/bin/env python3
+import sys
+
+# Echoes the request body back byte for byte
+data = sys.stdin.buffer.read()
+
+sys.stdout.write("Content-Type: application/octet-stream\r\n")
+sys.stdout.write("Content-Length: %d\r\n\r\n" % len(data))
+sys.stdout.flush()
+sys.stdout.buffer.write(data)
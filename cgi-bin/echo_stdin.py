@@ -0,0 +1,11 @@
+#!/usr/bin/env python3
+import os
+import sys
+
+# Reads the request body until EOF and echoes its length back
+data = sys.stdin.read()
+
+print("Content-Type: text/plain")
+print()
+print("CONTENT_LENGTH=%s" % os.environ.get("CONTENT_LENGTH", ""))
+print("read=%d" % len(data))
@@ -0,0 +1,6 @@
+#!/usr/bin/env python3
+import sys
+
+# Fails before writing any output, leaving only an error on stderr
+sys.stderr.write("boom: <missing> & \"broken\"\n")
+sys.exit(1)
@@ -0,0 +1,17 @@
+#!/usr/bin/env python3
+import sys
+import zlib
+import struct
+
+# Emits text headers followed by a binary PNG body (a 1x1 red pixel)
+def chunk(kind, data):
+    return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+png = b"\x89PNG\r\n\x1a\n"
+png += chunk(b"IHDR", struct.pack(">IIBBBBB", 1, 1, 8, 2, 0, 0, 0))
+png += chunk(b"IDAT", zlib.compress(b"\x00\xff\x00\x00"))
+png += chunk(b"IEND", b"")
+
+sys.stdout.write("Content-Type: image/png\r\n\r\n")
+sys.stdout.flush()
+sys.stdout.buffer.write(png)
@@ -0,0 +1,9 @@
+#!/usr/bin/env python3
+import os
+import sys
+
+# Echoes the method the script was run for and the body it was given
+body = sys.stdin.read(int(os.environ.get("CONTENT_LENGTH") or 0))
+print("Content-Type: text/plain")
+print()
+print("%s %s" % (os.environ.get("REQUEST_METHOD", ""), body))
@@ -0,0 +1,7 @@
+#!/usr/bin/env python3
+import os
+
+# Echoes QUERY_STRING exactly as the server passed it
+print("Content-Type: text/plain")
+print()
+print(os.environ.get("QUERY_STRING", ""))
@@ -0,0 +1,7 @@
+#!/usr/bin/env python3
+
+# Advertises byte ranges the server must not pretend to honour for CGI
+print("Content-Type: text/plain")
+print("Accept-Ranges: bytes")
+print()
+print("0123456789abcdef")
@@ -0,0 +1,9 @@
+#!/usr/bin/env python3
+import os
+
+# Redirect to a local path with ?local, otherwise to an absolute URL
+if os.environ.get('QUERY_STRING', '') == 'local':
+    print("Location: /static/test.txt")
+else:
+    print("Location: http://example.com/")
+print()
@@ -0,0 +1,7 @@
+#!/usr/bin/env python3
+import os
+
+# Echoes the request id the server passed along
+print("Content-Type: text/plain")
+print()
+print(os.environ.get("HTTP_X_REQUEST_ID", ""))
@@ -0,0 +1,7 @@
+#!/usr/bin/env python3
+import subprocess
+
+# Reports the working directory and PATH a shell started by the script sees
+print("Content-Type: text/plain")
+print()
+print(subprocess.run(["sh", "-c", "pwd; echo $PATH"], capture_output=True, text=True).stdout, end="")
@@ -0,0 +1,11 @@
+#!/usr/bin/env python3
+import sys
+import time
+
+# Waits a second before reading the request body, then echoes its length
+time.sleep(1)
+data = sys.stdin.buffer.read()
+
+print("Content-Type: text/plain")
+print()
+print("read=%d" % len(data))
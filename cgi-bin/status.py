@@ -0,0 +1,11 @@
+#!/usr/bin/env python3
+import os
+
+# Custom reason phrase by default, numeric-only status with ?plain
+if os.environ.get('QUERY_STRING', '') == 'plain':
+    print("Status: 404")
+else:
+    print("Status: 418 I'm a teapot")
+print("Content-Type: text/plain")
+print()
+print("status test")
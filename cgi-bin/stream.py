@@ -0,0 +1,11 @@
+#!/usr/bin/env python3
+import sys
+import time
+
+# Emits its body in two stages a second apart
+print("Content-Type: text/plain")
+print()
+print("part1")
+sys.stdout.flush()
+time.sleep(1)
+print("part2")
@@ -0,0 +1,10 @@
+#!/usr/bin/env python3
+import sys
+
+# Asks for the trailers the server computes, plus one it doesn't know
+print("Content-Type: text/plain")
+print("Trailer: X-Content-CRC32, Server-Timing, X-Unknown")
+print()
+print("first line")
+sys.stdout.flush()
+print("second line")
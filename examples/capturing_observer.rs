@@ -0,0 +1,81 @@
+use std::process;
+use std::sync::{Arc, Mutex};
+use webserv::config::{Config, Route, ServerConfigBuilder};
+use webserv::http_parser::HttpRequest;
+use webserv::http_response::HttpResponse;
+use webserv::observer::{RequestInfo, RequestObserver};
+use webserv::server::Server;
+
+// Serves ./www on 127.0.0.1:9135 and keeps what the observer was told about
+// each request; /captured lists it, one request per line, so the fields can
+// be checked against the requests that were actually made
+struct CapturingObserver {
+    captured: Arc<Mutex<Vec<String>>>,
+}
+
+impl RequestObserver for CapturingObserver {
+    fn on_request_complete(&mut self, info: &RequestInfo) {
+        self.captured.lock().unwrap().push(format!(
+            "remote={} method={} uri={} status={} bytes={} duration_ms={}",
+            info.remote,
+            info.method,
+            info.uri,
+            info.status,
+            info.bytes,
+            info.duration.as_millis()
+        ));
+    }
+}
+
+fn main() {
+    let captured: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+
+    let mut root = Route::new("/");
+    root.root = Some("./www".to_string());
+    root.methods = vec!["GET".to_string()];
+
+    let mut listing = Route::new("/captured");
+    listing.methods = vec!["GET".to_string()];
+    let handle = captured.clone();
+    listing.handler = Some(Arc::new(move |_request: &HttpRequest| {
+        let mut body = String::new();
+        for line in handle.lock().unwrap().iter() {
+            body.push_str(line);
+            body.push('\n');
+        }
+        let mut response = HttpResponse::new(200);
+        response.add_header("Content-Type".to_string(), "text/plain".to_string());
+        response.set_body_str(&body);
+        response
+    }));
+
+    let config = Config::builder()
+        .server(
+            ServerConfigBuilder::new()
+                .listen("127.0.0.1:9135")
+                .route(root)
+                .route(listing),
+        )
+        .build();
+
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut server = match Server::new(config) {
+        Ok(server) => server.with_observer(Box::new(CapturingObserver { captured })),
+        Err(e) => {
+            eprintln!("Failed to create server: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = server.run() {
+        eprintln!("Server error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,66 @@
+use std::process;
+use std::sync::Arc;
+use webserv::config::{Config, Route, ServerConfigBuilder};
+use webserv::http_parser::HttpRequest;
+use webserv::http_response::HttpResponse;
+use webserv::observer::ClfLogger;
+use webserv::server::Server;
+
+// Serves ./www on 127.0.0.1:9106 from a configuration built in code rather
+// than read from a file, with /api answered by a Rust handler, gzip on and
+// a short access log line
+fn main() {
+    let mut root = Route::new("/");
+    root.root = Some("./www".to_string());
+    root.methods = vec!["GET".to_string()];
+
+    let mut api = Route::new("/api");
+    api.methods = vec!["GET".to_string(), "POST".to_string()];
+    api.handler = Some(Arc::new(|request: &HttpRequest| {
+        let mut response = HttpResponse::new(200);
+        response.add_header("Content-Type".to_string(), "application/json".to_string());
+        response.set_body_str(&format!(
+            "{{\"method\":\"{}\",\"body_length\":{}}}\n",
+            request.method,
+            request.body.len()
+        ));
+        response
+    }));
+
+    let config = Config::builder()
+        .server(
+            ServerConfigBuilder::new()
+                .listen("127.0.0.1:9106")
+                .server_name("localhost")
+                .gzip(true)
+                .gzip_comp_level(9)
+                .method_override(true)
+                .keepalive_timeout(2)
+                .log_format("$request $status")
+                .route(root)
+                .route(api),
+        )
+        .build();
+
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // Small reads, so request bodies arrive over many of them
+    let mut server = match Server::new(config) {
+        Ok(server) => server.with_read_buffer_size(4096).with_observer(Box::new(ClfLogger)),
+        Err(e) => {
+            eprintln!("Failed to create server: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = server.run() {
+        eprintln!("Server error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,70 @@
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+use webserv::clock::ManualClock;
+use webserv::config::{Config, Route, ServerConfigBuilder};
+use webserv::http_parser::HttpRequest;
+use webserv::http_response::HttpResponse;
+use webserv::server::Server;
+
+// Serves ./www on 127.0.0.1:9131 with time standing still until a request
+// to /advance?ms=N moves it on, so session expiry and connection timeouts
+// can be tested at their exact boundaries
+fn main() {
+    let clock = ManualClock::new();
+
+    let mut root = Route::new("/");
+    root.root = Some("./www".to_string());
+    root.methods = vec!["GET".to_string()];
+
+    let mut advance = Route::new("/advance");
+    advance.methods = vec!["GET".to_string()];
+    advance.sessions = Some(false);
+    let handle = clock.clone();
+    advance.handler = Some(Arc::new(move |request: &HttpRequest| {
+        let ms = request.uri.query_params().get("ms").and_then(|ms| ms.parse().ok());
+        let mut response = HttpResponse::new(200);
+        response.add_header("Content-Type".to_string(), "text/plain".to_string());
+        match ms {
+            Some(ms) => {
+                handle.advance(Duration::from_millis(ms));
+                response.set_body_str("advanced\n");
+            }
+            None => {
+                response = HttpResponse::new(400);
+                response.set_body_str("expected ?ms=<milliseconds>\n");
+            }
+        }
+        response
+    }));
+
+    let config = Config::builder()
+        .server(
+            ServerConfigBuilder::new()
+                .listen("127.0.0.1:9131")
+                .route(root)
+                .route(advance),
+        )
+        .build();
+
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut server = match Server::new(config) {
+        Ok(server) => server.with_clock(Arc::new(clock)),
+        Err(e) => {
+            eprintln!("Failed to create server: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = server.run() {
+        eprintln!("Server error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,44 @@
+use std::process;
+use webserv::config::{Config, Route, ServerConfigBuilder};
+use webserv::file_provider::MemoryFileProvider;
+use webserv::server::Server;
+
+// Serves a handful of files registered in memory on 127.0.0.1:9134. The
+// root doesn't exist on disk, so everything served comes from the provider.
+fn main() {
+    let mut files = MemoryFileProvider::new();
+    files.add_file("/memory/index.html", b"<h1>from memory</h1>\n");
+    files.add_file("/memory/docs/a.txt", b"first file\n");
+    files.add_file("/memory/docs/b.txt", b"second file\n");
+
+    let mut root = Route::new("/");
+    root.root = Some("/memory".to_string());
+    root.methods = vec!["GET".to_string()];
+    root.index = vec!["index.html".to_string()];
+    root.autoindex = true;
+
+    let config = Config::builder()
+        .server(ServerConfigBuilder::new().listen("127.0.0.1:9134").route(root))
+        .build();
+
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut server = match Server::with_file_provider(config, Box::new(files)) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Failed to create server: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = server.run() {
+        eprintln!("Server error: {}", e);
+        process::exit(1);
+    }
+}
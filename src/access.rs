@@ -0,0 +1,94 @@
+use std::net::IpAddr;
+
+// Addresses matched by an allow or deny rule: `all`, a single address or a
+// CIDR block such as 10.0.0.0/8
+#[derive(Debug, Clone, PartialEq)]
+enum AddressMatch {
+    All,
+    Network(IpAddr, u8),
+}
+
+impl AddressMatch {
+    fn parse(value: &str) -> Result<Self, String> {
+        if value == "all" {
+            return Ok(AddressMatch::All);
+        }
+
+        let invalid = || format!("Invalid address '{}'", value);
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(AddressMatch::Network(addr, prefix))
+    }
+
+    fn matches(&self, ip: IpAddr) -> bool {
+        match (self, ip.to_canonical()) {
+            (AddressMatch::All, _) => true,
+            (AddressMatch::Network(IpAddr::V4(net), prefix), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(ip) & mask
+            }
+            (AddressMatch::Network(IpAddr::V6(net), prefix), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// `allow` and `deny` rules checked in order, as in nginx: the first one
+// matching the client decides, and a client no rule matches is let through
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    rules: Vec<(bool, AddressMatch)>,
+}
+
+impl AccessList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a rule from its directive and address, e.g. ("deny", "all")
+    pub fn add(&mut self, directive: &str, address: &str) -> Result<(), String> {
+        let allow = match directive {
+            "allow" => true,
+            "deny" => false,
+            other => return Err(format!("Expected allow or deny, found '{}'", other)),
+        };
+        self.rules.push((allow, AddressMatch::parse(address)?));
+        Ok(())
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.rules
+            .iter()
+            .find(|(_, address)| address.matches(ip))
+            .is_none_or(|(allow, _)| *allow)
+    }
+}
+
+// Access rules that only apply to methods outside the listed ones, like
+// nginx's `limit_except GET { allow 127.0.0.1; deny all; }`
+#[derive(Debug, Clone)]
+pub struct LimitExcept {
+    pub methods: Vec<String>,
+    pub access: AccessList,
+}
+
+impl LimitExcept {
+    // Whether a client at `ip` may use `method`; allowing GET allows HEAD too
+    pub fn permits(&self, method: &str, ip: IpAddr) -> bool {
+        let exempt = self.methods.iter().any(|allowed| {
+            allowed == method || (allowed == "GET" && method == "HEAD")
+        });
+        exempt || self.access.allows(ip)
+    }
+}
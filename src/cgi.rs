@@ -1,10 +1,16 @@
 use std::collections::HashMap;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Stdio};
+
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+// Headers with a dedicated variable, plus Proxy, which would set HTTP_PROXY
+// and redirect the outbound requests of many HTTP libraries
+const SKIPPED_HEADERS: [&str; 3] = ["content-length", "content-type", "proxy"];
 
 pub struct CgiHandler;
 
 impl CgiHandler {
+  #[allow(clippy::too_many_arguments)]
   pub fn execute(
     cgi_path: &str,
     script_path: &str,
@@ -15,7 +21,11 @@ impl CgiHandler {
     server_addr: &str,
     server_port: u16,
     remote_addr: &str,
-) -> Result<Vec<u8>, String> {
+    pass_env: &[String],
+    max_headers: usize,
+    working_dir: Option<&str>,
+    path_env: Option<&str>,
+) -> Result<Child, String> {
     // Create owned strings for environment variables
     let server_port_str = server_port.to_string();
     let content_length_str = body.len().to_string();
@@ -47,32 +57,61 @@ impl CgiHandler {
         env_vars.insert("CONTENT_TYPE", content_type);
     }
     
-    if !body.is_empty() {
+    // Scripts commonly read CONTENT_LENGTH bytes for POST, so an empty body
+    // must still announce itself as zero
+    if !body.is_empty() || method == "POST" {
         env_vars.insert("CONTENT_LENGTH", &content_length_str);
     }
 
-    // Pass other headers as HTTP_*
-    let mut http_headers: Vec<(String, String)> = Vec::new();
-    for (key, value) in headers {
-        let env_key = format!("HTTP_{}", key.to_uppercase().replace('-', "_"));
-        http_headers.push((env_key, value.clone()));
-    }
-
-    // Get directory of script for proper relative path handling
+    // Pass other headers as HTTP_*. Names outside letters, digits and '-'
+    // are dropped rather than mangled, so `X_Foo` can't pose as `X-Foo`, and
+    // sorting keeps which headers survive the cap independent of hash order.
+    let mut names: Vec<&String> = headers.keys()
+        .filter(|name| Self::passes_header(name))
+        .collect();
+    names.sort();
+    let http_headers: Vec<(String, &String)> = names.into_iter()
+        .take(max_headers)
+        .map(|name| (format!("HTTP_{}", name.to_uppercase().replace('-', "_")), &headers[name]))
+        .collect();
+
+    // Scripts run in their own directory by default, so relative paths in
+    // them resolve next to the script. Elsewhere the script is named by its
+    // absolute path instead.
     let script_path_obj = std::path::Path::new(script_path);
-    let script_dir = script_path_obj.parent().unwrap_or(std::path::Path::new("."));
-    let script_filename = script_path_obj.file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or(script_path);
+    let (script_dir, script_arg) = match working_dir {
+        Some(dir) => {
+            let absolute = std::path::absolute(script_path_obj)
+                .map_err(|e| format!("Failed to resolve CGI script path: {}", e))?;
+            (std::path::PathBuf::from(dir), absolute.into_os_string())
+        }
+        None => (
+            script_path_obj.parent().unwrap_or(std::path::Path::new(".")).to_path_buf(),
+            script_path_obj.file_name().unwrap_or(script_path_obj.as_os_str()).to_os_string(),
+        ),
+    };
 
     // Execute CGI
     let mut cmd = Command::new(cgi_path);
-    cmd.arg(script_filename)
+    cmd.arg(script_arg)
         .current_dir(script_dir)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 
+    // Start from an empty environment so server secrets don't leak into
+    // scripts, then add back only what the route allows
+    cmd.env_clear();
+    cmd.env("PATH", DEFAULT_PATH);
+    for name in pass_env {
+        if let Ok(value) = std::env::var(name) {
+            cmd.env(name, value);
+        }
+    }
+    if let Some(path) = path_env {
+        cmd.env("PATH", path);
+    }
+
     // Add base environment variables
     for (k, v) in env_vars.iter() {
         cmd.env(k, v);
@@ -83,67 +122,52 @@ impl CgiHandler {
         cmd.env(k, v);
     }
 
-    let mut child = cmd.spawn()
+    let child = cmd.spawn()
         .map_err(|e| format!("Failed to spawn CGI process: {}", e))?;
 
-    // Write body to stdin
-    if !body.is_empty() {
-        if let Some(mut stdin) = child.stdin.take() {
-            stdin.write_all(body)
-                .map_err(|e| format!("Failed to write to CGI stdin: {}", e))?;
-        }
-    }
-
-    // Read output with timeout
-    let output = child
-        .wait_with_output()
-        .map_err(|e| format!("Failed to read CGI output: {}", e))?;
+    // The caller feeds the body to stdin and reads the output as the script
+    // gets to them
+    Ok(child)
+}
 
-    if !output.status.success() {
-        return Err(format!(
-            "CGI script failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+    fn passes_header(name: &str) -> bool {
+        !name.is_empty()
+            && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            && !SKIPPED_HEADERS.contains(&name.to_ascii_lowercase().as_str())
     }
 
-    Ok(output.stdout)
-}
+    // Offset just past the blank line ending a script's header block
+    pub fn header_end(output: &[u8]) -> Option<usize> {
+        let crlf = output.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| pos + 4);
+        let lf = output.windows(2).position(|w| w == b"\n\n").map(|pos| pos + 2);
+        match (crlf, lf) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
 
+    // Only the header block has to be text: the boundary is found by
+    // scanning bytes and the body after it is passed on untouched, so a
+    // script may send images or other binary data
     pub fn parse_cgi_output(output: &[u8]) -> Result<(HashMap<String, String>, Vec<u8>), String> {
         let mut headers = HashMap::new();
-        let output_str = std::str::from_utf8(output)
-            .map_err(|_| "Invalid UTF-8 in CGI output")?;
-
-        // Find end of headers
-        if let Some(pos) = output_str.find("\r\n\r\n") {
-            let header_section = &output_str[..pos];
-            let body = &output[pos + 4..];
-
-            for line in header_section.lines() {
-                if let Some(colon_pos) = line.find(':') {
-                    let key = line[..colon_pos].trim().to_lowercase();
-                    let value = line[colon_pos + 1..].trim().to_string();
-                    headers.insert(key, value);
-                }
-            }
-
-            Ok((headers, body.to_vec()))
-        } else if let Some(pos) = output_str.find("\n\n") {
-            let header_section = &output_str[..pos];
-            let body = &output[pos + 2..];
-
-            for line in header_section.lines() {
-                if let Some(colon_pos) = line.find(':') {
-                    let key = line[..colon_pos].trim().to_lowercase();
-                    let value = line[colon_pos + 1..].trim().to_string();
-                    headers.insert(key, value);
-                }
-            }
 
-            Ok((headers, body.to_vec()))
-        } else {
+        let end = match Self::header_end(output) {
+            Some(end) => end,
             // No headers, all body
-            Ok((headers, output.to_vec()))
+            None => return Ok((headers, output.to_vec())),
+        };
+
+        let header_section = std::str::from_utf8(&output[..end])
+            .map_err(|_| "Invalid UTF-8 in CGI headers")?;
+        for line in header_section.lines() {
+            if let Some(colon_pos) = line.find(':') {
+                let key = line[..colon_pos].trim().to_lowercase();
+                let value = line[colon_pos + 1..].trim().to_string();
+                headers.insert(key, value);
+            }
         }
+
+        Ok((headers, output[end..].to_vec()))
     }
 }
\ No newline at end of file
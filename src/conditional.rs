@@ -0,0 +1,95 @@
+use crate::file_provider::FileInfo;
+use crate::http_date;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Validators of the representation being served
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    pub fn from_info(info: &FileInfo) -> Self {
+        Validators {
+            etag: format!("\"{:x}-{:x}\"", unix_seconds(info.modified), info.len),
+            last_modified: info.modified,
+        }
+    }
+
+    pub fn last_modified_header(&self) -> String {
+        http_date::format(self.last_modified)
+    }
+}
+
+// Evaluate the conditional headers in the order of RFC 9110 section 13.2.2,
+// returning the status to answer with when a precondition short-circuits
+// the request. If-None-Match always wins over If-Modified-Since, and
+// If-Match over If-Unmodified-Since. `validators` is None when the target
+// has no current representation, which fails any If-Match, even `*`.
+pub fn evaluate(headers: &HashMap<String, String>, method: &str, validators: Option<&Validators>) -> Option<u16> {
+    let validators = match validators {
+        Some(validators) => validators,
+        None if headers.contains_key("if-match") => return Some(412),
+        // The other conditions all hold, or don't apply, for a missing target
+        None => return None,
+    };
+
+    if let Some(if_match) = headers.get("if-match") {
+        if !etag_matches(if_match, &validators.etag, false) {
+            return Some(412);
+        }
+    } else if let Some(since) = headers.get("if-unmodified-since").and_then(|v| http_date::parse(v)) {
+        if unix_seconds(validators.last_modified) > unix_seconds(since) {
+            return Some(412);
+        }
+    }
+
+    let safe = method == "GET" || method == "HEAD";
+    if let Some(if_none_match) = headers.get("if-none-match") {
+        if etag_matches(if_none_match, &validators.etag, true) {
+            return Some(if safe { 304 } else { 412 });
+        }
+    } else if safe {
+        if let Some(since) = headers.get("if-modified-since").and_then(|v| http_date::parse(v)) {
+            if unix_seconds(validators.last_modified) <= unix_seconds(since) {
+                return Some(304);
+            }
+        }
+    }
+
+    None
+}
+
+// A Range request guarded by If-Range only applies while the representation
+// is still the one the client has a part of
+pub fn range_applies(headers: &HashMap<String, String>, validators: &Validators) -> bool {
+    match headers.get("if-range").map(|v| v.trim()) {
+        None => true,
+        Some(value) if value.starts_with('"') || value.starts_with("W/") => {
+            etag_matches(value, &validators.etag, false)
+        }
+        Some(value) => http_date::parse(value)
+            .is_some_and(|date| unix_seconds(date) == unix_seconds(validators.last_modified)),
+    }
+}
+
+// Match a comma separated entity-tag list; the weak comparison ignores the
+// W/ prefix while the strong one never matches a weak tag
+fn etag_matches(header: &str, etag: &str, weak: bool) -> bool {
+    if header.trim() == "*" {
+        return true;
+    }
+
+    header.split(',').map(|tag| tag.trim()).any(|tag| {
+        if weak {
+            tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+        } else {
+            !tag.starts_with("W/") && tag == etag
+        }
+    })
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -1,21 +1,287 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use crate::access::{AccessList, LimitExcept};
+use crate::handler::Handler;
+use crate::json::{self, JsonValue};
+
+// Interpreter used for CGI routes that don't set cgi_path
+pub const DEFAULT_CGI_PATH: &str = "/usr/bin/python3";
+
+// What each access log line records
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogFormat {
+    // Common Log Format followed by the quoted request id
+    Common,
+    // Common, plus request and response body sizes for bandwidth analysis
+    Sizes,
+    // A line of text with $variables, e.g. "$remote_addr \"$request\" $status"
+    Template(Vec<LogSegment>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogSegment {
+    Text(String),
+    Variable(LogVariable),
+}
+
+// The variables a log_format template may use, named as in nginx
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogVariable {
+    RemoteAddr,
+    // e.g. 06/Nov/1994:08:49:37 +0000
+    TimeLocal,
+    // The request line, e.g. GET /index.html HTTP/1.1
+    Request,
+    Status,
+    BodyBytesSent,
+    HttpReferer,
+    HttpUserAgent,
+    // Seconds with millisecond resolution, e.g. 0.002
+    RequestTime,
+    RequestId,
+}
+
+impl LogFormat {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "common" => Ok(LogFormat::Common),
+            "sizes" => Ok(LogFormat::Sizes),
+            _ if value.contains('$') => Self::parse_template(value),
+            _ => Err(format!("Invalid log_format '{}', expected common, sizes or a template with $variables", value)),
+        }
+    }
+
+    fn parse_template(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('$') {
+            if start > 0 {
+                segments.push(LogSegment::Text(rest[..start].to_string()));
+            }
+            let name_len = rest[start + 1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len() - start - 1);
+            let name = &rest[start + 1..start + 1 + name_len];
+
+            let variable = match name {
+                "remote_addr" => LogVariable::RemoteAddr,
+                "time_local" => LogVariable::TimeLocal,
+                "request" => LogVariable::Request,
+                "status" => LogVariable::Status,
+                "body_bytes_sent" => LogVariable::BodyBytesSent,
+                "http_referer" => LogVariable::HttpReferer,
+                "http_user_agent" => LogVariable::HttpUserAgent,
+                "request_time" => LogVariable::RequestTime,
+                "request_id" => LogVariable::RequestId,
+                _ => return Err(format!("Unknown log_format variable '${}'", name)),
+            };
+            segments.push(LogSegment::Variable(variable));
+            rest = &rest[start + 1 + name_len..];
+        }
+        if !rest.is_empty() {
+            segments.push(LogSegment::Text(rest.to_string()));
+        }
+
+        Ok(LogFormat::Template(segments))
+    }
+}
+
+// Matches the request field limit common to other servers
+pub const DEFAULT_CGI_MAX_HEADERS: usize = 100;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse { line: usize, msg: String },
+    Validation { msg: String },
+    DuplicateServer { address: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+            ConfigError::Validation { msg } => write!(f, "{}", msg),
+            ConfigError::DuplicateServer { address } => {
+                write!(f, "Duplicate server configuration for {}", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
     pub server_names: Vec<String>,
-    pub error_pages: HashMap<u16, String>,
+    pub error_pages: HashMap<u16, ErrorPage>,
     pub client_max_body_size: usize,
+    pub session_cookie_name: String,
+    pub session_timeout: u64,
+    pub sessions: bool,
+    // Leave HEAD, OPTIONS and static asset responses out of sessions, so
+    // health checks and preflights neither look one up nor get a cookie
+    pub session_fast_path: bool,
+    // Answers requests whose Host matches no server_name on its address
+    pub default_server: bool,
+    // Connections on the address open with a PROXY protocol v1 line giving
+    // the real client address
+    pub proxy_protocol: bool,
+    // Honour X-HTTP-Method-Override on POST requests
+    pub method_override: bool,
+    // Answer /favicon.ico with a built-in icon when no file provides one
+    pub default_favicon: bool,
+    // Kernel keepalive probing on accepted connections
+    pub tcp_keepalive: Option<TcpKeepalive>,
+    // Connections taken from the listen queue per wakeup before clients
+    // already connected get a turn; None accepts until the queue is empty
+    pub max_accepts_per_wakeup: Option<usize>,
+    // Open connections allowed from one client address; None for no limit
+    pub limit_conn_per_ip: Option<usize>,
+    // Response bodies streamed from open files at once, across all clients;
+    // past it small files are read whole and larger ones get a 503
+    pub max_open_files: Option<usize>,
+    // Requests being answered at once, across all clients; None for no
+    // limit. Past it requests wait in a queue of request_queue_size and get
+    // a 503 once that is full.
+    pub max_concurrent_requests: Option<usize>,
+    pub request_queue_size: usize,
+    // Seconds a 503 for an overloaded server asks the client to wait
+    pub retry_after: u64,
+    // Most bytes handed to one socket write, and read ahead from a streamed
+    // file; small values favour latency, large ones throughput. None writes
+    // whatever is pending.
+    pub write_chunk_size: Option<usize>,
+    // Disable Nagle's algorithm so small writes go out at once
+    pub tcp_nodelay: bool,
+    // Expand a relative `return` redirect into an absolute URL built from
+    // the request's Host
+    pub absolute_redirect: bool,
+    // Seconds a kept-alive connection may sit between requests
+    pub keepalive_timeout: u64,
+    // Seconds allowed for a request's whole header block to arrive
+    pub client_header_timeout: u64,
+    // Seconds allowed between two reads of a request body
+    pub client_body_timeout: u64,
+    // Refuse to start when the address can't be bound; off logs the
+    // failure and starts with whatever else did bind
+    pub strict_bind: bool,
+    // Compress responses on the fly for clients that accept gzip
+    pub gzip: bool,
+    // 1 (fastest) to 9 (smallest)
+    pub gzip_comp_level: u32,
+    // Bodies shorter than this many bytes are sent as is
+    pub gzip_min_length: usize,
+    pub log_format: LogFormat,
     pub routes: Vec<Route>,
 }
 
+// Seconds of idleness before the first probe, seconds between probes and
+// unanswered probes before the connection is dropped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TcpKeepalive {
+    pub idle: u32,
+    pub interval: u32,
+    pub count: u32,
+}
+
+impl TcpKeepalive {
+    pub const DEFAULT: TcpKeepalive = TcpKeepalive { idle: 60, interval: 10, count: 5 };
+
+    // `on`, `off` or `idle:interval:count`
+    fn parse(value: &str) -> Result<Option<Self>, String> {
+        match value {
+            "on" => return Ok(Some(Self::DEFAULT)),
+            "off" => return Ok(None),
+            _ => {}
+        }
+
+        let fields: Vec<u32> = value
+            .split(':')
+            .map(|field| field.parse().ok().filter(|n| *n > 0))
+            .collect::<Option<Vec<u32>>>()
+            .filter(|fields| fields.len() == 3)
+            .ok_or_else(|| format!("Invalid tcp_keepalive '{}', expected on, off or idle:interval:count", value))?;
+
+        Ok(Some(TcpKeepalive { idle: fields[0], interval: fields[1], count: fields[2] }))
+    }
+}
+
+// The page answering an error code. A target starting with '/' is a URI
+// served through an internal redirect; anything else is a file read as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPage {
+    pub target: String,
+    // From `=<code>`: the status sent instead of the original error's
+    pub status: Option<u16>,
+}
+
+impl ErrorPage {
+    pub fn new(target: &str) -> Self {
+        ErrorPage { target: target.to_string(), status: None }
+    }
+
+    // The status in an `=<code>` token
+    fn parse_status(token: &str) -> Result<u16, String> {
+        token[1..]
+            .parse::<u16>()
+            .ok()
+            .filter(|code| (100..600).contains(code))
+            .ok_or_else(|| format!("Invalid error_page status '{}'", token))
+    }
+}
+
+// How an uploaded file is named in upload_dir. A name already taken gets a
+// numeric suffix, e.g. report-1.txt; nothing is overwritten.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadNaming {
+    // The client's filename, sanitized
+    Preserve,
+    // A random token, keeping the client's extension
+    Random,
+    // upload_<unix seconds>, keeping the client's extension
+    Timestamp,
+}
+
+impl UploadNaming {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "preserve" => Ok(UploadNaming::Preserve),
+            "random" => Ok(UploadNaming::Random),
+            "timestamp" => Ok(UploadNaming::Timestamp),
+            _ => Err(format!("Invalid upload_naming '{}', expected preserve, random or timestamp", value)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Route {
     pub path: String,
     pub methods: Vec<String>,
+    // Client restrictions on the methods it doesn't list
+    pub limit_except: Option<LimitExcept>,
     pub root: Option<String>,
     pub index: Vec<String>,
     pub autoindex: bool,
@@ -23,6 +289,46 @@ pub struct Route {
     pub cgi_extension: Option<String>,
     pub cgi_path: Option<String>,
     pub upload_dir: Option<String>,
+    pub upload_naming: UploadNaming,
+    pub metrics: bool,
+    pub gzip_static: bool,
+    // Serve files as attachments, so browsers save rather than render them
+    pub force_download: bool,
+    // Content type of files whose extension is not known
+    pub default_type: Option<String>,
+    // Extension to content type, ahead of the built-in table
+    pub types: HashMap<String, String>,
+    pub try_files: Vec<String>,
+    pub cgi_pass_env: Vec<String>,
+    // Directory scripts run in instead of their own
+    pub cgi_working_dir: Option<String>,
+    // PATH given to scripts instead of the built-in default
+    pub cgi_path_env: Option<String>,
+    // Most request headers handed to a script as HTTP_* variables
+    pub cgi_max_headers: usize,
+    // Show a failed script's stderr in the 500 page instead of only logging it
+    pub cgi_debug: bool,
+    // Overrides the server's `sessions` setting when set
+    pub sessions: Option<bool>,
+    // Origins answered in a CORS preflight, or "*"; preflights are only
+    // recognised on locations that set some
+    pub cors_allow_origin: Vec<String>,
+    // Methods a preflight allows; the location's own methods when empty
+    pub cors_allow_methods: Vec<String>,
+    pub cors_allow_headers: Vec<String>,
+    // Seconds a browser may cache the preflight's answer
+    pub cors_max_age: Option<u64>,
+    pub allow_dir_delete: bool,
+    // POSTing here from localhost puts the server into draining mode
+    pub drain_control: bool,
+    // GETting here from localhost lists the live sessions
+    pub session_list: bool,
+    // Only reachable through internal redirects, never by a client directly
+    pub internal: bool,
+    // Answers every request for the location in Rust; only set in code
+    pub handler: Option<Arc<dyn Handler>>,
+    // Line of the location block, when loaded from a text config
+    pub line: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -30,20 +336,349 @@ pub struct Config {
     pub servers: Vec<ServerConfig>,
 }
 
+impl ServerConfig {
+    pub fn new() -> Self {
+        ServerConfig {
+            host: String::from("127.0.0.1"),
+            port: 8080,
+            server_names: Vec::new(),
+            error_pages: HashMap::new(),
+            client_max_body_size: 1048576, // 1MB default
+            session_cookie_name: String::from("sessionid"),
+            session_timeout: 3600,
+            sessions: true,
+            session_fast_path: true,
+            default_server: false,
+            proxy_protocol: false,
+            method_override: false,
+            default_favicon: false,
+            tcp_keepalive: None,
+            max_accepts_per_wakeup: None,
+            limit_conn_per_ip: None,
+            max_open_files: None,
+            max_concurrent_requests: None,
+            request_queue_size: 64,
+            retry_after: 5,
+            write_chunk_size: None,
+            tcp_nodelay: false,
+            absolute_redirect: false,
+            strict_bind: true,
+            keepalive_timeout: 30,
+            client_header_timeout: 30,
+            client_body_timeout: 30,
+            gzip: false,
+            gzip_comp_level: 6,
+            gzip_min_length: 20,
+            log_format: LogFormat::Common,
+            routes: Vec::new(),
+        }
+    }
+
+    // `client_max_body_size 0` lifts the limit, as in nginx
+    pub fn body_size_limit(&self) -> Option<usize> {
+        match self.client_max_body_size {
+            0 => None,
+            limit => Some(limit),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Route {
+    pub fn new(path: &str) -> Self {
+        Route {
+            path: path.to_string(),
+            methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
+            limit_except: None,
+            root: None,
+            index: vec!["index.html".to_string()],
+            autoindex: false,
+            redirect: None,
+            cgi_extension: None,
+            cgi_path: None,
+            upload_dir: None,
+            upload_naming: UploadNaming::Preserve,
+            metrics: false,
+            gzip_static: false,
+            force_download: false,
+            default_type: None,
+            types: HashMap::new(),
+            try_files: Vec::new(),
+            cgi_pass_env: Vec::new(),
+            cgi_working_dir: None,
+            cgi_path_env: None,
+            cgi_max_headers: DEFAULT_CGI_MAX_HEADERS,
+            cgi_debug: false,
+            sessions: None,
+            cors_allow_origin: Vec::new(),
+            cors_allow_methods: Vec::new(),
+            cors_allow_headers: Vec::new(),
+            cors_max_age: None,
+            allow_dir_delete: false,
+            drain_control: false,
+            session_list: false,
+            internal: false,
+            handler: None,
+            line: None,
+        }
+    }
+}
+
+// Builds a Config in code, e.g. when embedding the server:
+// Config::builder().server(ServerConfigBuilder::new().listen("*:8080").route(route)).build()
+pub struct ConfigBuilder {
+    servers: Vec<ServerConfigBuilder>,
+}
+
+impl ConfigBuilder {
+    pub fn server(mut self, server: ServerConfigBuilder) -> Self {
+        self.servers.push(server);
+        self
+    }
+
+    // Applies the same validation as loading a file
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let mut servers = Vec::new();
+        for builder in self.servers {
+            if let Some(msg) = builder.error {
+                return Err(ConfigError::Validation { msg });
+            }
+            servers.push(builder.server);
+        }
+        Config::validate(servers)
+    }
+}
+
+pub struct ServerConfigBuilder {
+    server: ServerConfig,
+    // The first invalid setting, reported by ConfigBuilder::build
+    error: Option<String>,
+}
+
+impl ServerConfigBuilder {
+    pub fn new() -> Self {
+        ServerConfigBuilder {
+            server: ServerConfig::new(),
+            error: None,
+        }
+    }
+
+    // Same forms as the `listen` directive: "port", "host:port" or "*:port"
+    pub fn listen(mut self, addr: &str) -> Self {
+        if let Err(msg) = Config::parse_listen(addr, &mut self.server) {
+            self.error.get_or_insert(msg);
+        }
+        self
+    }
+
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.server.server_names.push(name.to_string());
+        self
+    }
+
+    pub fn default_server(mut self) -> Self {
+        self.server.default_server = true;
+        self
+    }
+
+    pub fn proxy_protocol(mut self) -> Self {
+        self.server.proxy_protocol = true;
+        self
+    }
+
+    pub fn error_page(mut self, code: u16, page: ErrorPage) -> Self {
+        self.server.error_pages.insert(code, page);
+        self
+    }
+
+    // In bytes; 0 lifts the limit
+    pub fn client_max_body_size(mut self, size: usize) -> Self {
+        self.server.client_max_body_size = size;
+        self
+    }
+
+    pub fn sessions(mut self, enabled: bool) -> Self {
+        self.server.sessions = enabled;
+        self
+    }
+
+    pub fn session_fast_path(mut self, enabled: bool) -> Self {
+        self.server.session_fast_path = enabled;
+        self
+    }
+
+    pub fn method_override(mut self, enabled: bool) -> Self {
+        self.server.method_override = enabled;
+        self
+    }
+
+    pub fn default_favicon(mut self, enabled: bool) -> Self {
+        self.server.default_favicon = enabled;
+        self
+    }
+
+    // None turns keepalive probing off
+    pub fn tcp_keepalive(mut self, keepalive: Option<TcpKeepalive>) -> Self {
+        self.server.tcp_keepalive = keepalive;
+        self
+    }
+
+    pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+        self.server.tcp_nodelay = enabled;
+        self
+    }
+
+    pub fn max_accepts_per_wakeup(mut self, limit: usize) -> Self {
+        self.server.max_accepts_per_wakeup = self.positive("max_accepts_per_wakeup", limit);
+        self
+    }
+
+    pub fn limit_conn_per_ip(mut self, limit: usize) -> Self {
+        self.server.limit_conn_per_ip = self.positive("limit_conn_per_ip", limit);
+        self
+    }
+
+    pub fn max_open_files(mut self, limit: usize) -> Self {
+        self.server.max_open_files = self.positive("max_open_files", limit);
+        self
+    }
+
+    pub fn max_concurrent_requests(mut self, limit: usize) -> Self {
+        self.server.max_concurrent_requests = self.positive("max_concurrent_requests", limit);
+        self
+    }
+
+    pub fn request_queue_size(mut self, size: usize) -> Self {
+        self.server.request_queue_size = size;
+        self
+    }
+
+    pub fn retry_after(mut self, secs: u64) -> Self {
+        self.server.retry_after = secs;
+        self
+    }
+
+    // In bytes
+    pub fn write_chunk_size(mut self, size: usize) -> Self {
+        self.server.write_chunk_size = self.positive("write_chunk_size", size);
+        self
+    }
+
+    pub fn absolute_redirect(mut self, enabled: bool) -> Self {
+        self.server.absolute_redirect = enabled;
+        self
+    }
+
+    pub fn keepalive_timeout(mut self, secs: u64) -> Self {
+        if let Some(secs) = self.positive("keepalive_timeout", secs as usize) {
+            self.server.keepalive_timeout = secs as u64;
+        }
+        self
+    }
+
+    pub fn client_header_timeout(mut self, secs: u64) -> Self {
+        if let Some(secs) = self.positive("client_header_timeout", secs as usize) {
+            self.server.client_header_timeout = secs as u64;
+        }
+        self
+    }
+
+    pub fn client_body_timeout(mut self, secs: u64) -> Self {
+        if let Some(secs) = self.positive("client_body_timeout", secs as usize) {
+            self.server.client_body_timeout = secs as u64;
+        }
+        self
+    }
+
+    pub fn strict_bind(mut self, enabled: bool) -> Self {
+        self.server.strict_bind = enabled;
+        self
+    }
+
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        self.server.gzip = enabled;
+        self
+    }
+
+    pub fn gzip_comp_level(mut self, level: u32) -> Self {
+        match Config::parse_comp_level(&level.to_string()) {
+            Ok(level) => self.server.gzip_comp_level = level,
+            Err(msg) => {
+                self.error.get_or_insert(msg);
+            }
+        }
+        self
+    }
+
+    // In bytes
+    pub fn gzip_min_length(mut self, length: usize) -> Self {
+        self.server.gzip_min_length = length;
+        self
+    }
+
+    // Same forms as the `log_format` directive: "common", "sizes" or a
+    // template with $variables
+    pub fn log_format(mut self, format: &str) -> Self {
+        match LogFormat::parse(format) {
+            Ok(format) => self.server.log_format = format,
+            Err(msg) => {
+                self.error.get_or_insert(msg);
+            }
+        }
+        self
+    }
+
+    pub fn route(mut self, route: Route) -> Self {
+        self.server.routes.push(route);
+        self
+    }
+
+    // Zero is as invalid here as in the file, and is reported by build
+    fn positive(&mut self, directive: &str, value: usize) -> Option<usize> {
+        match Config::parse_positive(directive, &value.to_string()) {
+            Ok(value) => Some(value),
+            Err(msg) => {
+                self.error.get_or_insert(msg);
+                None
+            }
+        }
+    }
+}
+
+impl Default for ServerConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Config {
-    pub fn from_file(path: &str) -> io::Result<Self> {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder { servers: Vec::new() }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
         let content = fs::read_to_string(path)?;
-        Self::parse(&content)
+
+        if path.ends_with(".json") {
+            Self::parse_json(&content)
+        } else {
+            Self::parse(&content)
+        }
     }
 
-    fn parse(content: &str) -> io::Result<Self> {
+    fn parse(content: &str) -> Result<Self, ConfigError> {
         let mut servers = Vec::new();
         let lines: Vec<&str> = content.lines().collect();
         let mut i = 0;
 
         while i < lines.len() {
             let line = lines[i].trim();
-            
+
             if line.starts_with("server {") {
                 let (server, next_idx) = Self::parse_server(&lines, i)?;
                 servers.push(server);
@@ -53,33 +688,122 @@ impl Config {
             }
         }
 
+        Self::validate(servers)
+    }
+
+    fn validate(mut servers: Vec<ServerConfig>) -> Result<Self, ConfigError> {
         if servers.is_empty() {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "No servers configured"));
+            return Err(ConfigError::Validation { msg: "No servers configured".to_string() });
         }
 
-        // Validate no duplicate host:port combinations
+        // Blocks may share a host:port as long as their server names differ
+        // and at most one of them is the default_server
         let mut seen = HashMap::new();
+        let mut defaults = HashMap::new();
+        let mut proxied = HashMap::new();
         for server in &servers {
-            let key = format!("{}:{}", server.host, server.port);
-            if seen.contains_key(&key) {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("Duplicate server configuration for {}", key)
-                ));
+            let address = format!("{}:{}", server.host, server.port);
+            if server.default_server && defaults.insert(address.clone(), true).is_some() {
+                return Err(ConfigError::DuplicateServer { address });
+            }
+
+            // The PROXY line comes before any Host header, so every block
+            // on the address has to agree on it
+            if *proxied.entry(address.clone()).or_insert(server.proxy_protocol) != server.proxy_protocol {
+                return Err(ConfigError::Validation {
+                    msg: format!("proxy_protocol must be set on every listen for {}", address),
+                });
+            }
+
+            let names = if server.server_names.is_empty() {
+                vec![String::new()]
+            } else {
+                server.server_names.iter().map(|name| name.to_lowercase()).collect()
+            };
+            for name in names {
+                if seen.insert((address.clone(), name), true).is_some() {
+                    return Err(ConfigError::DuplicateServer { address });
+                }
+            }
+        }
+
+        // try_files may end in =code, answering with that error status once
+        // no candidate exists
+        for server in &servers {
+            for route in &server.routes {
+                for (i, entry) in route.try_files.iter().enumerate() {
+                    if let Some(code) = entry.strip_prefix('=') {
+                        let valid = i == route.try_files.len() - 1
+                            && code.parse::<u16>().is_ok_and(|code| (400..=599).contains(&code));
+                        if !valid {
+                            return Err(ConfigError::Validation {
+                                msg: format!(
+                                    "location {}: try_files status '{}' must be the last entry and an error code",
+                                    route.path, entry
+                                ),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        // Add default route if none specified
+        for server in &mut servers {
+            if server.routes.is_empty() {
+                let mut route = Route::new("/");
+                route.root = Some("./www".to_string());
+                server.routes.push(route);
             }
-            seen.insert(key, true);
         }
 
         Ok(Config { servers })
     }
 
-    fn parse_server(lines: &[&str], start: usize) -> io::Result<(ServerConfig, usize)> {
-        let mut host = String::from("127.0.0.1");
-        let mut port = 8080u16;
-        let mut server_names = Vec::new();
-        let mut error_pages = HashMap::new();
-        let mut client_max_body_size = 1048576; // 1MB default
-        let mut routes = Vec::new();
+    // Checks that need the filesystem, run by `webserv -t`: every root is a
+    // directory and every CGI interpreter is an executable file
+    pub fn check(&self) -> Result<(), ConfigError> {
+        for server in &self.servers {
+            for route in &server.routes {
+                let location = match route.line {
+                    Some(line) => format!("location {} (line {})", route.path, line),
+                    None => format!("location {}", route.path),
+                };
+
+                if let Some(ref root) = route.root {
+                    if !Path::new(root).is_dir() {
+                        return Err(ConfigError::Validation {
+                            msg: format!("{}: root '{}' is not a directory", location, root),
+                        });
+                    }
+                }
+
+                if route.cgi_extension.is_some() {
+                    let interpreter = route.cgi_path.as_deref().unwrap_or(DEFAULT_CGI_PATH);
+                    let executable = fs::metadata(interpreter)
+                        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+                    if !executable {
+                        return Err(ConfigError::Validation {
+                            msg: format!("{}: CGI interpreter '{}' is not executable", location, interpreter),
+                        });
+                    }
+                }
+
+                if let Some(ref dir) = route.cgi_working_dir {
+                    if !Path::new(dir).is_dir() {
+                        return Err(ConfigError::Validation {
+                            msg: format!("{}: cgi_working_dir '{}' is not a directory", location, dir),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_server(lines: &[&str], start: usize) -> Result<(ServerConfig, usize), ConfigError> {
+        let mut server = ServerConfig::new();
         let mut i = start + 1;
 
         while i < lines.len() {
@@ -89,38 +813,183 @@ impl Config {
                 break;
             }
 
+            let parts = Self::tokenize(line).map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+
             if line.starts_with("listen ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let addr = parts[1].trim_end_matches(';');
-                    if let Some(colon_pos) = addr.rfind(':') {
-                        host = addr[..colon_pos].to_string();
-                        port = addr[colon_pos + 1..].parse().unwrap_or(8080);
-                    } else {
-                        port = addr.parse().unwrap_or(8080);
+                if parts.len() < 2 {
+                    return Err(ConfigError::Parse {
+                        line: i + 1,
+                        msg: "listen needs an address".to_string(),
+                    });
+                }
+                Self::parse_listen(&parts[1], &mut server)
+                    .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+                for modifier in &parts[2..] {
+                    match modifier.as_str() {
+                        "default_server" => server.default_server = true,
+                        "proxy_protocol" => server.proxy_protocol = true,
+                        other => return Err(ConfigError::Parse {
+                            line: i + 1,
+                            msg: format!("Invalid listen parameter '{}'", other),
+                        }),
                     }
                 }
             } else if line.starts_with("server_name ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
                 for name in &parts[1..] {
-                    server_names.push(name.trim_end_matches(';').to_string());
+                    server.server_names.push(name.to_string());
                 }
             } else if line.starts_with("error_page ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 3 {
-                    if let Ok(code) = parts[1].parse::<u16>() {
-                        error_pages.insert(code, parts[2].trim_end_matches(';').to_string());
+                // Every code before the final token shares that page, e.g.
+                // `error_page 500 502 503 504 /50x.html;`, optionally
+                // answering with another status: `error_page 404 =200 /x;`
+                if let [_, codes @ .., target] = parts.as_slice() {
+                    let mut page = ErrorPage::new(target);
+                    let codes = match codes {
+                        [codes @ .., status] if status.starts_with('=') => {
+                            page.status = Some(ErrorPage::parse_status(status)
+                                .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?);
+                            codes
+                        }
+                        _ => codes,
+                    };
+                    for code in codes {
+                        let code = code.parse::<u16>().map_err(|_| ConfigError::Parse {
+                            line: i + 1,
+                            msg: format!("Invalid error_page code '{}'", code),
+                        })?;
+                        server.error_pages.insert(code, page.clone());
                     }
                 }
             } else if line.starts_with("client_max_body_size ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
                 if parts.len() >= 2 {
-                    let size_str = parts[1].trim_end_matches(';');
-                    client_max_body_size = Self::parse_size(size_str);
+                    let size_str = &parts[1];
+                    server.client_max_body_size = Self::parse_size(size_str);
+                }
+            } else if line.starts_with("session_cookie_name ") {
+                if parts.len() >= 2 {
+                    server.session_cookie_name = parts[1].to_string();
+                }
+            } else if line.starts_with("session_timeout ") {
+                if parts.len() >= 2 {
+                    let secs = &parts[1];
+                    server.session_timeout = secs.parse().map_err(|_| ConfigError::Parse {
+                        line: i + 1,
+                        msg: format!("Invalid session_timeout '{}'", secs),
+                    })?;
+                }
+            } else if line.starts_with("sessions ") {
+                if parts.len() >= 2 {
+                    server.sessions = parts[1] == "on";
+                }
+            } else if line.starts_with("session_fast_path ") {
+                if parts.len() >= 2 {
+                    server.session_fast_path = parts[1] == "on";
+                }
+            } else if line.starts_with("tcp_keepalive ") {
+                if parts.len() >= 2 {
+                    server.tcp_keepalive = TcpKeepalive::parse(&parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+                }
+            } else if line.starts_with("gzip ") {
+                if parts.len() >= 2 {
+                    server.gzip = parts[1] == "on";
+                }
+            } else if line.starts_with("gzip_comp_level ") {
+                if parts.len() >= 2 {
+                    server.gzip_comp_level = Self::parse_comp_level(&parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+                }
+            } else if line.starts_with("gzip_min_length ") {
+                if parts.len() >= 2 {
+                    server.gzip_min_length = Self::parse_strict_size("gzip_min_length", &parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+                }
+            } else if line.starts_with("log_format ") {
+                if parts.len() >= 2 {
+                    server.log_format = LogFormat::parse(&parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+                }
+            } else if line.starts_with("method_override ") {
+                if parts.len() >= 2 {
+                    server.method_override = parts[1] == "on";
+                }
+            } else if line.starts_with("max_accepts_per_wakeup ") {
+                if parts.len() >= 2 {
+                    server.max_accepts_per_wakeup = Some(Self::parse_positive("max_accepts_per_wakeup", &parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?);
+                }
+            } else if line.starts_with("limit_conn_per_ip ") {
+                if parts.len() >= 2 {
+                    server.limit_conn_per_ip = Some(Self::parse_positive("limit_conn_per_ip", &parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?);
+                }
+            } else if line.starts_with("max_open_files ") {
+                if parts.len() >= 2 {
+                    server.max_open_files = Some(Self::parse_positive("max_open_files", &parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?);
+                }
+            } else if line.starts_with("max_concurrent_requests ") {
+                if parts.len() >= 2 {
+                    server.max_concurrent_requests = Some(Self::parse_positive("max_concurrent_requests", &parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?);
+                }
+            } else if line.starts_with("request_queue_size ") {
+                if parts.len() >= 2 {
+                    let size = &parts[1];
+                    server.request_queue_size = size.parse().map_err(|_| ConfigError::Parse {
+                        line: i + 1,
+                        msg: format!("Invalid request_queue_size '{}'", size),
+                    })?;
+                }
+            } else if line.starts_with("write_chunk_size ") {
+                if parts.len() >= 2 {
+                    let size = Self::parse_strict_size("write_chunk_size", &parts[1])
+                        .and_then(|size| Self::check_nonzero("write_chunk_size", size))
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+                    server.write_chunk_size = Some(size);
+                }
+            } else if line.starts_with("tcp_nodelay ") {
+                if parts.len() >= 2 {
+                    server.tcp_nodelay = parts[1] == "on";
+                }
+            } else if line.starts_with("absolute_redirect ") {
+                if parts.len() >= 2 {
+                    server.absolute_redirect = parts[1] == "on";
+                }
+            } else if line.starts_with("strict_bind ") {
+                if parts.len() >= 2 {
+                    server.strict_bind = parts[1] == "on";
+                }
+            } else if line.starts_with("keepalive_timeout ") {
+                if parts.len() >= 2 {
+                    server.keepalive_timeout = Self::parse_positive("keepalive_timeout", &parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })? as u64;
+                }
+            } else if line.starts_with("client_header_timeout ") {
+                if parts.len() >= 2 {
+                    server.client_header_timeout = Self::parse_positive("client_header_timeout", &parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })? as u64;
+                }
+            } else if line.starts_with("client_body_timeout ") {
+                if parts.len() >= 2 {
+                    server.client_body_timeout = Self::parse_positive("client_body_timeout", &parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })? as u64;
+                }
+            } else if line.starts_with("retry_after ") {
+                if parts.len() >= 2 {
+                    let secs = &parts[1];
+                    server.retry_after = secs.parse().map_err(|_| ConfigError::Parse {
+                        line: i + 1,
+                        msg: format!("Invalid retry_after '{}'", secs),
+                    })?;
+                }
+            } else if line.starts_with("default_favicon ") {
+                if parts.len() >= 2 {
+                    server.default_favicon = parts[1] == "on";
                 }
             } else if line.starts_with("location ") {
                 let (route, next_idx) = Self::parse_location(lines, i)?;
-                routes.push(route);
+                server.routes.push(route);
                 i = next_idx;
                 continue;
             }
@@ -128,48 +997,90 @@ impl Config {
             i += 1;
         }
 
-        // Add default route if none specified
-        if routes.is_empty() {
-            routes.push(Route {
-                path: "/".to_string(),
-                methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
-                root: Some("./www".to_string()),
-                index: vec!["index.html".to_string()],
-                autoindex: false,
-                redirect: None,
-                cgi_extension: None,
-                cgi_path: None,
-                upload_dir: None,
+        if i >= lines.len() {
+            return Err(ConfigError::Parse {
+                line: start + 1,
+                msg: "Unterminated server block".to_string(),
             });
         }
 
-        Ok((ServerConfig {
-            host,
-            port,
-            server_names,
-            error_pages,
-            client_max_body_size,
-            routes,
-        }, i + 1))
+        Ok((server, i + 1))
     }
 
-    fn parse_location(lines: &[&str], start: usize) -> io::Result<(Route, usize)> {
+    // Split a directive into words. Double-quoted values keep their spaces
+    // and may contain \" escapes; an unquoted `;` ends the directive and an
+    // unquoted `#` starts a comment.
+    fn tokenize(line: &str) -> Result<Vec<String>, String> {
+        let mut tokens = Vec::new();
+        let mut chars = line.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == ';' || c == '#' {
+                break;
+            } else if c == '"' {
+                chars.next();
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => token.push(escaped),
+                            None => return Err("Unterminated quoted string".to_string()),
+                        },
+                        Some(c) => token.push(c),
+                        None => return Err("Unterminated quoted string".to_string()),
+                    }
+                }
+                tokens.push(token);
+            } else {
+                let mut token = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    fn parse_listen(addr: &str, server: &mut ServerConfig) -> Result<(), String> {
+        let port_str = if let Some(colon_pos) = addr.rfind(':') {
+            server.host = match &addr[..colon_pos] {
+                "*" => "0.0.0.0".to_string(),
+                host => host.to_string(),
+            };
+            &addr[colon_pos + 1..]
+        } else {
+            eprintln!(
+                "Warning: listen {} binds {} only; use *:{} to accept connections on all interfaces",
+                addr, server.host, addr
+            );
+            addr
+        };
+
+        server.port = port_str.parse()
+            .map_err(|_| format!("Invalid listen port '{}'", port_str))?;
+        Ok(())
+    }
+
+    fn parse_location(lines: &[&str], start: usize) -> Result<(Route, usize), ConfigError> {
         let line = lines[start].trim();
         let parts: Vec<&str> = line.split_whitespace().collect();
         let path = if parts.len() >= 2 {
-            parts[1].trim_end_matches('{').trim().to_string()
+            parts[1].trim_end_matches('{').trim()
         } else {
-            "/".to_string()
+            "/"
         };
 
-        let mut methods = vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()];
-        let mut root = None;
-        let mut index = vec!["index.html".to_string()];
-        let mut autoindex = false;
-        let mut redirect = None;
-        let mut cgi_extension = None;
-        let mut cgi_path = None;
-        let mut upload_dir = None;
+        let mut route = Route::new(path);
+        route.line = Some(start + 1);
         let mut i = start + 1;
 
         while i < lines.len() {
@@ -179,64 +1090,549 @@ impl Config {
                 break;
             }
 
+            let parts = Self::tokenize(line).map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+
             if line.starts_with("allow_methods ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                methods = parts[1..].iter()
-                    .map(|s| s.trim_end_matches(';').to_uppercase())
+                route.methods = parts[1..].iter()
+                    .map(|s| s.to_uppercase())
                     .collect();
+            } else if line.starts_with("limit_except ") {
+                let (limit_except, end) = Self::parse_limit_except(lines, i, &parts)?;
+                route.limit_except = Some(limit_except);
+                i = end;
             } else if line.starts_with("root ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
                 if parts.len() >= 2 {
-                    root = Some(parts[1].trim_end_matches(';').to_string());
+                    route.root = Some(parts[1].to_string());
                 }
             } else if line.starts_with("index ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                index = parts[1..].iter()
-                    .map(|s| s.trim_end_matches(';').to_string())
+                // `index off;` never probes for an index file
+                route.index = parts[1..].iter()
+                    .map(|s| s.to_string())
+                    .filter(|s| !s.is_empty())
                     .collect();
+                if route.index == ["off"] {
+                    route.index.clear();
+                }
             } else if line.starts_with("autoindex ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
                 if parts.len() >= 2 {
-                    autoindex = parts[1].trim_end_matches(';') == "on";
+                    route.autoindex = parts[1] == "on";
                 }
             } else if line.starts_with("return ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 3 {
-                    if let Ok(code) = parts[1].parse::<u16>() {
-                        redirect = Some((code, parts[2].trim_end_matches(';').to_string()));
+                if parts.len() >= 2 {
+                    let code = parts[1].parse::<u16>().map_err(|_| ConfigError::Parse {
+                        line: i + 1,
+                        msg: format!("Invalid return code '{}'", parts[1]),
+                    })?;
+                    // Only 444 makes sense without a target
+                    if parts.len() < 3 && code != 444 {
+                        return Err(ConfigError::Parse {
+                            line: i + 1,
+                            msg: format!("return {} needs a target", code),
+                        });
                     }
+                    let target = parts.get(2).map(|t| t.to_string()).unwrap_or_default();
+                    Self::check_redirect_target(code, &target)
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+                    route.redirect = Some((code, target));
+                }
+            } else if line.starts_with("default_type ") {
+                if parts.len() >= 2 {
+                    route.default_type = Some(parts[1].to_string());
+                }
+            } else if line.starts_with("types ") {
+                // `types text/html tpl tmpl;` maps each extension to the type
+                for extension in parts.iter().skip(2) {
+                    route.types.insert(extension.to_string(), parts[1].to_string());
                 }
             } else if line.starts_with("cgi_extension ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
                 if parts.len() >= 2 {
-                    cgi_extension = Some(parts[1].trim_end_matches(';').to_string());
+                    route.cgi_extension = Some(parts[1].to_string());
                 }
             } else if line.starts_with("cgi_path ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
                 if parts.len() >= 2 {
-                    cgi_path = Some(parts[1].trim_end_matches(';').to_string());
+                    route.cgi_path = Some(parts[1].to_string());
+                }
+            } else if line.starts_with("cgi_max_headers ") {
+                if parts.len() >= 2 {
+                    let count = &parts[1];
+                    route.cgi_max_headers = count.parse().map_err(|_| ConfigError::Parse {
+                        line: i + 1,
+                        msg: format!("Invalid cgi_max_headers '{}'", count),
+                    })?;
+                }
+            } else if line.starts_with("cgi_debug ") {
+                if parts.len() >= 2 {
+                    route.cgi_debug = parts[1] == "on";
+                }
+            } else if line.starts_with("cgi_working_dir ") {
+                if parts.len() >= 2 {
+                    route.cgi_working_dir = Some(parts[1].to_string());
+                }
+            } else if line.starts_with("cgi_path_env ") {
+                if parts.len() >= 2 {
+                    route.cgi_path_env = Some(parts[1].to_string());
                 }
             } else if line.starts_with("upload_dir ") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
                 if parts.len() >= 2 {
-                    upload_dir = Some(parts[1].trim_end_matches(';').to_string());
+                    route.upload_dir = Some(parts[1].to_string());
+                }
+            } else if line.starts_with("upload_naming ") {
+                if parts.len() >= 2 {
+                    route.upload_naming = UploadNaming::parse(&parts[1])
+                        .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+                }
+            } else if line.starts_with("metrics ") {
+                if parts.len() >= 2 {
+                    route.metrics = parts[1] == "on";
+                }
+            } else if line.starts_with("gzip_static ") {
+                if parts.len() >= 2 {
+                    route.gzip_static = parts[1] == "on";
+                }
+            } else if line.starts_with("force_download ") {
+                if parts.len() >= 2 {
+                    route.force_download = parts[1] == "on";
+                }
+            } else if line.starts_with("sessions ") {
+                if parts.len() >= 2 {
+                    route.sessions = Some(parts[1] == "on");
+                }
+            } else if line.starts_with("allow_dir_delete ") {
+                if parts.len() >= 2 {
+                    route.allow_dir_delete = parts[1] == "on";
+                }
+            } else if parts.first().is_some_and(|word| word == "internal") {
+                route.internal = true;
+            } else if line.starts_with("drain_control ") {
+                if parts.len() >= 2 {
+                    route.drain_control = parts[1] == "on";
                 }
+            } else if line.starts_with("session_list ") {
+                if parts.len() >= 2 {
+                    route.session_list = parts[1] == "on";
+                }
+            } else if line.starts_with("try_files ") {
+                route.try_files = parts[1..].iter()
+                    .map(|s| s.to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+            } else if line.starts_with("cgi_pass_env ") {
+                route.cgi_pass_env = parts[1..].iter()
+                    .map(|s| s.to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+            } else if line.starts_with("cors_max_age ") {
+                if parts.len() >= 2 {
+                    let secs = &parts[1];
+                    route.cors_max_age = Some(secs.parse().map_err(|_| ConfigError::Parse {
+                        line: i + 1,
+                        msg: format!("Invalid cors_max_age '{}'", secs),
+                    })?);
+                }
+            } else if line.starts_with("cors_allow_origin ") {
+                route.cors_allow_origin = parts[1..].iter()
+                    .map(|s| s.to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+            } else if line.starts_with("cors_allow_methods ") {
+                route.cors_allow_methods = parts[1..].iter()
+                    .map(|s| s.to_uppercase())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+            } else if line.starts_with("cors_allow_headers ") {
+                route.cors_allow_headers = parts[1..].iter()
+                    .map(|s| s.to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
             }
 
             i += 1;
         }
 
-        Ok((Route {
-            path,
-            methods,
-            root,
-            index,
-            autoindex,
-            redirect,
-            cgi_extension,
-            cgi_path,
-            upload_dir,
-        }, i + 1))
+        if i >= lines.len() {
+            return Err(ConfigError::Parse {
+                line: start + 1,
+                msg: format!("Unterminated location block for {}", route.path),
+            });
+        }
+
+        Ok((route, i + 1))
+    }
+
+    // `limit_except GET {` followed by allow/deny lines up to its own `}`;
+    // returns the rules and the index of that closing line
+    fn parse_limit_except(lines: &[&str], start: usize, parts: &[String]) -> Result<(LimitExcept, usize), ConfigError> {
+        if parts.last().map(|part| part.as_str()) != Some("{") || parts.len() < 3 {
+            return Err(ConfigError::Parse {
+                line: start + 1,
+                msg: "Expected limit_except <methods> {".to_string(),
+            });
+        }
+        let mut limit_except = LimitExcept {
+            methods: parts[1..parts.len() - 1].iter().map(|m| m.to_uppercase()).collect(),
+            access: AccessList::new(),
+        };
+
+        let mut i = start + 1;
+        while i < lines.len() {
+            let line = lines[i].trim();
+            if line == "}" {
+                return Ok((limit_except, i));
+            }
+
+            let parts = Self::tokenize(line).map_err(|msg| ConfigError::Parse { line: i + 1, msg })?;
+            match parts.as_slice() {
+                [] => {}
+                [directive, address] => limit_except.access.add(directive, address)
+                    .map_err(|msg| ConfigError::Parse { line: i + 1, msg })?,
+                _ => return Err(ConfigError::Parse {
+                    line: i + 1,
+                    msg: format!("Expected allow or deny in limit_except, found '{}'", line),
+                }),
+            }
+            i += 1;
+        }
+
+        Err(ConfigError::Parse {
+            line: start + 1,
+            msg: "Unterminated limit_except block".to_string(),
+        })
+    }
+
+    // The JSON format mirrors the text format: a "servers" array whose
+    // objects use the same directive names, with "locations" holding routes
+    fn parse_json(content: &str) -> Result<Self, ConfigError> {
+        let invalid = |msg: String| ConfigError::Validation { msg };
+
+        let root = json::parse(content)
+            .map_err(|e| ConfigError::Parse { line: e.line, msg: e.msg })?;
+        let server_values = root.get("servers")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| invalid("Expected a \"servers\" array".to_string()))?;
+
+        let mut servers = Vec::new();
+        for value in server_values {
+            servers.push(Self::parse_json_server(value).map_err(invalid)?);
+        }
+
+        Self::validate(servers)
+    }
+
+    fn parse_json_server(value: &JsonValue) -> Result<ServerConfig, String> {
+        let fields = value.as_object().ok_or("Expected server to be an object")?;
+        let mut server = ServerConfig::new();
+
+        for (key, value) in fields {
+            match key.as_str() {
+                "listen" => match value {
+                    JsonValue::String(addr) => Self::parse_listen(addr, &mut server)?,
+                    JsonValue::Number(_) => {
+                        let port = value.as_u64().ok_or("Invalid listen port")?;
+                        Self::parse_listen(&port.to_string(), &mut server)?;
+                    }
+                    _ => return Err("Expected \"listen\" to be a string or number".to_string()),
+                },
+                "server_name" => server.server_names = Self::json_strings(key, value)?,
+                "error_page" => {
+                    let pages = value.as_object().ok_or("Expected \"error_page\" to be an object")?;
+                    // A key may list several codes, e.g. "500 502 503 504", and a
+                    // value may be {"page": "/x.html", "status": 200}
+                    for (codes, value) in pages {
+                        let page = match value {
+                            JsonValue::String(target) => ErrorPage::new(target),
+                            _ => {
+                                let target = value.get("page")
+                                    .and_then(|page| page.as_str())
+                                    .ok_or("Expected error_page to be a path or an object with a \"page\"")?;
+                                let mut page = ErrorPage::new(target);
+                                if let Some(status) = value.get("status") {
+                                    let status = status.as_u64()
+                                        .filter(|code| (100..600).contains(code))
+                                        .ok_or("Invalid error_page status, expected a code between 100 and 599")?;
+                                    page.status = Some(status as u16);
+                                }
+                                page
+                            }
+                        };
+                        for code in codes.split_whitespace() {
+                            let code = code.parse::<u16>()
+                                .map_err(|_| format!("Invalid error_page code '{}'", code))?;
+                            server.error_pages.insert(code, page.clone());
+                        }
+                    }
+                }
+                "client_max_body_size" => {
+                    server.client_max_body_size = match value {
+                        JsonValue::String(size) => Self::parse_size(size),
+                        _ => value.as_u64().ok_or("Invalid client_max_body_size")? as usize,
+                    };
+                }
+                "session_cookie_name" => server.session_cookie_name = Self::json_string(key, value)?,
+                "sessions" => server.sessions = Self::json_bool(key, value)?,
+                "session_fast_path" => server.session_fast_path = Self::json_bool(key, value)?,
+                "default_server" => server.default_server = Self::json_bool(key, value)?,
+                "proxy_protocol" => server.proxy_protocol = Self::json_bool(key, value)?,
+                "method_override" => server.method_override = Self::json_bool(key, value)?,
+                "default_favicon" => server.default_favicon = Self::json_bool(key, value)?,
+                "max_accepts_per_wakeup" => {
+                    let limit = value.as_u64().ok_or("Invalid max_accepts_per_wakeup")?;
+                    server.max_accepts_per_wakeup = Some(Self::parse_positive(key, &limit.to_string())?);
+                }
+                "limit_conn_per_ip" => {
+                    let limit = value.as_u64().ok_or("Invalid limit_conn_per_ip")?;
+                    server.limit_conn_per_ip = Some(Self::parse_positive(key, &limit.to_string())?);
+                }
+                "max_open_files" => {
+                    let limit = value.as_u64().ok_or("Invalid max_open_files")?;
+                    server.max_open_files = Some(Self::parse_positive(key, &limit.to_string())?);
+                }
+                "max_concurrent_requests" => {
+                    let limit = value.as_u64().ok_or("Invalid max_concurrent_requests")?;
+                    server.max_concurrent_requests = Some(Self::parse_positive(key, &limit.to_string())?);
+                }
+                "request_queue_size" => {
+                    server.request_queue_size = value.as_u64().ok_or("Invalid request_queue_size")? as usize;
+                }
+                "gzip" => server.gzip = Self::json_bool(key, value)?,
+                "gzip_comp_level" => {
+                    server.gzip_comp_level = value.as_u64()
+                        .filter(|level| (1..=9).contains(level))
+                        .ok_or("Invalid gzip_comp_level, expected 1 to 9")? as u32;
+                }
+                "gzip_min_length" => {
+                    server.gzip_min_length = match value {
+                        JsonValue::String(size) => Self::parse_strict_size(key, size)?,
+                        _ => value.as_u64().ok_or("Invalid gzip_min_length")? as usize,
+                    };
+                }
+                "log_format" => server.log_format = LogFormat::parse(&Self::json_string(key, value)?)?,
+                "tcp_keepalive" => server.tcp_keepalive = match value {
+                    JsonValue::Bool(true) => Some(TcpKeepalive::DEFAULT),
+                    JsonValue::Bool(false) => None,
+                    JsonValue::String(spec) => TcpKeepalive::parse(spec)?,
+                    _ => return Err("Expected \"tcp_keepalive\" to be a boolean or \"idle:interval:count\"".to_string()),
+                },
+                "session_timeout" => {
+                    server.session_timeout = value.as_u64().ok_or("Invalid session_timeout")?;
+                }
+                "write_chunk_size" => {
+                    let size = match value {
+                        JsonValue::String(size) => Self::parse_strict_size(key, size)?,
+                        _ => value.as_u64().ok_or("Invalid write_chunk_size")? as usize,
+                    };
+                    server.write_chunk_size = Some(Self::check_nonzero(key, size)?);
+                }
+                "tcp_nodelay" => server.tcp_nodelay = Self::json_bool(key, value)?,
+                "absolute_redirect" => server.absolute_redirect = Self::json_bool(key, value)?,
+                "strict_bind" => server.strict_bind = Self::json_bool(key, value)?,
+                "keepalive_timeout" => {
+                    let secs = value.as_u64().ok_or("Invalid keepalive_timeout")?;
+                    server.keepalive_timeout = Self::parse_positive(key, &secs.to_string())? as u64;
+                }
+                "client_header_timeout" => {
+                    let secs = value.as_u64().ok_or("Invalid client_header_timeout")?;
+                    server.client_header_timeout = Self::parse_positive(key, &secs.to_string())? as u64;
+                }
+                "client_body_timeout" => {
+                    let secs = value.as_u64().ok_or("Invalid client_body_timeout")?;
+                    server.client_body_timeout = Self::parse_positive(key, &secs.to_string())? as u64;
+                }
+                "retry_after" => {
+                    server.retry_after = value.as_u64().ok_or("Invalid retry_after")?;
+                }
+                "locations" => {
+                    let routes = value.as_array().ok_or("Expected \"locations\" to be an array")?;
+                    for route in routes {
+                        server.routes.push(Self::parse_json_route(route)?);
+                    }
+                }
+                _ => return Err(format!("Unknown server directive '{}'", key)),
+            }
+        }
+
+        Ok(server)
+    }
+
+    fn parse_json_route(value: &JsonValue) -> Result<Route, String> {
+        let path = value.get("path")
+            .and_then(|v| v.as_str())
+            .ok_or("Expected location to have a \"path\" string")?;
+        let mut route = Route::new(path);
+
+        for (key, value) in value.as_object().unwrap() {
+            match key.as_str() {
+                "path" => {}
+                "allow_methods" => {
+                    route.methods = Self::json_strings(key, value)?
+                        .iter()
+                        .map(|m| m.to_uppercase())
+                        .collect();
+                }
+                "root" => route.root = Some(Self::json_string(key, value)?),
+                "index" => {
+                    // `false` disables index probing, like `index off;`
+                    route.index = if value.as_bool() == Some(false) {
+                        Vec::new()
+                    } else {
+                        Self::json_strings(key, value)?
+                    };
+                }
+                "limit_except" => {
+                    // {"methods": ["GET"], "rules": ["allow 127.0.0.1", "deny all"]}
+                    let mut limit_except = LimitExcept {
+                        methods: value.get("methods")
+                            .map(|methods| Self::json_strings("methods", methods))
+                            .transpose()?
+                            .ok_or("Expected \"limit_except\" to have \"methods\"")?
+                            .iter()
+                            .map(|m| m.to_uppercase())
+                            .collect(),
+                        access: AccessList::new(),
+                    };
+                    let rules = value.get("rules")
+                        .map(|rules| Self::json_strings("rules", rules))
+                        .transpose()?
+                        .unwrap_or_default();
+                    for rule in rules {
+                        match rule.split_whitespace().collect::<Vec<_>>().as_slice() {
+                            [directive, address] => limit_except.access.add(directive, address)?,
+                            _ => return Err(format!("Invalid limit_except rule '{}'", rule)),
+                        }
+                    }
+                    route.limit_except = Some(limit_except);
+                }
+                "autoindex" => route.autoindex = Self::json_bool(key, value)?,
+                "return" => {
+                    let parts = value.as_array()
+                        .filter(|parts| parts.len() == 2 || parts.len() == 1)
+                        .ok_or("Expected \"return\" to be [code, target]")?;
+                    let code = parts[0].as_u64()
+                        .and_then(|c| u16::try_from(c).ok())
+                        .ok_or("Invalid return code")?;
+                    let target = match parts.get(1) {
+                        Some(target) => Self::json_string(key, target)?,
+                        None if code == 444 => String::new(),
+                        None => return Err(format!("return {} needs a target", code)),
+                    };
+                    Self::check_redirect_target(code, &target)?;
+                    route.redirect = Some((code, target));
+                }
+                "cgi_extension" => route.cgi_extension = Some(Self::json_string(key, value)?),
+                "cgi_path" => route.cgi_path = Some(Self::json_string(key, value)?),
+                "upload_dir" => route.upload_dir = Some(Self::json_string(key, value)?),
+                "upload_naming" => route.upload_naming = UploadNaming::parse(&Self::json_string(key, value)?)?,
+                "metrics" => route.metrics = Self::json_bool(key, value)?,
+                "gzip_static" => route.gzip_static = Self::json_bool(key, value)?,
+                "force_download" => route.force_download = Self::json_bool(key, value)?,
+                "default_type" => route.default_type = Some(Self::json_string(key, value)?),
+                "types" => {
+                    // {"text/html": ["tpl", "tmpl"]}, as the text directive
+                    let types = value.as_object().ok_or("Expected \"types\" to be an object")?;
+                    for (content_type, extensions) in types {
+                        for extension in Self::json_strings(key, extensions)? {
+                            route.types.insert(extension, content_type.clone());
+                        }
+                    }
+                }
+                "try_files" => route.try_files = Self::json_strings(key, value)?,
+                "cgi_pass_env" => route.cgi_pass_env = Self::json_strings(key, value)?,
+                "cors_allow_origin" => route.cors_allow_origin = Self::json_strings(key, value)?,
+                "cors_allow_methods" => {
+                    route.cors_allow_methods = Self::json_strings(key, value)?
+                        .iter()
+                        .map(|method| method.to_uppercase())
+                        .collect();
+                }
+                "cors_allow_headers" => route.cors_allow_headers = Self::json_strings(key, value)?,
+                "cors_max_age" => route.cors_max_age = Some(value.as_u64().ok_or("Invalid cors_max_age")?),
+                "cgi_max_headers" => {
+                    route.cgi_max_headers = value.as_u64().ok_or("Invalid cgi_max_headers")? as usize;
+                }
+                "cgi_debug" => route.cgi_debug = Self::json_bool(key, value)?,
+                "cgi_working_dir" => route.cgi_working_dir = Some(Self::json_string(key, value)?),
+                "cgi_path_env" => route.cgi_path_env = Some(Self::json_string(key, value)?),
+                "sessions" => route.sessions = Some(Self::json_bool(key, value)?),
+                "allow_dir_delete" => route.allow_dir_delete = Self::json_bool(key, value)?,
+                "drain_control" => route.drain_control = Self::json_bool(key, value)?,
+                "session_list" => route.session_list = Self::json_bool(key, value)?,
+                "internal" => route.internal = Self::json_bool(key, value)?,
+                _ => return Err(format!("Unknown location directive '{}'", key)),
+            }
+        }
+
+        Ok(route)
+    }
+
+    fn json_string(key: &str, value: &JsonValue) -> Result<String, String> {
+        value.as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| format!("Expected \"{}\" to be a string", key))
+    }
+
+    fn json_bool(key: &str, value: &JsonValue) -> Result<bool, String> {
+        value.as_bool().ok_or_else(|| format!("Expected \"{}\" to be a boolean", key))
+    }
+
+    // Accept either a single string or an array of strings
+    fn json_strings(key: &str, value: &JsonValue) -> Result<Vec<String>, String> {
+        match value {
+            JsonValue::String(s) => Ok(vec![s.clone()]),
+            JsonValue::Array(items) => items.iter().map(|item| Self::json_string(key, item)).collect(),
+            _ => Err(format!("Expected \"{}\" to be a string or an array of strings", key)),
+        }
+    }
+
+    // A redirect goes either to a path on this server, e.g. "/new", or to an
+    // absolute http(s) URL with a host, e.g. "https://example.org/new"
+    fn check_redirect_target(code: u16, target: &str) -> Result<(), String> {
+        if !(300..400).contains(&code) || (target.starts_with('/') && !target.starts_with("//")) {
+            return Ok(());
+        }
+        let rest = target.strip_prefix("http://").or_else(|| target.strip_prefix("https://"));
+        match rest {
+            Some(rest) if !rest.is_empty() && !rest.starts_with(['/', '?', '#']) => Ok(()),
+            _ => Err(format!(
+                "Invalid return {} target '{}', expected a path or an http(s) URL",
+                code, target
+            )),
+        }
+    }
+
+    fn parse_positive(directive: &str, value: &str) -> Result<usize, String> {
+        value.parse::<usize>()
+            .ok()
+            .filter(|value| *value > 0)
+            .ok_or_else(|| format!("Invalid {} '{}', expected at least 1", directive, value))
+    }
+
+    fn check_nonzero(directive: &str, value: usize) -> Result<usize, String> {
+        if value == 0 {
+            return Err(format!("Invalid {} '0', expected at least 1", directive));
+        }
+        Ok(value)
+    }
+
+    // A byte count with an optional k, m or g suffix. Unlike parse_size,
+    // anything else is an error rather than a fallback.
+    fn parse_strict_size(directive: &str, value: &str) -> Result<usize, String> {
+        let (digits, multiplier) = match value.as_bytes().last().map(|b| b.to_ascii_lowercase()) {
+            Some(b'k') => (&value[..value.len() - 1], 1024),
+            Some(b'm') => (&value[..value.len() - 1], 1024 * 1024),
+            Some(b'g') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+            _ => (value, 1),
+        };
+        Some(digits)
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse::<usize>().ok())
+            .and_then(|n| n.checked_mul(multiplier))
+            .ok_or_else(|| format!("Invalid {} '{}', expected a size such as 512, 64k or 1m", directive, value))
+    }
+
+    fn parse_comp_level(level: &str) -> Result<u32, String> {
+        level.parse::<u32>()
+            .ok()
+            .filter(|level| (1..=9).contains(level))
+            .ok_or_else(|| format!("Invalid gzip_comp_level '{}', expected 1 to 9", level))
     }
 
     fn parse_size(size_str: &str) -> usize {
@@ -254,4 +1650,4 @@ impl Config {
         let num_str = size_str.trim_end_matches(|c: char| !c.is_numeric());
         num_str.parse::<usize>().unwrap_or(1048576) * multiplier
     }
-}
\ No newline at end of file
+}
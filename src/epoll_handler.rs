@@ -218,4 +218,43 @@ pub fn set_nonblocking(fd: RawFd) -> io::Result<()> {
     } else {
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+// Enable SO_KEEPALIVE; the probe timing can only be tuned on Linux
+pub fn set_keepalive(fd: RawFd, idle: u32, interval: u32, count: u32) -> io::Result<()> {
+    set_int_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+
+    #[cfg(target_os = "linux")]
+    {
+        set_int_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, idle as libc::c_int)?;
+        set_int_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, interval as libc::c_int)?;
+        set_int_option(fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, count as libc::c_int)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (idle, interval, count);
+
+    Ok(())
+}
+
+// Disable Nagle's algorithm, sending small writes without waiting to
+// coalesce them
+pub fn set_nodelay(fd: RawFd) -> io::Result<()> {
+    set_int_option(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY, 1)
+}
+
+fn set_int_option(fd: RawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+use crate::http_response::HttpResponse;
+
+// A 1x1 transparent icon: the ICO header and directory entry, then a 32-bit
+// BITMAPINFOHEADER (height doubled for the AND mask), one pixel and the mask
+const ICON: [u8; 70] = [
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00,
+    0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x20, 0x00, 0x30, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00,
+    0x28, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x20, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+// The built-in icon never changes, so browsers may keep it for a week
+pub fn response() -> HttpResponse {
+    let mut response = HttpResponse::new(200);
+    response.add_header("Content-Type".to_string(), "image/x-icon".to_string());
+    response.add_header("Cache-Control".to_string(), "public, max-age=604800".to_string());
+    response.set_body(ICON.to_vec());
+    response
+}
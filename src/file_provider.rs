@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek};
+use std::time::SystemTime;
+
+// A readable, seekable file body handed out by a provider
+pub trait FileBody: Read + Seek {}
+
+impl<T: Read + Seek> FileBody for T {}
+
+pub struct FileInfo {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+impl FileInfo {
+    pub fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+}
+
+// Where static content is read from. Uploads, deletes and CGI scripts still
+// work on the real filesystem.
+pub trait FileProvider {
+    fn metadata(&self, path: &str) -> io::Result<FileInfo>;
+    fn open(&self, path: &str) -> io::Result<Box<dyn FileBody>>;
+    // Names of the entries directly inside a directory, in no particular order
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>>;
+}
+
+// The default provider, backed by std::fs
+pub struct DiskFileProvider;
+
+impl FileProvider for DiskFileProvider {
+    fn metadata(&self, path: &str) -> io::Result<FileInfo> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(FileInfo {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    fn open(&self, path: &str) -> io::Result<Box<dyn FileBody>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(path)?.flatten() {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+}
+
+// Serves files registered up front from memory, so file serving can be
+// exercised without touching the disk. Directories exist implicitly as
+// prefixes of registered paths.
+pub struct MemoryFileProvider {
+    files: HashMap<String, (Vec<u8>, SystemTime)>,
+}
+
+impl MemoryFileProvider {
+    pub fn new() -> Self {
+        MemoryFileProvider {
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn add_file(&mut self, path: &str, contents: &[u8]) {
+        self.add_file_modified(path, contents, SystemTime::now());
+    }
+
+    pub fn add_file_modified(&mut self, path: &str, contents: &[u8], modified: SystemTime) {
+        self.files.insert(path.to_string(), (contents.to_vec(), modified));
+    }
+
+    fn not_found(path: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+    }
+
+    // Registered paths below a directory, relative to it
+    fn children<'a>(&'a self, dir: &str) -> impl Iterator<Item = (&'a str, SystemTime)> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        self.files.iter().filter_map(move |(path, (_, modified))| {
+            path.strip_prefix(&prefix).map(|rest| (rest, *modified))
+        })
+    }
+}
+
+impl Default for MemoryFileProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileProvider for MemoryFileProvider {
+    fn metadata(&self, path: &str) -> io::Result<FileInfo> {
+        if let Some((contents, modified)) = self.files.get(path) {
+            return Ok(FileInfo {
+                is_dir: false,
+                len: contents.len() as u64,
+                modified: *modified,
+            });
+        }
+
+        // A directory is as new as its newest entry
+        self.children(path)
+            .map(|(_, modified)| modified)
+            .max()
+            .map(|modified| FileInfo {
+                is_dir: true,
+                len: 0,
+                modified,
+            })
+            .ok_or_else(|| Self::not_found(path))
+    }
+
+    fn open(&self, path: &str) -> io::Result<Box<dyn FileBody>> {
+        match self.files.get(path) {
+            Some((contents, _)) => Ok(Box::new(Cursor::new(contents.clone()))),
+            None => Err(Self::not_found(path)),
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let mut names: Vec<String> = self.children(path)
+            .map(|(rest, _)| rest.split('/').next().unwrap_or(rest).to_string())
+            .collect();
+        if names.is_empty() {
+            return Err(Self::not_found(path));
+        }
+
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+}
@@ -0,0 +1,261 @@
+// gzip (RFC 1952) around a deflate (RFC 1951) encoder that emits a single
+// block with the fixed Huffman codes. Higher levels search longer match
+// chains and defer matches when the next position has a longer one.
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+// Per level: how many chain links to follow and the match length that is
+// good enough to stop searching
+const CHAIN_LENGTH: [usize; 10] = [0, 4, 8, 16, 32, 64, 128, 256, 1024, 4096];
+const NICE_LENGTH: [usize; 10] = [0, 8, 16, 32, 64, 128, 128, 258, 258, 258];
+// Levels from here on use lazy matching
+const LAZY_LEVEL: u32 = 4;
+
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+// Compress `data` into a gzip member; `level` runs from 1 (fastest) to 9
+// (smallest)
+pub fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let level = level.clamp(1, 9);
+
+    // Magic, deflate, no flags, no mtime, extra flags for the level, Unix
+    let xfl = match level {
+        1 => 4,
+        9 => 2,
+        _ => 0,
+    };
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, xfl, 3];
+
+    let mut bits = BitWriter::new(out);
+    deflate(data, level, &mut bits);
+    out = bits.finish();
+
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn deflate(data: &[u8], level: u32, bits: &mut BitWriter) {
+    // BFINAL, then BTYPE 01 for the fixed codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut matcher = Matcher::new(data, level);
+    let lazy = level >= LAZY_LEVEL;
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let (mut len, mut dist) = matcher.find(pos);
+        matcher.insert(pos);
+
+        // A longer match one byte on is worth a literal first
+        if lazy && len >= MIN_MATCH && len < NICE_LENGTH[level as usize] {
+            let (next_len, next_dist) = matcher.find(pos + 1);
+            if next_len > len {
+                write_literal(bits, data[pos]);
+                pos += 1;
+                matcher.insert(pos);
+                len = next_len;
+                dist = next_dist;
+            }
+        }
+
+        if len >= MIN_MATCH {
+            write_match(bits, len, dist);
+            for skipped in pos + 1..pos + len {
+                matcher.insert(skipped);
+            }
+            pos += len;
+        } else {
+            write_literal(bits, data[pos]);
+            pos += 1;
+        }
+    }
+
+    // End of block
+    write_symbol(bits, 256);
+}
+
+// Hash chains over every 3-byte prefix seen so far
+struct Matcher<'a> {
+    data: &'a [u8],
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    // Positions below this are already chained
+    inserted: usize,
+    chain_length: usize,
+    nice_length: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8], level: u32) -> Self {
+        Matcher {
+            data,
+            // Positions are stored plus one so zero marks an empty slot
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; data.len()],
+            inserted: 0,
+            chain_length: CHAIN_LENGTH[level as usize],
+            nice_length: NICE_LENGTH[level as usize],
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let d = self.data;
+        let h = (d[pos] as u32) << 10 ^ (d[pos + 1] as u32) << 5 ^ d[pos + 2] as u32;
+        (h & ((1 << HASH_BITS) - 1)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos < self.inserted || pos + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let h = self.hash(pos);
+        self.prev[pos] = self.head[h];
+        self.head[h] = pos as u32 + 1;
+        self.inserted = pos + 1;
+    }
+
+    // The longest earlier match for the bytes at `pos`, as (length, distance)
+    fn find(&self, pos: usize) -> (usize, usize) {
+        let data = self.data;
+        if pos + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+
+        let max_len = MAX_MATCH.min(data.len() - pos);
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        let mut links = self.chain_length;
+
+        while candidate != 0 && links > 0 {
+            let start = candidate as usize - 1;
+            if pos - start > WINDOW_SIZE {
+                break;
+            }
+
+            let len = data[start..]
+                .iter()
+                .zip(&data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.0 {
+                best = (len, pos - start);
+                if len >= self.nice_length || len == max_len {
+                    break;
+                }
+            }
+
+            candidate = self.prev[start];
+            links -= 1;
+        }
+
+        best
+    }
+}
+
+fn write_literal(bits: &mut BitWriter, byte: u8) {
+    write_symbol(bits, byte as usize);
+}
+
+fn write_match(bits: &mut BitWriter, len: usize, dist: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base <= len).unwrap();
+    write_symbol(bits, 257 + code);
+    bits.write((len - LENGTH_BASE[code]) as u32, LENGTH_EXTRA[code]);
+
+    let code = DIST_BASE.iter().rposition(|&base| base <= dist).unwrap();
+    bits.write_code(code as u32, 5);
+    bits.write((dist - DIST_BASE[code]) as u32, DIST_EXTRA[code]);
+}
+
+// A literal/length symbol in the fixed Huffman code
+fn write_symbol(bits: &mut BitWriter, symbol: usize) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+// Packs values least significant bit first, as deflate requires
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        BitWriter { out, buffer: 0, count: 0 }
+    }
+
+    fn write(&mut self, value: u32, len: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are defined most significant bit first
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+// Extend the CRC-32 of earlier data, starting from 0, with `data`
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
@@ -0,0 +1,26 @@
+use crate::http_parser::HttpRequest;
+use crate::http_response::HttpResponse;
+use std::fmt;
+
+// Answers requests for a location in Rust, in place of static files and CGI.
+// Set on `Route::handler` when embedding the server; closures taking the
+// request and returning a response implement it.
+pub trait Handler {
+    fn handle(&self, request: &HttpRequest) -> HttpResponse;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&HttpRequest) -> HttpResponse,
+{
+    fn handle(&self, request: &HttpRequest) -> HttpResponse {
+        self(request)
+    }
+}
+
+// Lets Route keep deriving Debug
+impl fmt::Debug for dyn Handler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Handler")
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// Format a timestamp as an IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn format(time: SystemTime) -> String {
+    let (days, year, month, day, secs) = split(time);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAY_NAMES[(days % 7) as usize],
+        day,
+        MONTH_NAMES[(month - 1) as usize],
+        year,
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+// Format a timestamp the way Common Log Format access logs do, e.g.
+// "06/Nov/1994:08:49:37 +0000"
+pub fn format_clf(time: SystemTime) -> String {
+    let (_, year, month, day, secs) = split(time);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTH_NAMES[(month - 1) as usize],
+        year,
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+// Days since the epoch, the civil date and the seconds into that day
+fn split(time: SystemTime) -> (i64, i64, u32, u32, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let days = (secs / 86400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    (days, year, month, day, secs % 86400)
+}
+
+// Parse any of the three forms HTTP/1.1 recipients must accept: the
+// IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT", and the obsolete RFC 850
+// ("Sunday, 06-Nov-94 08:49:37 GMT") and asctime ("Sun Nov  6 08:49:37 1994")
+// forms. Anything else yields None, which callers treat as a missing header.
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    match parts.as_slice() {
+        [weekday, day, month, year, clock, "GMT"] if weekday.ends_with(',') => {
+            from_fields(year.parse().ok()?, month, day, clock)
+        }
+        [weekday, date, clock, "GMT"] if weekday.ends_with(',') => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            if date.next().is_some() || year.len() != 2 {
+                return None;
+            }
+            // Two-digit years before 70 can only be this century's, as
+            // nothing predates the epoch
+            let year: i64 = year.parse().ok()?;
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            from_fields(year, month, day, clock)
+        }
+        [_, month, day, clock, year] => from_fields(year.parse().ok()?, month, day, clock),
+        _ => None,
+    }
+}
+
+fn from_fields(year: i64, month: &str, day: &str, clock: &str) -> Option<SystemTime> {
+    let day: u32 = day.parse().ok()?;
+    let month = MONTH_NAMES.iter().position(|m| *m == month)? as u32 + 1;
+
+    let clock: Vec<u64> = clock
+        .split(':')
+        .map(|p| p.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if clock.len() != 3 || clock[0] > 23 || clock[1] > 59 || clock[2] > 60 {
+        return None;
+    }
+    if !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day) as u64;
+    let secs = days * 86400 + clock[0] * 3600 + clock[1] * 60 + clock[2];
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+// Howard Hinnant's days-from-civil algorithm, days relative to 1970-01-01
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
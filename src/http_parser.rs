@@ -1,9 +1,17 @@
+use crate::request_uri::RequestUri;
 use std::collections::HashMap;
 
+const MAX_METHOD_LEN: usize = 16;
+// Request line plus header fields, like nginx's four 8k header buffers
+const MAX_HEADER_BYTES: usize = 32 * 1024;
+// A chunk-size line still waiting for its CRLF; chunk data itself is handed
+// over as it arrives, so this bounds what a chunked body keeps unparsed
+const MAX_CHUNK_LINE: usize = 4096;
+
 #[derive(Debug, Clone)]
 pub struct HttpRequest {
     pub method: String,
-    pub uri: String,
+    pub uri: RequestUri,
     pub version: String,
     pub headers: HashMap<String, String>,
     pub body: Vec<u8>,
@@ -14,13 +22,43 @@ impl HttpRequest {
     pub fn new() -> Self {
         HttpRequest {
             method: String::new(),
-            uri: String::new(),
+            uri: RequestUri::default(),
             version: String::new(),
             headers: HashMap::new(),
             body: Vec::new(),
             complete: false,
         }
     }
+
+    // A handshake asking to switch protocols, e.g. to WebSocket
+    pub fn is_upgrade(&self) -> bool {
+        let connection_upgrade = self.headers.get("connection").is_some_and(|value| {
+            value.split(',').any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        });
+        connection_upgrade && self.headers.contains_key("upgrade")
+    }
+
+    // HTTP/1.1 connections persist unless the client sends `Connection:
+    // close`; HTTP/1.0 ones only when it asks for keep-alive
+    pub fn keeps_alive(&self) -> bool {
+        let connection_has = |token: &str| {
+            self.headers.get("connection").is_some_and(|value| {
+                value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token))
+            })
+        };
+
+        if self.version == "HTTP/1.0" {
+            connection_has("keep-alive")
+        } else {
+            !connection_has("close")
+        }
+    }
+}
+
+impl Default for HttpRequest {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub struct HttpParser {
@@ -31,6 +69,12 @@ pub struct HttpParser {
     is_chunked: bool,
     chunk_size: usize,
     chunk_state: ChunkState,
+    // Body bytes are consumed without being kept, for a body already known
+    // to be refused
+    discard_body: bool,
+    // Request line and header bytes consumed so far for this request
+    header_bytes: usize,
+    headers_too_large: bool,
 }
 
 #[derive(PartialEq)]
@@ -46,6 +90,7 @@ enum ChunkState {
     Size,
     Data,
     TrailingCRLF,
+    Trailer,
 }
 
 impl HttpParser {
@@ -58,6 +103,9 @@ impl HttpParser {
             is_chunked: false,
             chunk_size: 0,
             chunk_state: ChunkState::Size,
+            discard_body: false,
+            header_bytes: 0,
+            headers_too_large: false,
         }
     }
 
@@ -79,8 +127,13 @@ impl HttpParser {
                     self.headers_complete = true;
                     
                     // Check for Content-Length or Transfer-Encoding
+                    // Only plain digits; usize parsing alone would take "+5"
                     if let Some(cl) = request.headers.get("content-length") {
-                        self.content_length = cl.parse().ok();
+                        let cl = cl.trim();
+                        if cl.is_empty() || !cl.bytes().all(|b| b.is_ascii_digit()) {
+                            return Err(format!("Invalid Content-Length: {}", cl));
+                        }
+                        self.content_length = Some(cl.parse().map_err(|_| format!("Content-Length too large: {}", cl))?);
                     }
                     
                     if let Some(te) = request.headers.get("transfer-encoding") {
@@ -120,7 +173,24 @@ impl HttpParser {
     }
 
     fn parse_request_line(&mut self, request: &mut HttpRequest) -> Result<bool, String> {
+        // Empty lines before a request line are ignored (RFC 9112 section
+        // 2.2), such as the stray CRLF some clients send after a POST body.
+        // They count as header bytes so an endless run of them is still cut off.
+        let blank = self.buffer.chunks(2).take_while(|pair| *pair == b"\r\n").count() * 2;
+        if blank > 0 {
+            self.count_header_bytes(blank, 0)?;
+            self.buffer.drain(..blank);
+        }
+        if self.buffer == b"\r" {
+            return Ok(false);
+        }
+
+        if !self.plausible_request_start() {
+            return Err("Malformed request line".to_string());
+        }
+
         if let Some(pos) = self.find_crlf() {
+            self.count_header_bytes(pos + 2, 0)?;
             let line = String::from_utf8_lossy(&self.buffer[..pos]);
             let parts: Vec<&str> = line.split_whitespace().collect();
 
@@ -129,40 +199,106 @@ impl HttpParser {
             }
 
             request.method = parts[0].to_uppercase();
-            request.uri = parts[1].to_string();
+            request.uri = RequestUri::parse(parts[1])?;
             request.version = parts[2].to_string();
 
             self.buffer.drain(..pos + 2);
             Ok(true)
         } else {
+            self.count_header_bytes(0, self.buffer.len())?;
             Ok(false)
         }
     }
 
+    // Header bytes are limited per request, however they were split across
+    // reads: `line` bytes of complete lines are added to the total and
+    // `pending` bytes of a line still waiting for its CRLF count as well
+    fn count_header_bytes(&mut self, line: usize, pending: usize) -> Result<(), String> {
+        self.header_bytes += line;
+        if self.header_bytes + pending > MAX_HEADER_BYTES {
+            self.headers_too_large = true;
+            return Err("Request headers too large".to_string());
+        }
+        Ok(())
+    }
+
+    // Whether bytes of a following request were read along with this one;
+    // empty lines alone don't start a request
+    pub fn has_buffered_data(&self) -> bool {
+        self.buffer.chunks(2).any(|pair| pair != b"\r\n")
+    }
+
+    // Bytes that cannot begin a method token mean the previous request was
+    // mis-framed (e.g. a body longer than its Content-Length), so fail fast
+    // instead of waiting for a CRLF that may never come
+    fn plausible_request_start(&self) -> bool {
+        let method_len = self.buffer
+            .iter()
+            .take_while(|b| b.is_ascii_alphabetic())
+            .count();
+
+        match self.buffer.get(method_len) {
+            None => method_len <= MAX_METHOD_LEN,
+            Some(b' ') => method_len > 0 && method_len <= MAX_METHOD_LEN,
+            Some(_) => false,
+        }
+    }
+
     fn parse_headers(&mut self, request: &mut HttpRequest) -> Result<bool, String> {
         loop {
             if let Some(pos) = self.find_crlf() {
+                self.count_header_bytes(pos + 2, 0)?;
                 if pos == 0 {
                     // Empty line, headers complete
                     self.buffer.drain(..2);
                     return Ok(true);
                 }
 
+                // A line starting with whitespace continues the previous
+                // header (obs-fold); intermediaries disagree on whether to
+                // join or drop it, which makes it a way to smuggle requests
+                if matches!(self.buffer[0], b' ' | b'\t') {
+                    return Err("Obsolete line folding in headers".to_string());
+                }
+
                 let line = String::from_utf8_lossy(&self.buffer[..pos]);
                 if let Some(colon_pos) = line.find(':') {
                     let key = line[..colon_pos].trim().to_lowercase();
-                    let value = line[colon_pos + 1..].trim().to_string();
+                    let mut value = line[colon_pos + 1..].trim().to_string();
+
+                    // Repeated Content-Length values, in one header or several,
+                    // collapse to one only if they agree; otherwise the body
+                    // length is ambiguous and a way to smuggle requests
+                    if key == "content-length" {
+                        let mut values = value.split(',').map(|v| v.trim().to_string());
+                        let first = values.next().unwrap_or_default();
+                        let existing = request.headers.get("content-length");
+                        if values.any(|v| v != first) || existing.is_some_and(|e| *e != first) {
+                            return Err("Conflicting Content-Length headers".to_string());
+                        }
+                        value = first;
+                    }
                     request.headers.insert(key, value);
                 }
 
                 self.buffer.drain(..pos + 2);
             } else {
+                self.count_header_bytes(0, self.buffer.len())?;
                 return Ok(false);
             }
         }
     }
 
     fn parse_body(&mut self, request: &mut HttpRequest) -> Result<bool, String> {
+        // Count down what is left of a discarded body as it arrives
+        if self.discard_body {
+            let remaining = self.content_length.unwrap_or(0);
+            let consumed = remaining.min(self.buffer.len());
+            self.buffer.drain(..consumed);
+            self.content_length = Some(remaining - consumed);
+            return Ok(consumed == remaining);
+        }
+
         if let Some(content_length) = self.content_length {
             if self.buffer.len() >= content_length {
                 request.body.extend_from_slice(&self.buffer[..content_length]);
@@ -186,24 +322,33 @@ impl HttpParser {
 
                         self.buffer.drain(..pos + 2);
 
-                        if self.chunk_size == 0 {
-                            // Last chunk
-                            return Ok(true);
-                        }
-
-                        self.chunk_state = ChunkState::Data;
+                        // The last chunk is followed by optional trailer
+                        // fields and the blank line ending the message
+                        self.chunk_state = if self.chunk_size == 0 {
+                            ChunkState::Trailer
+                        } else {
+                            ChunkState::Data
+                        };
+                    } else if self.buffer.len() > MAX_CHUNK_LINE {
+                        return Err("Chunk size line too long".to_string());
                     } else {
                         return Ok(false);
                     }
                 }
                 ChunkState::Data => {
-                    if self.buffer.len() >= self.chunk_size {
-                        request.body.extend_from_slice(&self.buffer[..self.chunk_size]);
-                        self.buffer.drain(..self.chunk_size);
-                        self.chunk_state = ChunkState::TrailingCRLF;
-                    } else {
+                    // Take whatever part of the chunk has arrived, so a large
+                    // chunk sent slowly never piles up in the buffer and counts
+                    // toward the body size as it goes
+                    let available = self.chunk_size.min(self.buffer.len());
+                    if !self.discard_body {
+                        request.body.extend_from_slice(&self.buffer[..available]);
+                    }
+                    self.buffer.drain(..available);
+                    self.chunk_size -= available;
+                    if self.chunk_size > 0 {
                         return Ok(false);
                     }
+                    self.chunk_state = ChunkState::TrailingCRLF;
                 }
                 ChunkState::TrailingCRLF => {
                     if self.buffer.len() >= 2 {
@@ -213,6 +358,24 @@ impl HttpParser {
                         return Ok(false);
                     }
                 }
+                ChunkState::Trailer => {
+                    // Trailer fields are read past but not used
+                    match self.find_crlf() {
+                        Some(0) => {
+                            self.buffer.drain(..2);
+                            return Ok(true);
+                        }
+                        // Trailer fields share the header size limit
+                        Some(pos) => {
+                            self.count_header_bytes(pos + 2, 0)?;
+                            self.buffer.drain(..pos + 2);
+                        }
+                        None => {
+                            self.count_header_bytes(0, self.buffer.len())?;
+                            return Ok(false);
+                        }
+                    }
+                }
             }
         }
     }
@@ -224,25 +387,170 @@ impl HttpParser {
     pub fn is_complete(&self) -> bool {
         self.state == ParserState::Done
     }
+
+    // The Content-Length of the request being parsed, once its headers are in
+    pub fn content_length(&self) -> Option<usize> {
+        self.content_length.filter(|_| self.headers_complete)
+    }
+
+    // Whether the request being parsed has its headers in and a body to
+    // follow them
+    pub fn expects_body(&self) -> bool {
+        self.headers_complete && (self.is_chunked || self.content_length.is_some_and(|len| len > 0))
+    }
+
+    // Stop keeping the body of the current request; it still has to be read
+    // so the connection stays in step with the client
+    pub fn discard_body(&mut self, request: &mut HttpRequest) {
+        self.discard_body = true;
+        request.body = Vec::new();
+    }
+
+    pub fn is_discarding_body(&self) -> bool {
+        self.discard_body
+    }
+
+    // Whether parsing failed because the header block grew past the limit
+    pub fn headers_too_large(&self) -> bool {
+        self.headers_too_large
+    }
+
+    // Prepare for the next request on a keep-alive connection, keeping any
+    // bytes already received that belong to it
+    pub fn reset(&mut self) {
+        self.state = ParserState::RequestLine;
+        self.headers_complete = false;
+        self.content_length = None;
+        self.is_chunked = false;
+        self.chunk_size = 0;
+        self.chunk_state = ChunkState::Size;
+        self.discard_body = false;
+        self.header_bytes = 0;
+    }
+}
+
+impl Default for HttpParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-pub fn parse_query_string(uri: &str) -> HashMap<String, String> {
+// Decode `a=1&b=2` into its pairs; entries without '=' are skipped
+pub fn parse_query(query: &str) -> HashMap<String, String> {
     let mut params = HashMap::new();
-    
-    if let Some(query_start) = uri.find('?') {
-        let query = &uri[query_start + 1..];
-        for pair in query.split('&') {
-            if let Some(eq_pos) = pair.find('=') {
-                let key = urldecode(&pair[..eq_pos]);
-                let value = urldecode(&pair[eq_pos + 1..]);
-                params.insert(key, value);
-            }
+    for pair in query.split('&') {
+        if let Some(eq_pos) = pair.find('=') {
+            let key = urldecode(&pair[..eq_pos]);
+            let value = urldecode(&pair[eq_pos + 1..]);
+            params.insert(key, value);
         }
     }
-    
     params
 }
 
+// Percent-decode a request path; unlike query strings, '+' stays literal
+pub fn decode_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Collapse repeated slashes and drop a trailing slash, keeping the root as "/"
+pub fn normalize_path(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    format!("/{}", segments.join("/"))
+}
+
+// Parse a `Range: bytes=...` header against a representation of `len` bytes.
+// Returns None when the header should be ignored and an empty list when none
+// of the requested ranges is satisfiable.
+pub fn parse_range(header: &str, len: usize) -> Option<Vec<(usize, usize)>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+
+    for part in spec.split(',') {
+        let (start, end) = part.trim().split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        if start.is_empty() {
+            // Suffix range: the last N bytes
+            let suffix = end.parse::<usize>().ok()?;
+            if suffix > 0 && len > 0 {
+                ranges.push((len.saturating_sub(suffix), len - 1));
+            }
+        } else {
+            let start = start.parse::<usize>().ok()?;
+            let end = if end.is_empty() {
+                usize::MAX
+            } else {
+                end.parse::<usize>().ok()?
+            };
+
+            if end < start {
+                return None;
+            }
+            if start < len {
+                ranges.push((start, end.min(len - 1)));
+            }
+        }
+    }
+
+    Some(ranges)
+}
+
+// Whether `encoding` is acceptable under an Accept-Encoding header. An item
+// naming it decides, whatever its position; `*` only covers encodings the
+// header doesn't name.
+pub fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+
+        let exact = name.eq_ignore_ascii_case(encoding);
+        if !exact && (name != "*" || wildcard.is_some()) {
+            continue;
+        }
+
+        // An explicit q=0 means the encoding is not acceptable
+        let rejected = params.any(|p| {
+            let p = p.trim();
+            p.starts_with("q=") && p[2..].parse::<f32>().map(|q| q == 0.0).unwrap_or(false)
+        });
+        if exact {
+            return !rejected;
+        }
+        wildcard = Some(!rejected);
+    }
+
+    wildcard.unwrap_or(false)
+}
+
+// The host part of a Host header, e.g. "example.com" for "example.com:8080"
+// and "[::1]" for "[::1]:8080"
+pub fn strip_port(host: &str) -> &str {
+    let host = host.trim();
+    match host.rfind(':') {
+        Some(pos) if !host[pos..].contains(']') => &host[..pos],
+        _ => host,
+    }
+}
+
 fn urldecode(s: &str) -> String {
     let mut result = String::new();
     let mut chars = s.chars();
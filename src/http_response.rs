@@ -1,10 +1,14 @@
+use crate::file_provider::FileBody;
+use crate::http_date;
 use std::collections::HashMap;
+use std::time::SystemTime;
 
 pub struct HttpResponse {
     pub status_code: u16,
     pub status_text: String,
     pub headers: HashMap<String, String>,
     pub body: Vec<u8>,
+    pub body_file: Option<(Box<dyn FileBody>, u64)>,
 }
 
 impl HttpResponse {
@@ -14,12 +18,14 @@ impl HttpResponse {
         let mut headers = HashMap::new();
         headers.insert("Server".to_string(), "Webserv/1.0".to_string());
         headers.insert("Connection".to_string(), "keep-alive".to_string());
+        headers.insert("Date".to_string(), http_date::format(SystemTime::now()));
 
         HttpResponse {
             status_code,
             status_text,
             headers,
             body: Vec::new(),
+            body_file: None,
         }
     }
 
@@ -27,7 +33,9 @@ impl HttpResponse {
         match code {
             200 => "OK",
             201 => "Created",
+            202 => "Accepted",
             204 => "No Content",
+            206 => "Partial Content",
             301 => "Moved Permanently",
             302 => "Found",
             304 => "Not Modified",
@@ -35,18 +43,50 @@ impl HttpResponse {
             403 => "Forbidden",
             404 => "Not Found",
             405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            412 => "Precondition Failed",
             413 => "Payload Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
             500 => "Internal Server Error",
             501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
             _ => "Unknown",
         }.to_string()
     }
 
+    // A 304 carries the validators the 200 would have, but never a body or
+    // Content-Length, so it's built without going through set_body
+    pub fn not_modified(etag: Option<&str>, last_modified: &str) -> Self {
+        let mut response = HttpResponse::new(304);
+        if let Some(etag) = etag {
+            response.add_header("ETag".to_string(), etag.to_string());
+        }
+        response.add_header("Last-Modified".to_string(), last_modified.to_string());
+        response
+    }
+
     pub fn set_body(&mut self, body: Vec<u8>) {
         self.headers.insert("Content-Length".to_string(), body.len().to_string());
         self.body = body;
     }
 
+    // Stream `len` bytes from an already positioned file instead of holding
+    // the body in memory
+    pub fn set_body_file(&mut self, file: Box<dyn FileBody>, len: u64) {
+        self.headers.insert("Content-Length".to_string(), len.to_string());
+        self.body = Vec::new();
+        self.body_file = Some((file, len));
+    }
+
     pub fn set_body_str(&mut self, body: &str) {
         self.set_body(body.as_bytes().to_vec());
     }
@@ -55,6 +95,19 @@ impl HttpResponse {
         self.headers.insert(key, value);
     }
 
+    // Record a request header the response was negotiated on, merging it
+    // into any Vary value already present
+    pub fn add_vary(&mut self, header: &str) {
+        let vary = self.headers.entry("Vary".to_string()).or_default();
+        if vary.split(',').any(|existing| existing.trim().eq_ignore_ascii_case(header)) {
+            return;
+        }
+        if !vary.is_empty() {
+            vary.push_str(", ");
+        }
+        vary.push_str(header);
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut response = format!(
             "HTTP/1.1 {} {}\r\n",
@@ -73,6 +126,51 @@ impl HttpResponse {
         bytes
     }
 
+    // The zero-length chunk ending a chunked body, carrying any trailer
+    // fields that could only be computed once the body was sent
+    pub fn last_chunk(trailers: &[(String, String)]) -> Vec<u8> {
+        let mut chunk = String::from("0\r\n");
+        for (name, value) in trailers {
+            chunk.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        chunk.push_str("\r\n");
+        chunk.into_bytes()
+    }
+
+    // Build a 206 multipart/byteranges response for the given inclusive
+    // byte ranges
+    pub fn byte_ranges(content: &[u8], ranges: &[(usize, usize)], content_type: &str) -> Self {
+        let mut response = HttpResponse::new(206);
+        let total = content.len();
+        let boundary = Self::generate_boundary();
+        let mut body = Vec::new();
+
+        for (start, end) in ranges {
+            body.extend_from_slice(format!(
+                "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, content_type, start, end, total
+            ).as_bytes());
+            body.extend_from_slice(&content[*start..=*end]);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+        response.add_header(
+            "Content-Type".to_string(),
+            format!("multipart/byteranges; boundary={}", boundary),
+        );
+        response.set_body(body);
+        response
+    }
+
+    fn generate_boundary() -> String {
+        use std::collections::hash_map::RandomState;
+        use std::hash::{BuildHasher, Hasher};
+
+        let hasher = RandomState::new().build_hasher();
+        format!("webserv_{:016x}", hasher.finish())
+    }
+
 pub fn error_page(code: u16, custom_page: Option<&str>) -> Self {
         let mut response = HttpResponse::new(code);
         
@@ -114,6 +212,36 @@ pub fn error_page(code: u16, custom_page: Option<&str>) -> Self {
         response
     }
 
+    // A 500 for a failed CGI script showing what it wrote to stderr, for
+    // locations with `cgi_debug on`
+    pub fn cgi_debug(stderr: &str) -> Self {
+        let mut response = HttpResponse::new(500);
+        let body = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>500 {}</title>
+    <style>
+        body {{ font-family: monospace; padding: 20px; }}
+        pre {{ background: #f0f0f0; padding: 10px; white-space: pre-wrap; }}
+    </style>
+</head>
+<body>
+    <h1>500 {}</h1>
+    <p>The CGI script failed. Its error output:</p>
+    <pre>{}</pre>
+</body>
+</html>"#,
+            Self::status_text(500),
+            Self::status_text(500),
+            html_escape(stderr)
+        );
+
+        response.add_header("Content-Type".to_string(), "text/html".to_string());
+        response.set_body_str(&body);
+        response
+    }
+
     pub fn directory_listing(path: &str, uri: &str, entries: Vec<String>) -> Self {
         let mut response = HttpResponse::new(200);
         
@@ -135,8 +263,12 @@ pub fn error_page(code: u16, custom_page: Option<&str>) -> Self {
             uri, uri
         );
 
+        // Links are absolute since the listed path may lack a trailing slash
+        let base = uri.trim_end_matches('/');
+
         if uri != "/" {
-            body.push_str(r#"<a href="../">../</a>"#);
+            let parent = &base[..base.rfind('/').unwrap_or(0)];
+            body.push_str(&format!(r#"<a href="{}/">../</a>"#, parent));
         }
 
         for entry in entries {
@@ -150,8 +282,8 @@ pub fn error_page(code: u16, custom_page: Option<&str>) -> Self {
             };
 
             body.push_str(&format!(
-                r#"<a href="{}{}">{}</a>"#,
-                if uri.ends_with('/') { "" } else { "/" },
+                r#"<a href="{}/{}">{}</a>"#,
+                base,
                 entry,
                 display_name
             ));
@@ -163,4 +295,19 @@ pub fn error_page(code: u16, custom_page: Option<&str>) -> Self {
         response.set_body_str(&body);
         response
     }
-}
\ No newline at end of file
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,21 @@
+pub mod config;
+pub mod epoll_handler;
+pub mod http_parser;
+pub mod http_response;
+pub mod server;
+pub mod cgi;
+pub mod session;
+pub mod metrics;
+pub mod json;
+pub mod http_date;
+pub mod conditional;
+pub mod file_provider;
+pub mod observer;
+pub mod gzip;
+pub mod multipart;
+pub mod handler;
+pub mod favicon;
+pub mod access;
+pub mod proxy_protocol;
+pub mod request_uri;
+pub mod clock;
@@ -1,35 +1,61 @@
-mod config;
-mod epoll_handler;
-mod http_parser;
-mod http_response;
-mod server;
-mod cgi;
-mod session;
-
 use std::process;
-use config::Config;
-use server::Server;
+use webserv::config::{Config, ConfigError};
+use webserv::observer::ClfLogger;
+use webserv::server::Server;
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     
-    if args.len() != 2 {
-        eprintln!("Usage: {} <config_file>", args[0]);
-        process::exit(1);
-    }
+    // `-t` / `--check` validates the configuration without serving, like nginx
+    let (check_only, config_path) = match args.as_slice() {
+        [_, flag, path] if flag == "-t" || flag == "--check" => (true, path),
+        [_, path] => (false, path),
+        _ => {
+            eprintln!("Usage: {} [-t|--check] <config_file>", args[0]);
+            process::exit(1);
+        }
+    };
 
-    let config_path = &args[1];
-    
-    let config = match Config::from_file(config_path) {
+    let loaded = Config::from_file(config_path).and_then(|config| {
+        if check_only {
+            config.check()?;
+        }
+        Ok(config)
+    });
+
+    let config = match loaded {
         Ok(cfg) => cfg,
-        Err(e) => {
-            eprintln!("Failed to load configuration: {}", e);
+        Err(ConfigError::Io(e)) => {
+            eprintln!("Failed to read configuration file {}: {}", config_path, e);
+            process::exit(1);
+        }
+        Err(e @ ConfigError::Parse { .. }) => {
+            eprintln!("Syntax error in {} at {}", config_path, e);
+            process::exit(1);
+        }
+        Err(e @ ConfigError::Validation { .. }) => {
+            eprintln!("Invalid configuration in {}: {}", config_path, e);
+            process::exit(1);
+        }
+        Err(e @ ConfigError::DuplicateServer { .. }) => {
+            eprintln!("Invalid configuration in {}: {} (blocks sharing an address need distinct server names and at most one default_server)", config_path, e);
             process::exit(1);
         }
     };
 
+    if check_only {
+        println!("{}: configuration OK", config_path);
+        return;
+    }
+
+    // A client hanging up mid-response must surface as EPIPE on the write
+    // and close that one connection, never terminate the whole server
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_IGN);
+    }
+
     let mut server = match Server::new(config) {
-        Ok(srv) => srv,
+        Ok(srv) => srv.with_observer(Box::new(ClfLogger)),
         Err(e) => {
             eprintln!("Failed to create server: {}", e);
             process::exit(1);
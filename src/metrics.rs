@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+pub struct Metrics {
+    requests_total: u64,
+    responses_by_class: [u64; 5],
+    bytes_sent: u64,
+    bytes_received: u64,
+    cgi_executions: u64,
+    socket_reads: u64,
+    socket_writes: u64,
+    request_duration_sum: Duration,
+    request_duration_count: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            requests_total: 0,
+            responses_by_class: [0; 5],
+            bytes_sent: 0,
+            bytes_received: 0,
+            cgi_executions: 0,
+            socket_reads: 0,
+            socket_writes: 0,
+            request_duration_sum: Duration::ZERO,
+            request_duration_count: 0,
+        }
+    }
+
+    pub fn record_request(&mut self) {
+        self.requests_total += 1;
+    }
+
+    pub fn record_response(&mut self, status_code: u16, bytes: usize) {
+        let class = (status_code / 100) as usize;
+        if (1..=5).contains(&class) {
+            self.responses_by_class[class - 1] += 1;
+        }
+        self.bytes_sent += bytes as u64;
+    }
+
+    // Request bodies only; headers aren't counted
+    pub fn record_request_body(&mut self, bytes: usize) {
+        self.bytes_received += bytes as u64;
+    }
+
+    pub fn record_cgi_execution(&mut self) {
+        self.cgi_executions += 1;
+    }
+
+    pub fn record_socket_read(&mut self) {
+        self.socket_reads += 1;
+    }
+
+    pub fn record_socket_write(&mut self) {
+        self.socket_writes += 1;
+    }
+
+    // Time from a request's first byte to its response's last byte
+    pub fn record_duration(&mut self, duration: Duration) {
+        self.request_duration_sum += duration;
+        self.request_duration_count += 1;
+    }
+
+    // Render the counters in the Prometheus text exposition format
+    pub fn render(&self, active_connections: usize, active_sessions: usize) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP webserv_requests_total Total number of requests processed.\n");
+        out.push_str("# TYPE webserv_requests_total counter\n");
+        out.push_str(&format!("webserv_requests_total {}\n", self.requests_total));
+
+        out.push_str("# HELP webserv_responses_total Total number of responses sent, by status class.\n");
+        out.push_str("# TYPE webserv_responses_total counter\n");
+        for (i, count) in self.responses_by_class.iter().enumerate() {
+            out.push_str(&format!("webserv_responses_total{{class=\"{}xx\"}} {}\n", i + 1, count));
+        }
+
+        out.push_str("# HELP webserv_active_connections Number of open client connections.\n");
+        out.push_str("# TYPE webserv_active_connections gauge\n");
+        out.push_str(&format!("webserv_active_connections {}\n", active_connections));
+
+        out.push_str("# HELP webserv_active_sessions Number of live sessions.\n");
+        out.push_str("# TYPE webserv_active_sessions gauge\n");
+        out.push_str(&format!("webserv_active_sessions {}\n", active_sessions));
+
+        out.push_str("# HELP webserv_bytes_sent_total Total number of response bytes sent.\n");
+        out.push_str("# TYPE webserv_bytes_sent_total counter\n");
+        out.push_str(&format!("webserv_bytes_sent_total {}\n", self.bytes_sent));
+
+        out.push_str("# HELP webserv_bytes_received_total Total number of request body bytes received.\n");
+        out.push_str("# TYPE webserv_bytes_received_total counter\n");
+        out.push_str(&format!("webserv_bytes_received_total {}\n", self.bytes_received));
+
+        out.push_str("# HELP webserv_cgi_executions_total Total number of CGI scripts executed.\n");
+        out.push_str("# TYPE webserv_cgi_executions_total counter\n");
+        out.push_str(&format!("webserv_cgi_executions_total {}\n", self.cgi_executions));
+
+        out.push_str("# HELP webserv_socket_reads_total Total number of read calls on client sockets.\n");
+        out.push_str("# TYPE webserv_socket_reads_total counter\n");
+        out.push_str(&format!("webserv_socket_reads_total {}\n", self.socket_reads));
+
+        out.push_str("# HELP webserv_socket_writes_total Total number of write calls on client sockets.\n");
+        out.push_str("# TYPE webserv_socket_writes_total counter\n");
+        out.push_str(&format!("webserv_socket_writes_total {}\n", self.socket_writes));
+
+        out.push_str("# HELP webserv_request_duration_seconds Time from first request byte to last response byte.\n");
+        out.push_str("# TYPE webserv_request_duration_seconds summary\n");
+        out.push_str(&format!(
+            "webserv_request_duration_seconds_sum {:.6}\n",
+            self.request_duration_sum.as_secs_f64()
+        ));
+        out.push_str(&format!("webserv_request_duration_seconds_count {}\n", self.request_duration_count));
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
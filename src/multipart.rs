@@ -0,0 +1,137 @@
+// multipart/form-data bodies (RFC 7578), as sent by HTML upload forms
+
+pub struct Part {
+    pub name: Option<String>,
+    // Set for file fields: the name the client gave the file
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+// The boundary parameter of a multipart Content-Type, unquoted
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = split_params(content_type).into_iter();
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| unquote(value.trim()))
+        .filter(|boundary| !boundary.is_empty())
+}
+
+pub fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+
+    // Anything before the first delimiter is a preamble to ignore
+    let mut pos = find(body, &delimiter, 0).ok_or("Missing multipart boundary")? + delimiter.len();
+
+    loop {
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err("Malformed multipart boundary line".to_string());
+        }
+        pos += 2;
+
+        let headers_end = find(body, b"\r\n\r\n", pos).ok_or("Unterminated multipart headers")?;
+        let mut part = Part {
+            name: None,
+            filename: None,
+            content_type: None,
+            data: Vec::new(),
+        };
+
+        let headers = String::from_utf8_lossy(&body[pos..headers_end]);
+        for line in headers.split("\r\n") {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            if key.eq_ignore_ascii_case("content-disposition") {
+                for param in split_params(value).into_iter().skip(1) {
+                    if let Some((name, value)) = param.split_once('=') {
+                        match name.trim().to_ascii_lowercase().as_str() {
+                            "name" => part.name = Some(unquote(value.trim())),
+                            "filename" => part.filename = Some(unquote(value.trim())),
+                            _ => {}
+                        }
+                    }
+                }
+            } else if key.eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.to_string());
+            }
+        }
+
+        // The CRLF before the next delimiter belongs to it, not the data
+        let data_start = headers_end + 4;
+        let mut next = delimiter.clone();
+        next.splice(0..0, *b"\r\n");
+        let data_end = find(body, &next, data_start).ok_or("Unterminated multipart part")?;
+        part.data = body[data_start..data_end].to_vec();
+        parts.push(part);
+
+        pos = data_end + next.len();
+    }
+}
+
+// A client-supplied filename reduced to a safe name inside the upload
+// directory: no path components, no leading dots and only plain characters
+pub fn sanitize_filename(filename: &str) -> Option<String> {
+    let base = filename.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = base
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    // Leave room under the usual 255 byte limit for a collision suffix
+    let cleaned: String = cleaned.trim_start_matches('.').chars().take(200).collect();
+
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned)
+    }
+}
+
+// Split a header value on the semicolons outside quoted strings
+fn split_params(value: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&value[start..]);
+    params
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| pos + from)
+}
@@ -0,0 +1,106 @@
+use crate::config::{LogFormat, LogSegment, LogVariable};
+use crate::http_date;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+// What the server knows about a request once its response is produced
+pub struct RequestInfo<'a> {
+    pub remote: SocketAddr,
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub version: &'a str,
+    pub status: u16,
+    // Request body bytes, as received
+    pub request_bytes: u64,
+    // Body bytes, without the status line and headers
+    pub bytes: u64,
+    pub duration: Duration,
+    // The X-Request-Id the response was sent with
+    pub request_id: &'a str,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    // The log_format of the server that answered
+    pub log_format: &'a LogFormat,
+}
+
+// Hook for logging and metrics integrations, called once per response
+pub trait RequestObserver {
+    fn on_request_complete(&mut self, info: &RequestInfo);
+}
+
+pub struct NoopObserver;
+
+impl RequestObserver for NoopObserver {
+    fn on_request_complete(&mut self, _info: &RequestInfo) {}
+}
+
+// Writes one access log line per request to stdout: by default Common Log
+// Format followed by the quoted request id, with `log_format sizes` also the
+// body sizes in bytes, or else the server's log_format template
+pub struct ClfLogger;
+
+impl ClfLogger {
+    pub fn format(info: &RequestInfo, time: SystemTime) -> String {
+        // Requests that never got a request line are logged as "-"
+        let request_line = if info.method.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{} {} {}", info.method, info.uri, info.version)
+        };
+
+        match info.log_format {
+            LogFormat::Common => Self::common(info, &request_line, time),
+            LogFormat::Sizes => format!(
+                "{} request_bytes={} response_bytes={}",
+                Self::common(info, &request_line, time),
+                info.request_bytes,
+                info.bytes
+            ),
+            LogFormat::Template(segments) => Self::render(segments, info, &request_line, time),
+        }
+    }
+
+    fn common(info: &RequestInfo, request_line: &str, time: SystemTime) -> String {
+        let bytes = match info.bytes {
+            0 => "-".to_string(),
+            n => n.to_string(),
+        };
+
+        format!(
+            "{} - - [{}] \"{}\" {} {} \"{}\"",
+            info.remote.ip(),
+            http_date::format_clf(time),
+            request_line,
+            info.status,
+            bytes,
+            info.request_id
+        )
+    }
+
+    fn render(segments: &[LogSegment], info: &RequestInfo, request_line: &str, time: SystemTime) -> String {
+        let mut line = String::new();
+        for segment in segments {
+            match segment {
+                LogSegment::Text(text) => line.push_str(text),
+                LogSegment::Variable(variable) => line.push_str(&match variable {
+                    LogVariable::RemoteAddr => info.remote.ip().to_string(),
+                    LogVariable::TimeLocal => http_date::format_clf(time),
+                    LogVariable::Request => request_line.to_string(),
+                    LogVariable::Status => info.status.to_string(),
+                    LogVariable::BodyBytesSent => info.bytes.to_string(),
+                    LogVariable::HttpReferer => info.referer.unwrap_or("-").to_string(),
+                    LogVariable::HttpUserAgent => info.user_agent.unwrap_or("-").to_string(),
+                    LogVariable::RequestTime => format!("{:.3}", info.duration.as_secs_f64()),
+                    LogVariable::RequestId => info.request_id.to_string(),
+                }),
+            }
+        }
+        line
+    }
+}
+
+impl RequestObserver for ClfLogger {
+    fn on_request_complete(&mut self, info: &RequestInfo) {
+        println!("{}", Self::format(info, SystemTime::now()));
+    }
+}
@@ -0,0 +1,40 @@
+use std::net::{IpAddr, SocketAddr};
+
+// Longest PROXY protocol v1 line, CRLF included
+pub const MAX_HEADER_LEN: usize = 107;
+
+// Parse the line a load balancer sends ahead of the client's bytes, e.g.
+// "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n", into the client's
+// address. "PROXY UNKNOWN" carries none and yields None, leaving the
+// connection's own peer address in place.
+pub fn parse(line: &[u8]) -> Result<Option<SocketAddr>, String> {
+    let line = line
+        .strip_suffix(b"\r\n")
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .ok_or("PROXY line not terminated by CRLF")?;
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+            let source: IpAddr = source.parse().map_err(|_| format!("Invalid source address '{}'", source))?;
+            let destination: IpAddr = destination.parse()
+                .map_err(|_| format!("Invalid destination address '{}'", destination))?;
+            if source.is_ipv4() != (*family == "TCP4") || destination.is_ipv4() != source.is_ipv4() {
+                return Err(format!("Address does not match {}", family));
+            }
+            let port = parse_port(source_port)?;
+            parse_port(destination_port)?;
+            Ok(Some(SocketAddr::new(source, port)))
+        }
+        _ => Err(format!("Malformed PROXY line '{}'", line)),
+    }
+}
+
+// Ports are plain decimal without leading zeros
+fn parse_port(value: &str) -> Result<u16, String> {
+    if value.is_empty() || (value.len() > 1 && value.starts_with('0')) || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("Invalid port '{}'", value));
+    }
+    value.parse().map_err(|_| format!("Invalid port '{}'", value))
+}
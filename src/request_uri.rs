@@ -0,0 +1,57 @@
+use crate::http_parser::{decode_path, normalize_path, parse_query};
+use std::collections::HashMap;
+use std::fmt;
+
+// A request target split once into its path and query, e.g.
+// "/search/a%20b?q=1&page=2" into "/search/a%20b" and "q=1&page=2"
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestUri {
+    raw: String,
+    // Offset of the '?' that starts the query, if there is one
+    query_start: Option<usize>,
+}
+
+impl RequestUri {
+    // The query runs from the first '?' to the end, so a later '?' is part
+    // of it. Fragments stay with the client and are never sent, so a '#'
+    // makes the target invalid.
+    pub fn parse(target: &str) -> Result<Self, String> {
+        if target.contains('#') {
+            return Err("Fragment in request target".to_string());
+        }
+        Ok(RequestUri {
+            raw: target.to_string(),
+            query_start: target.find('?'),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    // The path as sent, still percent-encoded
+    pub fn path(&self) -> &str {
+        &self.raw[..self.query_start.unwrap_or(self.raw.len())]
+    }
+
+    // Still encoded; Some("") for a target ending in a bare '?'
+    pub fn query(&self) -> Option<&str> {
+        self.query_start.map(|start| &self.raw[start + 1..])
+    }
+
+    // The path decoded and normalized, as used for routing
+    pub fn decoded_path(&self) -> String {
+        normalize_path(&decode_path(self.path()))
+    }
+
+    // Decoded query parameters; a repeated name keeps its last value
+    pub fn query_params(&self) -> HashMap<String, String> {
+        self.query().map(parse_query).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for RequestUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
@@ -1,31 +1,213 @@
-use crate::config::{Config, Route, ServerConfig};
-use crate::epoll_handler::{set_nonblocking, Epoll};
-use crate::http_parser::{HttpParser, HttpRequest};
+use crate::config::{Config, LogFormat, Route, ServerConfig, UploadNaming, DEFAULT_CGI_PATH};
+use crate::epoll_handler::{set_keepalive, set_nodelay, set_nonblocking, Epoll};
+use crate::http_parser::{accepts_encoding, decode_path, parse_range, strip_port, HttpParser, HttpRequest};
+use crate::request_uri::RequestUri;
 use crate::http_response::HttpResponse;
 use crate::cgi::CgiHandler;
-use crate::session::{SessionManager, parse_cookies, create_set_cookie};
-use std::collections::HashMap;
-use std::io::{self, Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use crate::session::{SessionManager, parse_cookies, create_set_cookie, random_id};
+use crate::metrics::Metrics;
+use crate::http_date;
+use crate::conditional::{self, Validators};
+use crate::file_provider::{DiskFileProvider, FileBody, FileProvider};
+use crate::observer::{NoopObserver, RequestInfo, RequestObserver};
+use crate::gzip;
+use crate::favicon;
+use crate::proxy_protocol;
+use crate::multipart;
+use crate::clock::{Clock, SystemClock};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 const MAX_EVENTS: usize = 1024;
-const BUFFER_SIZE: usize = 8192;
+// Socket and pipe reads go into chunks this large by default, up to
+// MAX_READ_PER_WAKEUP bytes before other connections get a turn
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_READ_PER_WAKEUP: usize = 256 * 1024;
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_INTERNAL_REDIRECTS: u32 = 10;
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_PENDING_OUTPUT: usize = 1024 * 1024;
+// Largest file read into memory once max_open_files streams are open
+const MAX_BUFFERED_FILE: u64 = 64 * 1024;
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+// A client with output pending must accept at least this much per window
+const PROGRESS_WINDOW: Duration = Duration::from_secs(5);
+const MIN_PROGRESS_BYTES: u64 = 2048;
+const MAX_PIPELINED_PER_WAKEUP: usize = 8;
+const MAX_REQUEST_ID_LEN: usize = 128;
+const MAX_CGI_STDERR: usize = 64 * 1024;
+// Compression runs on the event loop, so larger bodies are sent as is
+const MAX_GZIP_BODY: u64 = 256 * 1024;
+// Trailer fields a CGI script may declare; the server fills them in once
+// the script's body is complete
+const CGI_TRAILERS: [&str; 2] = ["X-Content-CRC32", "Server-Timing"];
+// Methods the server can act on, whatever a location's allow_methods lists
+const SUPPORTED_METHODS: [&str; 5] = ["GET", "POST", "PUT", "DELETE", "OPTIONS"];
+
+// A response queued for writing, reported to the observer once the last of
+// its bytes has been flushed to the socket
+struct PendingCompletion {
+    end_offset: u64,
+    method: String,
+    uri: String,
+    version: String,
+    status: u16,
+    request_bytes: u64,
+    bytes: u64,
+    started: Instant,
+    request_id: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    log_format: LogFormat,
+}
+
+// A response body still being read from disk
+struct BodyStream {
+    file: Box<dyn FileBody>,
+    remaining: u64,
+}
+
+// A CGI script whose output is forwarded to the client as it is produced
+struct CgiProcess {
+    child: Child,
+    // The request body is written to stdin as the pipe has room, and the
+    // pipe dropped once all of it is through
+    stdin: Option<ChildStdin>,
+    stdin_written: usize,
+    // Each pipe is dropped once it reaches EOF
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+    // Output read before the end of the header block
+    header_buf: Vec<u8>,
+    stderr_buf: Vec<u8>,
+    // Set once the response headers went out: whether the body is sent with
+    // chunked framing or as raw bytes
+    chunked: Option<bool>,
+    // Reading stdout is paused while too much output waits on the client
+    paused: bool,
+    started: Instant,
+    // Trailer fields the script declared that are sent after a chunked body
+    trailers: Vec<&'static str>,
+    // CRC-32 of the body so far, for the X-Content-CRC32 trailer
+    body_crc: u32,
+    // The location's cgi_debug setting
+    debug: bool,
+}
+
+// Where a connection is in its request cycle, which decides the timeout
+// that applies to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConnectionPhase {
+    // Kept alive between requests, nothing of the next one read yet
+    Idle,
+    ReadingRequest,
+    // Waiting on a script that hasn't answered yet, or for a free slot
+    // under max_concurrent_requests
+    Processing,
+    Writing,
+}
+
+// What becomes of a complete request under max_concurrent_requests
+enum Admission {
+    Start,
+    Wait,
+    Refuse,
+}
 
 enum ClientState {
     Reading,
-    Writing { response: Vec<u8>, written: usize },
+    Writing { response: Vec<u8>, written: usize, body: Option<BodyStream> },
 }
 
 struct Client {
     stream: TcpStream,
+    remote_addr: SocketAddr,
     state: ClientState,
     parser: HttpParser,
     request: HttpRequest,
     last_activity: Instant,
+    // Port the connection was accepted on, for picking its server block
+    local_port: u16,
     server_config: ServerConfig,
+    internal_redirects: u32,
+    // While an error_page URI is being served: the original error code and
+    // the status to answer with
+    error_page: Option<(u16, u16)>,
+    last_write_progress: Instant,
+    // Bytes written since progress_check_start, for the slow reader watchdog
+    bytes_since_check: u64,
+    progress_check_start: Instant,
+    close_after_write: bool,
+    // When the first byte of the request being parsed arrived
+    request_start: Option<Instant>,
+    bytes_queued: u64,
+    bytes_flushed: u64,
+    completions: VecDeque<PendingCompletion>,
+    // Whether the response to the current request takes part in sessions
+    sessions_enabled: bool,
+    // The script answering the current request, if it is still running
+    cgi: Option<CgiProcess>,
+    // Whether the current request's Expect header has been acted on
+    expectation_answered: bool,
+    // The PROXY protocol line read so far, until it is complete
+    proxy_header: Option<Vec<u8>>,
+    // Holds one of the server's request slots until its responses are sent
+    in_flight: bool,
+    // Has a complete request waiting in the server's request queue
+    queued: bool,
+}
+
+impl Client {
+    fn phase(&self) -> ConnectionPhase {
+        if matches!(self.state, ClientState::Writing { .. }) {
+            ConnectionPhase::Writing
+        } else if self.cgi.is_some() || self.queued {
+            ConnectionPhase::Processing
+        } else if self.request_start.is_some() || self.parser.has_buffered_data() {
+            ConnectionPhase::ReadingRequest
+        } else {
+            ConnectionPhase::Idle
+        }
+    }
+
+    // Idle connections get keepalive_timeout; a request's headers have
+    // client_header_timeout to arrive in full, and its body may pause for
+    // client_body_timeout between reads
+    fn timed_out(&self, now: Instant) -> bool {
+        let config = &self.server_config;
+        let since_activity = now.duration_since(self.last_activity);
+        match self.phase() {
+            ConnectionPhase::Idle => since_activity > Duration::from_secs(config.keepalive_timeout),
+            ConnectionPhase::ReadingRequest if self.parser.expects_body() => {
+                since_activity > Duration::from_secs(config.client_body_timeout)
+            }
+            ConnectionPhase::ReadingRequest => {
+                let started = self.request_start.unwrap_or(self.last_activity);
+                now.duration_since(started) > Duration::from_secs(config.client_header_timeout)
+            }
+            ConnectionPhase::Processing | ConnectionPhase::Writing => since_activity > CLIENT_TIMEOUT,
+        }
+    }
+
+    // Stop dispatching pipelined requests while a body is streaming, a CGI
+    // script is still answering or too much output is already waiting on a
+    // slow reader
+    fn is_backlogged(&self) -> bool {
+        if self.cgi.is_some() {
+            return true;
+        }
+        match self.state {
+            ClientState::Writing { ref response, written, ref body } => {
+                body.is_some() || response.len() - written > MAX_PENDING_OUTPUT
+            }
+            ClientState::Reading => false,
+        }
+    }
 }
 
 pub struct Server {
@@ -33,19 +215,67 @@ pub struct Server {
     epoll: Epoll,
     listeners: Vec<TcpListener>,
     clients: HashMap<RawFd, Client>,
+    // CGI stdout/stderr pipes and the client each one answers
+    cgi_pipes: HashMap<RawFd, RawFd>,
     session_manager: SessionManager,
+    metrics: Metrics,
+    files: Box<dyn FileProvider>,
+    observer: Box<dyn RequestObserver>,
+    // Set by a drain_control request: no new connections are accepted and
+    // run returns once the remaining clients are done
+    draining: bool,
+    // Shared by every socket and CGI pipe read, since each read is consumed
+    // right away
+    read_buffer: Vec<u8>,
+    // Client sockets and CGI pipes closed since the last epoll_wait. Later
+    // events in that batch are stale even if an accept or a new script has
+    // already been handed the same fd number.
+    closed_fds: HashSet<RawFd>,
+    // Open client connections by remote address, for limit_conn_per_ip
+    connections_per_ip: HashMap<IpAddr, usize>,
+    // Clients holding a request slot, for max_concurrent_requests
+    requests_in_flight: usize,
+    // Clients whose request waits for a slot, oldest first
+    request_queue: VecDeque<RawFd>,
+    // Time source for connection timeouts, shared with the session manager
+    clock: Arc<dyn Clock>,
 }
 
 impl Server {
     pub fn new(config: Config) -> io::Result<Self> {
+        Self::with_file_provider(config, Box::new(DiskFileProvider))
+    }
+
+    // Serve static content from `files` instead of the local disk
+    pub fn with_file_provider(config: Config, files: Box<dyn FileProvider>) -> io::Result<Self> {
         let epoll = Epoll::new()?;
         let mut listeners = Vec::new();
 
-        // Create listeners for each server
+        // Create one listener per address; server blocks sharing it are told
+        // apart by the Host header
+        let mut bound = Vec::new();
+        let mut failed = Vec::new();
         for server_config in &config.servers {
             let addr = format!("{}:{}", server_config.host, server_config.port);
-            let listener = TcpListener::bind(&addr)?;
-            
+            if bound.contains(&addr) || failed.contains(&addr) {
+                continue;
+            }
+            let listener = match TcpListener::bind(&addr) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    // Skipping is only allowed when no block on the address
+                    // asks for strict_bind
+                    let strict = config.servers.iter()
+                        .any(|s| s.strict_bind && format!("{}:{}", s.host, s.port) == addr);
+                    if strict {
+                        return Err(io::Error::new(e.kind(), format!("Failed to bind {}: {}", addr, e)));
+                    }
+                    eprintln!("Failed to bind {}: {}", addr, e);
+                    failed.push(addr);
+                    continue;
+                }
+            };
+
             set_nonblocking(listener.as_raw_fd())?;
             
             // Register listener with epoll
@@ -57,6 +287,15 @@ impl Server {
 
             println!("Listening on {}", addr);
             listeners.push(listener);
+            bound.push(addr);
+        }
+
+        if !failed.is_empty() {
+            println!("Bound {} of {} addresses; skipped {}",
+                bound.len(), bound.len() + failed.len(), failed.join(", "));
+        }
+        if listeners.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "No address could be bound"));
         }
 
         Ok(Server {
@@ -64,10 +303,42 @@ impl Server {
             epoll,
             listeners,
             clients: HashMap::new(),
+            cgi_pipes: HashMap::new(),
             session_manager: SessionManager::new(),
+            metrics: Metrics::new(),
+            files,
+            observer: Box::new(NoopObserver),
+            draining: false,
+            read_buffer: vec![0; READ_CHUNK_SIZE],
+            closed_fds: HashSet::new(),
+            connections_per_ip: HashMap::new(),
+            requests_in_flight: 0,
+            request_queue: VecDeque::new(),
+            clock: Arc::new(SystemClock),
         })
     }
 
+    // Report every completed request to `observer`
+    pub fn with_observer(mut self, observer: Box<dyn RequestObserver>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    // Time connections and sessions by `clock`, e.g. a ManualClock that
+    // tests advance by hand
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.session_manager = SessionManager::with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    // Read sockets and CGI pipes `size` bytes at a time instead of
+    // READ_CHUNK_SIZE; the buffer is allocated once and reused
+    pub fn with_read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer = vec![0; size.max(1)];
+        self
+    }
+
     pub fn run(&mut self) -> io::Result<()> {
         let mut events = vec![
             libc::epoll_event {
@@ -79,7 +350,7 @@ impl Server {
 
         loop {
             // Cleanup expired sessions periodically
-            self.session_manager.cleanup_expired(3600); // 1 hour
+            self.session_manager.cleanup_expired();
 
             // Epoll wait with timeout for connection management
             let n_events = match self.epoll.wait(&mut events, 1000) {
@@ -87,26 +358,33 @@ impl Server {
                 Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
                 Err(e) => return Err(e),
             };
+            self.closed_fds.clear();
 
             // Check for timeouts
             self.check_timeouts();
 
-            for i in 0..n_events {
-                let fd = events[i].u64 as RawFd;
-                let event_flags = events[i].events;
+            for event in events.iter().take(n_events) {
+                let fd = event.u64 as RawFd;
+                let event_flags = event.events;
+
+                if self.closed_fds.contains(&fd) {
+                    continue;
+                }
 
                 // Check if it's a listener
                 if self.is_listener(fd) {
                     self.accept_connection(fd)?;
+                } else if let Some(&client_fd) = self.cgi_pipes.get(&fd) {
+                    if self.handle_cgi_output(client_fd, fd).is_err() {
+                        self.close_client(client_fd);
+                    }
                 } else if self.clients.contains_key(&fd) {
                     if event_flags & libc::EPOLLIN as u32 != 0 {
-                        if let Err(_) = self.handle_read(fd) {
-                            self.close_client(fd);
-                        }
-                    } else if event_flags & libc::EPOLLOUT as u32 != 0 {
-                        if let Err(_) = self.handle_write(fd) {
+                        if self.handle_read(fd).is_err() {
                             self.close_client(fd);
                         }
+                    } else if event_flags & libc::EPOLLOUT as u32 != 0 && self.handle_write(fd).is_err() {
+                        self.close_client(fd);
                     }
 
                     if event_flags & (libc::EPOLLERR | libc::EPOLLHUP) as u32 != 0 {
@@ -114,6 +392,16 @@ impl Server {
                     }
                 }
             }
+
+            self.start_queued_requests();
+
+            if self.draining {
+                self.close_idle_clients();
+                if self.clients.is_empty() {
+                    println!("Drained all connections, shutting down");
+                    return Ok(());
+                }
+            }
         }
     }
 
@@ -127,27 +415,76 @@ impl Server {
             .find(|l| l.as_raw_fd() == listener_fd)
             .unwrap();
 
-        loop {
+        // Connections left in the queue keep the listener readable, so the
+        // next wakeup takes them after the clients in this one
+        let local_port = listener.local_addr()?.port();
+        let default_config = self.find_server_config(local_port, None);
+        let limit = default_config.max_accepts_per_wakeup;
+        let limit_per_ip = default_config.limit_conn_per_ip;
+        let mut accepted = 0;
+
+        while limit.is_none_or(|limit| accepted < limit) {
+            accepted += 1;
             match listener.accept() {
-                Ok((stream, _addr)) => {
+                Ok((mut stream, remote_addr)) => {
+                    // A client already holding its share of connections is
+                    // turned away before it costs anything more
+                    let open = self.connections_per_ip.get(&remote_addr.ip()).copied().unwrap_or(0);
+                    if limit_per_ip.is_some_and(|limit| open >= limit) {
+                        let response = Self::overload_response(self.find_server_config(local_port, None));
+                        let _ = stream.write_all(&response.to_bytes());
+                        continue;
+                    }
+
                     set_nonblocking(stream.as_raw_fd())?;
 
                     let fd = stream.as_raw_fd();
 
                     // Find matching server config
-                    let server_config = self.find_server_config(listener_fd);
+                    let server_config = self.find_server_config(local_port, None).clone();
+                    if let Some(keepalive) = server_config.tcp_keepalive {
+                        if let Err(e) = set_keepalive(fd, keepalive.idle, keepalive.interval, keepalive.count) {
+                            eprintln!("Failed to enable TCP keepalive: {}", e);
+                        }
+                    }
+                    if server_config.tcp_nodelay {
+                        if let Err(e) = set_nodelay(fd) {
+                            eprintln!("Failed to set TCP_NODELAY: {}", e);
+                        }
+                    }
 
+                    let sessions_enabled = server_config.sessions;
+                    let proxy_header = server_config.proxy_protocol.then(Vec::new);
                     let client = Client {
                         stream,
+                        remote_addr,
                         state: ClientState::Reading,
                         parser: HttpParser::new(),
                         request: HttpRequest::new(),
-                        last_activity: Instant::now(),
+                        last_activity: self.clock.now(),
+                        local_port,
                         server_config,
+                        internal_redirects: 0,
+                        error_page: None,
+                        last_write_progress: self.clock.now(),
+                        bytes_since_check: 0,
+                        progress_check_start: self.clock.now(),
+                        close_after_write: false,
+                        request_start: None,
+                        bytes_queued: 0,
+                        bytes_flushed: 0,
+                        completions: VecDeque::new(),
+                        sessions_enabled,
+                        cgi: None,
+                        expectation_answered: false,
+                        proxy_header,
+                        in_flight: false,
+                        queued: false,
                     };
 
                     self.epoll.add(fd, libc::EPOLLIN as u32, fd as u64)?;
                     self.clients.insert(fd, client);
+                    *self.connections_per_ip.entry(remote_addr.ip()).or_insert(0) += 1;
                 }
                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
                 Err(e) => return Err(e),
@@ -157,247 +494,874 @@ impl Server {
         Ok(())
     }
 
-    fn find_server_config(&self, listener_fd: RawFd) -> ServerConfig {
-        let listener = self.listeners
+    // Pick the server block for a request on `port`: the one whose
+    // server_name matches the Host header, else a `server_name _` block for
+    // any other Host, else the default_server for the address, else the
+    // first block declared on it
+    fn find_server_config(&self, port: u16, host: Option<&str>) -> &ServerConfig {
+        let candidates: Vec<&ServerConfig> = self.config.servers
             .iter()
-            .find(|l| l.as_raw_fd() == listener_fd)
-            .unwrap();
-
-        let addr = listener.local_addr().unwrap();
+            .filter(|server| server.port == port)
+            .collect();
+
+        let host = host.map(|h| strip_port(h).to_lowercase());
+        if let Some(host) = host {
+            let named = candidates.iter().find(|server| {
+                server.server_names.iter().any(|name| name.eq_ignore_ascii_case(&host))
+            });
+            if let Some(server) = named {
+                return server;
+            }
 
-        // Find first matching server config
-        for server in &self.config.servers {
-            if server.port == addr.port() {
-                return server.clone();
+            let catch_all = candidates.iter().find(|server| {
+                server.server_names.iter().any(|name| name == "_")
+            });
+            if let Some(server) = catch_all {
+                return server;
             }
         }
 
-        self.config.servers[0].clone()
+        candidates.iter()
+            .find(|server| server.default_server)
+            .or(candidates.first())
+            .copied()
+            .unwrap_or(&self.config.servers[0])
     }
 
     fn handle_read(&mut self, fd: RawFd) -> io::Result<()> {
         let client = self.clients.get_mut(&fd).unwrap();
-        client.last_activity = Instant::now();
+        client.last_activity = self.clock.now();
 
-        let mut buffer = [0u8; BUFFER_SIZE];
-        
-        match client.stream.read(&mut buffer) {
-            Ok(0) => {
-                // Connection closed
-                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "Connection closed"));
-            }
-            Ok(n) => {
-                // Parse the request
-                if let Err(e) = client.parser.parse(&buffer[..n], &mut client.request) {
-                    let response = HttpResponse::error_page(400, None);
-                    self.send_response(fd, response)?;
+        // Take what the socket holds in as few reads as possible; a read
+        // that doesn't fill the chunk means it is empty for now
+        let mut total = 0;
+        loop {
+            let client = self.clients.get_mut(&fd).unwrap();
+            self.metrics.record_socket_read();
+            let n = match client.stream.read(&mut self.read_buffer) {
+                Ok(0) if total == 0 => {
+                    // Closing before sending anything, as port scanners and
+                    // speculative browser connections do, or between
+                    // keep-alive requests is routine; only a close part way
+                    // through a request is worth a warning
+                    if client.phase() != ConnectionPhase::Idle {
+                        eprintln!("Connection from {} closed mid-request", client.remote_addr);
+                    }
+                    return Err(io::Error::new(io::ErrorKind::ConnectionReset, "Connection closed"));
+                }
+                // Answer what arrived before the close; the next wakeup sees it
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    if client.phase() != ConnectionPhase::Idle {
+                        eprintln!("Connection from {} failed mid-request: {}", client.remote_addr, e);
+                    }
+                    return Err(e);
+                }
+            };
+            total += n;
+
+            if client.request_start.is_none() {
+                client.request_start = Some(self.clock.now());
+            }
+
+            // A proxied connection opens with the PROXY line, whose client
+            // address stands in for the load balancer's from then on
+            let mut data = &self.read_buffer[..n];
+            if let Some(pending) = client.proxy_header.as_mut() {
+                let line_end = data.iter().position(|&b| b == b'\n').map(|pos| pos + 1);
+                let taken = line_end.unwrap_or(data.len());
+                pending.extend_from_slice(&data[..taken]);
+                data = &data[taken..];
+
+                let parsed = if pending.len() > proxy_protocol::MAX_HEADER_LEN {
+                    Some(Err("PROXY line too long".to_string()))
+                } else {
+                    line_end.map(|_| proxy_protocol::parse(pending))
+                };
+                match parsed {
+                    None => {}
+                    Some(Ok(source)) => {
+                        if let Some(source) = source {
+                            release_connection(&mut self.connections_per_ip, client.remote_addr.ip());
+                            *self.connections_per_ip.entry(source.ip()).or_insert(0) += 1;
+                            client.remote_addr = source;
+                        }
+                        client.proxy_header = None;
+                        // The PROXY line isn't part of a request, so a
+                        // health check closing right after it is still idle
+                        if data.is_empty() {
+                            client.request_start = None;
+                        }
+                    }
+                    Some(Err(msg)) => {
+                        eprintln!("Rejected connection from {}: {}", client.remote_addr, msg);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                    }
+                }
+            }
+
+            // Parse the request
+            if client.parser.parse(data, &mut client.request).is_err() {
+                return self.send_bad_request(fd);
+            }
+
+            if self.answer_expectation(fd)? {
+                return Ok(());
+            }
+
+            // A body that is going to be refused is read but not kept
+            if self.body_exceeds_limit(fd) {
+                let client = self.clients.get_mut(&fd).unwrap();
+                client.parser.discard_body(&mut client.request);
+            }
+
+            if n < self.read_buffer.len() || total >= MAX_READ_PER_WAKEUP {
+                break;
+            }
+        }
+
+        self.process_complete_requests(fd)
+    }
+
+    // Act on an Expect header once the headers are in but before any of the
+    // body: a body that would be refused is turned away with a final status
+    // the client can see before sending it, anything else gets 100 Continue.
+    // Returns whether the request was answered, closing the connection.
+    fn answer_expectation(&mut self, fd: RawFd) -> io::Result<bool> {
+        let client = self.clients.get_mut(&fd).unwrap();
+        if client.expectation_answered
+            || !client.parser.expects_body()
+            || client.request.complete
+            || client.request.version != "HTTP/1.1"
+        {
+            return Ok(false);
+        }
+        let expect = match client.request.headers.get("expect") {
+            Some(expect) => expect.trim().to_ascii_lowercase(),
+            None => return Ok(false),
+        };
+        client.expectation_answered = true;
+
+        let code = if expect != "100-continue" {
+            417
+        } else if self.body_exceeds_limit(fd) {
+            413
+        } else {
+            // A client that already started on the body doesn't need one, and
+            // nothing may be slipped in while an earlier response is streaming
+            let client = self.clients.get_mut(&fd).unwrap();
+            if client.request.body.is_empty() && !client.is_backlogged() {
+                let interim = b"HTTP/1.1 100 Continue\r\n\r\n".to_vec();
+                client.bytes_queued += interim.len() as u64;
+                self.queue_output(fd, interim, None)?;
+            }
+            return Ok(false);
+        };
+
+        // The body may or may not follow, so nothing after it can be framed
+        self.clients.get_mut(&fd).unwrap().close_after_write = true;
+        self.metrics.record_request();
+        self.send_error(fd, code)?;
+        Ok(true)
+    }
+
+    // Whether the request being read declares or has already sent more body
+    // than the server it is addressed to accepts
+    fn body_exceeds_limit(&self, fd: RawFd) -> bool {
+        let client = &self.clients[&fd];
+        if client.parser.is_discarding_body() {
+            return false;
+        }
+
+        let host = client.request.headers.get("host").map(|host| host.as_str());
+        match self.find_server_config(client.local_port, host).body_size_limit() {
+            Some(limit) => {
+                client.parser.content_length().is_some_and(|len| len > limit)
+                    || client.request.body.len() > limit
+            }
+            None => false,
+        }
+    }
+
+    // A single read may carry several pipelined requests, so keep dispatching
+    // until the parser runs out of complete ones or the client falls behind
+    fn process_complete_requests(&mut self, fd: RawFd) -> io::Result<()> {
+        let mut dispatched = 0;
+
+        loop {
+            let client = self.clients.get_mut(&fd).unwrap();
+            if client.is_backlogged() {
+                // Picked up again once the pending output drains
+                return Ok(());
+            }
+
+            // Yield so one deeply pipelined connection can't starve the
+            // others; every dispatched request left a response to write, so
+            // handle_write resumes the rest once this batch is flushed
+            if dispatched == MAX_PIPELINED_PER_WAKEUP {
+                return Ok(());
+            }
+
+            // Whatever follows a response that closes the connection is
+            // never answered
+            if client.close_after_write {
+                return Ok(());
+            }
+
+            if !client.request.complete {
+                if client.parser.parse(&[], &mut client.request).is_err() {
+                    return self.send_bad_request(fd);
+                }
+                if !client.request.complete {
+                    self.answer_expectation(fd)?;
                     return Ok(());
                 }
+            }
 
-                // Check if request is complete
-                if client.request.complete {
-                    self.process_request(fd)?;
+            if !client.in_flight {
+                match self.admit_request(fd) {
+                    Admission::Start => self.process_request(fd)?,
+                    // Started from the main loop once a slot frees up
+                    Admission::Wait => return Ok(()),
+                    Admission::Refuse => {
+                        self.metrics.record_request();
+                        self.ensure_request_id(fd);
+                        self.send_error(fd, 503)?;
+                    }
                 }
+            } else {
+                self.process_request(fd)?;
+            }
+            dispatched += 1;
+
+            // A running script keeps its request current until it is done;
+            // a dropped connection has no next request
+            let Some(client) = self.clients.get_mut(&fd) else {
+                return Ok(());
+            };
+            if client.cgi.is_some() {
+                return Ok(());
+            }
+
+            // Start on the next request with whatever bytes are left over
+            client.parser.reset();
+            client.request = HttpRequest::new();
+            client.expectation_answered = false;
+            if client.parser.has_buffered_data() {
+                client.request_start = Some(self.clock.now());
+            }
+        }
+    }
+
+    // Take a request slot for the complete request on `fd`, or line it up
+    // behind the requests already waiting. Slots are handed out in arrival
+    // order, so a newcomer never overtakes the queue.
+    fn admit_request(&mut self, fd: RawFd) -> Admission {
+        let client = &self.clients[&fd];
+        let host = client.request.headers.get("host").map(|host| host.as_str());
+        let config = self.find_server_config(client.local_port, host);
+        let (limit, queue_size) = (config.max_concurrent_requests, config.request_queue_size);
+
+        let first_in_line = match self.request_queue.front() {
+            Some(&front) => front == fd,
+            None => true,
+        };
+        if first_in_line && limit.is_none_or(|limit| self.requests_in_flight < limit) {
+            let client = self.clients.get_mut(&fd).unwrap();
+            if client.queued {
+                client.queued = false;
+                self.request_queue.pop_front();
+            }
+            client.in_flight = true;
+            self.requests_in_flight += 1;
+            return Admission::Start;
+        }
+
+        let client = self.clients.get_mut(&fd).unwrap();
+        if client.queued {
+            Admission::Wait
+        } else if self.request_queue.len() < queue_size {
+            client.queued = true;
+            self.request_queue.push_back(fd);
+            Admission::Wait
+        } else {
+            Admission::Refuse
+        }
+    }
+
+    // Start waiting requests, oldest first, while there are slots for them
+    fn start_queued_requests(&mut self) {
+        while let Some(&fd) = self.request_queue.front() {
+            if self.process_complete_requests(fd).is_err() {
+                self.close_client(fd);
             }
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                // No data available, continue
+            // Still first in line: no slot yet, or the client can't take its
+            // response right now
+            if self.request_queue.front() == Some(&fd) {
+                break;
             }
-            Err(e) => return Err(e),
         }
+    }
+
+    fn send_bad_request(&mut self, fd: RawFd) -> io::Result<()> {
+        let code = if self.clients[&fd].parser.headers_too_large() { 431 } else { 400 };
+        let mut response = HttpResponse::error_page(code, None);
+        response.add_header("Connection".to_string(), "close".to_string());
+        self.send_response(fd, response)?;
 
+        // Nothing after a malformed request can be framed reliably, so the
+        // connection is closed once the error has gone out
+        let client = self.clients.get_mut(&fd).unwrap();
+        client.parser = HttpParser::new();
+        client.request = HttpRequest::new();
+        client.close_after_write = true;
         Ok(())
     }
 
     fn handle_write(&mut self, fd: RawFd) -> io::Result<()> {
         let client = self.clients.get_mut(&fd).unwrap();
-        client.last_activity = Instant::now();
+        client.last_activity = self.clock.now();
+        let write_chunk = client.server_config.write_chunk_size;
+        let chunk_size = write_chunk.unwrap_or(STREAM_CHUNK_SIZE);
+
+        if let ClientState::Writing { ref mut response, ref mut written, ref mut body } = client.state {
+            // Top up from a streamed body only once the socket has drained
+            // most of what is pending, keeping the buffer bounded
+            if let Some(stream) = body {
+                if stream.remaining > 0 && response.len() - *written < chunk_size {
+                    response.drain(..*written);
+                    *written = 0;
+
+                    let pending = response.len();
+                    let want = chunk_size.min(stream.remaining as usize);
+                    response.resize(pending + want, 0);
+                    let n = stream.file.read(&mut response[pending..])?;
+                    if n == 0 {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "File truncated while streaming"));
+                    }
+                    response.truncate(pending + n);
+                    stream.remaining -= n as u64;
+                }
+            }
 
-        if let ClientState::Writing { ref response, ref mut written } = client.state {
-            match client.stream.write(&response[*written..]) {
+            let end = write_chunk.map_or(response.len(), |chunk| response.len().min(*written + chunk));
+            self.metrics.record_socket_write();
+            // A write cut short by a signal sent nothing and is simply retried
+            let result = loop {
+                match client.stream.write(&response[*written..end]) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    result => break result,
+                }
+            };
+            match result {
                 Ok(0) => {
                     return Err(io::Error::new(io::ErrorKind::WriteZero, "Write zero"));
                 }
                 Ok(n) => {
                     *written += n;
-
-                    if *written >= response.len() {
-                        // Response sent, reset for next request
-                        client.state = ClientState::Reading;
-                        client.parser = HttpParser::new();
-                        client.request = HttpRequest::new();
-
-                        // Switch back to reading
-                        self.epoll.modify(fd, libc::EPOLLIN as u32, fd as u64)?;
-                    }
+                    client.last_write_progress = self.clock.now();
+                    client.bytes_flushed += n as u64;
+                    client.bytes_since_check += n as u64;
                 }
                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
                     // Can't write now, will try again
+                    return Ok(());
                 }
                 Err(e) => return Err(e),
             }
+
+            return self.after_write(fd);
         }
 
         Ok(())
     }
 
+    // Report responses whose last byte went out and, once nothing is left to
+    // send, go back to reading
+    fn after_write(&mut self, fd: RawFd) -> io::Result<()> {
+        let client = self.clients.get_mut(&fd).unwrap();
+
+        // Report every response whose last byte just went out
+        while client.completions
+            .front()
+            .is_some_and(|completion| completion.end_offset <= client.bytes_flushed)
+        {
+            let completion = client.completions.pop_front().unwrap();
+            let duration = self.clock.now().duration_since(completion.started);
+            self.metrics.record_duration(duration);
+            self.observer.on_request_complete(&RequestInfo {
+                remote: client.remote_addr,
+                method: &completion.method,
+                uri: &completion.uri,
+                version: &completion.version,
+                status: completion.status,
+                request_bytes: completion.request_bytes,
+                bytes: completion.bytes,
+                duration,
+                request_id: &completion.request_id,
+                referer: completion.referer.as_deref(),
+                user_agent: completion.user_agent.as_deref(),
+                log_format: &completion.log_format,
+            });
+        }
+
+        let (pending, body_done) = match client.state {
+            ClientState::Writing { ref response, written, ref body } => (
+                response.len() - written,
+                body.as_ref().is_none_or(|stream| stream.remaining == 0),
+            ),
+            ClientState::Reading => return Ok(()),
+        };
+
+        // A script paused behind a slow client may produce more once most of
+        // its output went out
+        if let Some(cgi) = client.cgi.as_mut() {
+            if cgi.paused && pending < STREAM_CHUNK_SIZE {
+                cgi.paused = false;
+                if let Some(stdout) = &cgi.stdout {
+                    self.epoll.modify(stdout.as_raw_fd(), libc::EPOLLIN as u32, stdout.as_raw_fd() as u64)?;
+                }
+            }
+        }
+
+        if pending > 0 || !body_done {
+            return Ok(());
+        }
+
+        // Caught up with a script that is still running; wait for its output
+        // (or the client hanging up) rather than for writability
+        if client.cgi.is_some() {
+            return self.epoll.modify(fd, libc::EPOLLIN as u32, fd as u64);
+        }
+
+        if client.close_after_write {
+            self.close_client(fd);
+            return Ok(());
+        }
+
+        // Response sent, the parser was already reset when
+        // the request was dispatched
+        client.state = ClientState::Reading;
+        if client.in_flight {
+            client.in_flight = false;
+            self.requests_in_flight -= 1;
+        }
+
+        // Switch back to reading
+        self.epoll.modify(fd, libc::EPOLLIN as u32, fd as u64)?;
+
+        // Pipelined requests held back while writing
+        self.process_complete_requests(fd)
+    }
+
    fn process_request(&mut self, fd: RawFd) -> io::Result<()> {
     // Clone the data we need before borrowing self mutably
-    let (method, uri, body_len, server_config) = {
+    let (mut method, uri, body_len, local_port, host) = {
         let client = self.clients.get(&fd).unwrap();
         (
             client.request.method.clone(),
             client.request.uri.clone(),
             client.request.body.len(),
-            client.server_config.clone(),
+            client.local_port,
+            client.request.headers.get("host").cloned(),
         )
     };
+    let server_config = self.find_server_config(local_port, host.as_deref()).clone();
+    self.clients.get_mut(&fd).unwrap().server_config = server_config.clone();
+
+    self.metrics.record_request();
+    self.metrics.record_request_body(body_len);
+    self.ensure_request_id(fd);
+    let client = self.clients.get_mut(&fd).unwrap();
+    client.internal_redirects = 0;
+    client.error_page = None;
 
     // Check body size limit
-    if body_len > server_config.client_max_body_size {
-        let response = HttpResponse::error_page(
-            413,
-            server_config.error_pages.get(&413).map(|s| s.as_str()),
-        );
+    let discarded = self.clients.get(&fd).unwrap().parser.is_discarding_body();
+    if discarded || server_config.body_size_limit().is_some_and(|limit| body_len > limit) {
+        return self.send_error(fd, 413);
+    }
+
+    // 100-continue is the only expectation there is to meet
+    let request = &self.clients.get(&fd).unwrap().request;
+    let unmet_expectation = request.headers
+        .get("expect")
+        .is_some_and(|expect| !expect.trim().eq_ignore_ascii_case("100-continue"));
+    if unmet_expectation && request.version == "HTTP/1.1" {
+        return self.send_error(fd, 417);
+    }
+
+    // Protocol upgrades such as WebSocket aren't supported; say so rather
+    // than answering the handshake as a plain GET
+    if self.clients.get(&fd).unwrap().request.is_upgrade() {
+        let mut response = HttpResponse::new(501);
+        response.add_header("Content-Type".to_string(), "text/plain".to_string());
+        response.set_body_str("Protocol upgrades (e.g. WebSocket) are not supported\n");
         return self.send_response(fd, response);
     }
 
-    // Find matching route
-    let route = self.find_route(&uri, &server_config);
+    // `OPTIONS *` asks about the server as a whole rather than any resource
+    if method == "OPTIONS" && uri.as_str() == "*" {
+        let mut response = HttpResponse::new(204);
+        response.add_header("Allow".to_string(), Self::server_methods(&server_config).join(", "));
+        return self.send_response(fd, response);
+    }
 
-    // Check if method is allowed
-    if let Some(ref route) = route {
-        if !route.methods.contains(&method) {
-            let response = HttpResponse::error_page(
-                405,
-                server_config.error_pages.get(&405).map(|s| s.as_str()),
-            );
-            return self.send_response(fd, response);
-        }
+    // Decode and normalize the path before any routing decisions
+    let path = uri.decoded_path();
+    if path.split('/').any(|segment| segment == "..") {
+        return self.send_error(fd, 403);
     }
 
-    // Handle redirect
-    if let Some(ref route) = route {
-        if let Some((code, ref location)) = route.redirect {
-            let mut response = HttpResponse::new(code);
-            response.add_header("Location".to_string(), location.clone());
-            return self.send_response(fd, response);
+    // Find matching route; internal locations don't exist for clients
+    let route = self.find_route(&path, &server_config);
+    if route.is_some_and(|route| route.internal) {
+        return self.send_error(fd, 404);
+    }
+    self.clients.get_mut(&fd).unwrap().sessions_enabled = route
+        .and_then(|route| route.sessions)
+        .unwrap_or(server_config.sessions);
+
+    // A CORS preflight asks whether a cross-origin request may follow. It is
+    // answered from the location's cors_* settings rather than as an OPTIONS
+    // for the resource, so OPTIONS needn't be among its allowed methods.
+    if method == "OPTIONS" {
+        if let Some(route) = route.filter(|route| !route.cors_allow_origin.is_empty()) {
+            let headers = &self.clients[&fd].request.headers;
+            if headers.contains_key("origin") && headers.contains_key("access-control-request-method") {
+                let response = Self::preflight_response(route, headers);
+                return self.send_response(fd, response);
+            }
         }
     }
 
-    // Process based on method
-    match method.as_str() {
-        "GET" => self.handle_get(fd, route),
-        "POST" => self.handle_post(fd, route),
-        "DELETE" => self.handle_delete(fd, route),
-        _ => {
-            let response = HttpResponse::error_page(
-                405,
-                server_config.error_pages.get(&405).map(|s| s.as_str()),
-            );
-            self.send_response(fd, response)
+    // A POST may stand in for a method the client can't send; from here on
+    // the request is handled as the overridden method
+    if server_config.method_override && method == "POST" {
+        let client = self.clients.get_mut(&fd).unwrap();
+        if let Some(overridden) = client.request.headers.get("x-http-method-override") {
+            method = overridden.trim().to_uppercase();
+            client.request.method = method.clone();
         }
     }
-}
 
-    fn handle_get(&mut self, fd: RawFd, route: Option<&Route>) -> io::Result<()> {
-        let client = self.clients.get(&fd).unwrap();
-        let request = &client.request;
-        let server_config = &client.server_config;
+    // Check if method is allowed
+    // HEAD is a GET without the body, so it goes wherever GET does
+    if let Some(route) = route {
+        let allowed = route.methods.contains(&method)
+            || (method == "HEAD" && route.methods.iter().any(|m| m == "GET"));
+        if !allowed {
+            return self.send_error(fd, 405);
+        }
+        let remote_ip = self.clients[&fd].remote_addr.ip();
+        if route.limit_except.as_ref().is_some_and(|limit| !limit.permits(&method, remote_ip)) {
+            return self.send_error(fd, 403);
+        }
+    }
 
-        let route = match route {
-            Some(r) => r,
-            None => {
-                let response = HttpResponse::error_page(
-                    404,
-                    server_config.error_pages.get(&404).map(|s| s.as_str()),
-                );
-                return self.send_response(fd, response);
+    // Handle return: 3xx codes redirect, 444 drops the connection without a
+    // response, anything else answers with the text
+    if let Some(route) = route {
+        if let Some((code, ref target)) = route.redirect {
+            if code == 444 {
+                self.close_client(fd);
+                return Ok(());
             }
-        };
+            let mut response = HttpResponse::new(code);
+            if (300..400).contains(&code) {
+                let location = if server_config.absolute_redirect && target.starts_with('/') {
+                    let host = self.clients[&fd].request.headers.get("host").cloned();
+                    Self::absolute_url(&server_config, host.as_deref(), target)
+                } else {
+                    target.clone()
+                };
+                response.add_header("Location".to_string(), location);
+                // Content-Length: 0, or a kept-alive client waits for more
+                response.set_body(Vec::new());
+            } else {
+                response.add_header("Content-Type".to_string(), "text/plain".to_string());
+                response.set_body_str(target);
+            }
+            return self.send_response(fd, response);
+        }
+    }
 
-        let uri_path = request.uri.split('?').next().unwrap_or(&request.uri);
-        let file_path = self.resolve_path(uri_path, route);
+    // Enter draining mode
+    if let Some(route) = route {
+        if route.drain_control {
+            return self.handle_drain(fd, &method);
+        }
+    }
 
-        // Check if file exists
-        let metadata = match std::fs::metadata(&file_path) {
-            Ok(m) => m,
-            Err(_) => {
-                let response = HttpResponse::error_page(
-                    404,
-                    server_config.error_pages.get(&404).map(|s| s.as_str()),
-                );
-                return self.send_response(fd, response);
-            }
-        };
+    // List the live sessions
+    if let Some(route) = route {
+        if route.session_list {
+            return self.handle_session_list(fd);
+        }
+    }
 
-        // If directory
-       // If directory
-if metadata.is_dir() {
-    // Try index files
-    for index_file in &route.index {
-        let index_path = format!("{}/{}", file_path, index_file);
-        if std::path::Path::new(&index_path).exists() {
-            return self.serve_file(fd, &index_path);
+    // Serve the metrics endpoint
+    if let Some(route) = route {
+        if route.metrics {
+            let mut response = HttpResponse::new(200);
+            response.add_header(
+                "Content-Type".to_string(),
+                "text/plain; version=0.0.4".to_string(),
+            );
+            response.set_body_str(&self.metrics.render(
+                self.clients.len(),
+                self.session_manager.session_count(),
+            ));
+            return self.send_response(fd, response);
         }
     }
 
-    // Directory listing
-    if route.autoindex {
-        // Clone what we need before calling serve_directory_listing
-        let file_path_clone = file_path.clone();
-        let uri_path_clone = uri_path.to_string();
-        return self.serve_directory_listing(fd, &file_path_clone, &uri_path_clone);
-    } else {
-        let response = HttpResponse::error_page(
-            403,
-            server_config.error_pages.get(&403).map(|s| s.as_str()),
-        );
+    // Hand the request to a handler registered in code
+    if let Some(handler) = route.and_then(|route| route.handler.as_ref()) {
+        let response = handler.handle(&self.clients.get(&fd).unwrap().request);
         return self.send_response(fd, response);
     }
-}
 
-        // Check for CGI
-        if let Some(ref cgi_ext) = route.cgi_extension {
-            if file_path.ends_with(cgi_ext) {
-                return self.execute_cgi(fd, route, &file_path);
-            }
+    // Browsers ask for a favicon on every visit; a built-in one keeps that
+    // from filling the logs with 404s when nothing on disk provides it
+    if server_config.default_favicon && method == "GET" && path == "/favicon.ico" {
+        let on_disk = route.is_some_and(|route| {
+            self.files.metadata(&self.resolve_path(&path, route)).is_ok_and(|m| m.is_file())
+        });
+        if !on_disk {
+            return self.send_response(fd, favicon::response());
         }
-
-        // Serve regular file
-        self.serve_file(fd, &file_path)
     }
 
-    fn handle_post(&mut self, fd: RawFd, route: Option<&Route>) -> io::Result<()> {
-        let client = self.clients.get(&fd).unwrap();
-        let request = &client.request;
-        let server_config = &client.server_config;
+    // Process based on method
+    match method.as_str() {
+        "GET" | "HEAD" => self.serve_path(fd, route, &path),
+        "POST" => self.handle_post(fd, route, &path),
+        "PUT" => self.handle_put(fd, route, &path),
+        "DELETE" => self.handle_delete(fd, route, &path),
+        _ => self.send_error(fd, 405),
+    }
+}
 
-        let route = match route {
-            Some(r) => r,
+    // `path` on this server as an absolute URL, for clients that need a
+    // redirect spelled out. The Host the client used is kept, port and all;
+    // without one the server's first name or its address stands in.
+    fn absolute_url(server_config: &ServerConfig, host: Option<&str>, path: &str) -> String {
+        let authority = match host.map(str::trim).filter(|host| !host.is_empty()) {
+            Some(host) => host.to_string(),
             None => {
-                let response = HttpResponse::error_page(
-                    404,
-                    server_config.error_pages.get(&404).map(|s| s.as_str()),
-                );
-                return self.send_response(fd, response);
+                let name = server_config.server_names.first().unwrap_or(&server_config.host);
+                if server_config.port == 80 {
+                    name.clone()
+                } else {
+                    format!("{}:{}", name, server_config.port)
+                }
             }
         };
+        // Connections are plain TCP, so the scheme is always http
+        format!("http://{}{}", authority, path)
+    }
 
-        let uri_path = request.uri.split('?').next().unwrap_or(&request.uri);
-
-        // Check for file upload
-        if let Some(content_type) = request.headers.get("content-type") {
-            if content_type.contains("multipart/form-data") {
-                return self.handle_file_upload(fd, route);
+    // A 204 carrying the location's CORS settings. An origin that isn't
+    // allowed gets no Access-Control headers at all, which the browser takes
+    // as a refusal.
+    fn preflight_response(route: &Route, headers: &HashMap<String, String>) -> HttpResponse {
+        let mut response = HttpResponse::new(204);
+        let origin = headers["origin"].trim();
+        let wildcard = route.cors_allow_origin.iter().any(|allowed| allowed == "*");
+        if !wildcard {
+            response.add_vary("Origin");
+            if !route.cors_allow_origin.iter().any(|allowed| allowed == origin) {
+                return response;
             }
         }
 
-        // Check for CGI
-        let file_path = self.resolve_path(uri_path, route);
-        if let Some(ref cgi_ext) = route.cgi_extension {
-            if uri_path.ends_with(cgi_ext) {
-                return self.execute_cgi(fd, route, &file_path);
-            }
+        let allow_origin = if wildcard { "*" } else { origin };
+        response.add_header("Access-Control-Allow-Origin".to_string(), allow_origin.to_string());
+        let methods = if route.cors_allow_methods.is_empty() {
+            &route.methods
+        } else {
+            &route.cors_allow_methods
+        };
+        response.add_header("Access-Control-Allow-Methods".to_string(), methods.join(", "));
+        if !route.cors_allow_headers.is_empty() {
+            response.add_header("Access-Control-Allow-Headers".to_string(), route.cors_allow_headers.join(", "));
+        }
+        if let Some(max_age) = route.cors_max_age {
+            response.add_header("Access-Control-Max-Age".to_string(), max_age.to_string());
+        }
+        response
+    }
+
+    // Every method some location of the server answers, in the order of
+    // SUPPORTED_METHODS; OPTIONS itself is always there for `OPTIONS *`
+    fn server_methods(server_config: &ServerConfig) -> Vec<&'static str> {
+        SUPPORTED_METHODS
+            .into_iter()
+            .filter(|method| {
+                *method == "OPTIONS"
+                    || server_config.routes.iter().any(|route| route.methods.iter().any(|m| m == method))
+            })
+            .collect()
+    }
+
+    // A GET resolves in one fixed order: the file the URI names, then each
+    // try_files candidate, then the directory's index files and autoindex,
+    // then the try_files fallback, and finally 403 for a directory or 404
+    fn serve_path(&mut self, fd: RawFd, route: Option<&Route>, uri_path: &str) -> io::Result<()> {
+        let route = match route {
+            Some(r) => r,
+            None => return self.send_error(fd, 404),
+        };
+
+        let file_path = self.resolve_path(uri_path, route);
+        let metadata = self.files.metadata(&file_path);
+        if metadata.as_ref().is_ok_and(|m| !m.is_dir) {
+            return self.serve_regular(fd, route, &file_path);
+        }
+
+        // A candidate ending in a slash names a directory, anything else a file
+        let candidates = route.try_files.split_last().map_or(&[][..], |(_, candidates)| candidates);
+        for candidate in candidates {
+            let candidate_uri = candidate.replace("$uri", uri_path);
+            let candidate_path = self.resolve_path(&candidate_uri, route);
+            let names_dir = candidate_uri.ends_with('/');
+            match self.files.metadata(&candidate_path) {
+                Ok(m) if names_dir && m.is_dir => {}
+                Ok(m) if !names_dir && m.is_file() => return self.serve_regular(fd, route, &candidate_path),
+                _ => continue,
+            }
+            if self.serve_directory(fd, route, &candidate_uri, &candidate_path)? {
+                return Ok(());
+            }
+        }
+
+        let is_dir = metadata.as_ref().is_ok_and(|m| m.is_dir);
+        if is_dir && self.serve_directory(fd, route, uri_path, &file_path)? {
+            return Ok(());
+        }
+
+        // `=404` and the like answer with that status instead of redirecting
+        if let Some(fallback) = route.try_files.last() {
+            if let Some(code) = fallback.strip_prefix('=').and_then(|code| code.parse().ok()) {
+                return self.send_error(fd, code);
+            }
+            let fallback_uri = fallback.replace("$uri", uri_path);
+            return self.internal_redirect(fd, &fallback_uri);
+        }
+
+        match metadata {
+            Ok(_) => {
+                self.log_path_error(fd, 403, &file_path, "directory has no index");
+                self.send_error(fd, 403)
+            }
+            Err(e) => self.send_path_error(fd, &file_path, &e),
+        }
+    }
+
+    // Answer for a directory with its index file or listing. Returns false
+    // when it has neither, leaving the request to the next step.
+    fn serve_directory(&mut self, fd: RawFd, route: &Route, uri_path: &str, dir_path: &str) -> io::Result<bool> {
+        // A directory is addressed with a trailing slash, so relative links
+        // in its index resolve inside it. Only the client's own URI is
+        // redirected; internal redirects serve the directory as they are.
+        // The check uses the raw URI, since the normalized path drops the slash.
+        let client = &self.clients[&fd];
+        let raw_path = client.request.uri.path();
+        let query = client.request.uri.query();
+        if !raw_path.ends_with('/') && client.internal_redirects == 0 && client.error_page.is_none() {
+            let location = match query {
+                Some(query) => format!("{}/?{}", raw_path, query),
+                None => format!("{}/", raw_path),
+            };
+            let mut response = HttpResponse::new(301);
+            response.add_header("Location".to_string(), location);
+            response.set_body(Vec::new());
+            self.send_response(fd, response)?;
+            return Ok(true);
+        }
+
+        for index_file in &route.index {
+            // An absolute index is an internal redirect, like nginx
+            if index_file.starts_with('/') {
+                self.internal_redirect(fd, index_file)?;
+                return Ok(true);
+            }
+
+            let index_path = format!("{}/{}", dir_path, index_file);
+            if self.files.metadata(&index_path).is_ok_and(|m| m.is_file()) {
+                self.serve_file(fd, route, &index_path)?;
+                return Ok(true);
+            }
+        }
+
+        if route.autoindex {
+            self.serve_directory_listing(fd, dir_path, uri_path)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // A file is run as a script when the route has CGI for its extension,
+    // otherwise sent as it is
+    fn serve_regular(&mut self, fd: RawFd, route: &Route, file_path: &str) -> io::Result<()> {
+        if is_cgi(route, file_path) {
+            return self.execute_cgi(fd, route, file_path);
+        }
+        self.serve_file(fd, route, file_path)
+    }
+
+    fn internal_redirect(&mut self, fd: RawFd, uri: &str) -> io::Result<()> {
+        let client = self.clients.get_mut(&fd).unwrap();
+        client.internal_redirects += 1;
+
+        // Circular try_files/index setups would otherwise never terminate
+        if client.internal_redirects > MAX_INTERNAL_REDIRECTS {
+            eprintln!(
+                "[{}] Internal redirect limit exceeded while resolving {}",
+                client.request.headers["x-request-id"], uri
+            );
+            return self.send_error(fd, 500);
+        }
+
+        let server_config = client.server_config.clone();
+        let uri_path = match RequestUri::parse(uri) {
+            Ok(target) => target.decoded_path(),
+            Err(_) => return self.send_error(fd, 500),
+        };
+        let route = self.find_route(&uri_path, &server_config);
+        self.serve_path(fd, route, &uri_path)
+    }
+
+    fn handle_post(&mut self, fd: RawFd, route: Option<&Route>, uri_path: &str) -> io::Result<()> {
+        let route = match route {
+            Some(r) => r,
+            None => return self.send_error(fd, 404),
+        };
+        let request = &self.clients.get(&fd).unwrap().request;
+
+        // Only routes with an upload_dir store files; elsewhere a form goes
+        // to the script it was posted to, or is refused
+        let multipart = request.headers
+            .get("content-type")
+            .is_some_and(|content_type| content_type.contains("multipart/form-data"));
+        if multipart {
+            if let Some(ref upload_dir) = route.upload_dir {
+                return self.handle_file_upload(fd, route, upload_dir);
+            }
+        }
+
+        // Check for CGI
+        if is_cgi(route, uri_path) {
+            let file_path = self.resolve_path(uri_path, route);
+            return self.execute_cgi(fd, route, &file_path);
+        }
+
+        if multipart {
+            return self.send_error(fd, 403);
         }
 
         // Default POST response
@@ -406,86 +1370,327 @@ if metadata.is_dir() {
         self.send_response(fd, response)
     }
 
-    fn handle_delete(&mut self, fd: RawFd, route: Option<&Route>) -> io::Result<()> {
+    // Store the body at the resolved path: 201 for a new file, 204 for one
+    // replaced. The parent directory has to exist already. A script gets
+    // the request instead and decides for itself.
+    fn handle_put(&mut self, fd: RawFd, route: Option<&Route>, uri_path: &str) -> io::Result<()> {
+        let route = match route {
+            Some(r) => r,
+            None => return self.send_error(fd, 404),
+        };
+
+        let file_path = self.resolve_path(uri_path, route);
+        if is_cgi(route, uri_path) {
+            return self.execute_cgi(fd, route, &file_path);
+        }
+
         let client = self.clients.get(&fd).unwrap();
-        let request = &client.request;
-        let server_config = &client.server_config;
 
+        // If-Match / If-Unmodified-Since guard against overwriting a file that
+        // changed since the client last saw it
+        let existing = DiskFileProvider.metadata(&file_path).ok();
+        if existing.as_ref().is_some_and(|metadata| metadata.is_dir) {
+            return self.send_error(fd, 409);
+        }
+        let validators = existing.as_ref().map(Validators::from_info);
+        if let Some(code) = conditional::evaluate(&client.request.headers, "PUT", validators.as_ref()) {
+            return self.send_error(fd, code);
+        }
+
+        // Written beside the target and renamed over it, so a reader never
+        // sees half a file
+        let temp_path = format!("{}.put-{}", file_path, random_id(fd as usize));
+        let result = std::fs::write(&temp_path, &client.request.body)
+            .and_then(|_| std::fs::rename(&temp_path, &file_path));
+        match result {
+            Ok(()) if existing.is_some() => self.send_response(fd, HttpResponse::new(204)),
+            Ok(()) => {
+                let mut response = HttpResponse::new(201);
+                response.add_header("Location".to_string(), uri_path.to_string());
+                response.set_body(Vec::new());
+                self.send_response(fd, response)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&temp_path);
+                match e.kind() {
+                    io::ErrorKind::NotFound => self.send_error(fd, 409),
+                    io::ErrorKind::PermissionDenied => self.send_error(fd, 403),
+                    _ => self.send_error(fd, 500),
+                }
+            }
+        }
+    }
+
+    // Remove the resolved file, or let a script handle the DELETE
+    fn handle_delete(&mut self, fd: RawFd, route: Option<&Route>, uri_path: &str) -> io::Result<()> {
         let route = match route {
             Some(r) => r,
-            None => {
-                let response = HttpResponse::error_page(
-                    404,
-                    server_config.error_pages.get(&404).map(|s| s.as_str()),
-                );
-                return self.send_response(fd, response);
-            }
+            None => return self.send_error(fd, 404),
         };
 
-        let uri_path = request.uri.split('?').next().unwrap_or(&request.uri);
         let file_path = self.resolve_path(uri_path, route);
+        if is_cgi(route, uri_path) {
+            return self.execute_cgi(fd, route, &file_path);
+        }
+
+        let client = self.clients.get(&fd).unwrap();
 
-        match std::fs::remove_file(&file_path) {
+        // If-Match / If-Unmodified-Since guard against deleting a file that
+        // changed since the client last saw it
+        let validators = DiskFileProvider.metadata(&file_path).ok().map(|metadata| Validators::from_info(&metadata));
+        if let Some(code) = conditional::evaluate(&client.request.headers, "DELETE", validators.as_ref()) {
+            return self.send_error(fd, code);
+        }
+
+        // Directories go only where the route opts in, and never the route's
+        // own root. symlink_metadata keeps a link to a directory a plain file.
+        let is_dir = std::fs::symlink_metadata(&file_path).is_ok_and(|m| m.is_dir());
+        let result = if is_dir {
+            let is_root = uri_path.trim_end_matches('/') == route.path.trim_end_matches('/');
+            if !route.allow_dir_delete || is_root {
+                return self.send_error(fd, 403);
+            }
+            std::fs::remove_dir_all(&file_path)
+        } else {
+            std::fs::remove_file(&file_path)
+        };
+
+        match result {
             Ok(_) => {
                 let response = HttpResponse::new(204);
                 self.send_response(fd, response)
             }
-            Err(_) => {
-                let response = HttpResponse::error_page(
-                    404,
-                    server_config.error_pages.get(&404).map(|s| s.as_str()),
-                );
-                self.send_response(fd, response)
-            }
+            Err(_) => self.send_error(fd, 404),
         }
     }
 
-    fn serve_file(&mut self, fd: RawFd, file_path: &str) -> io::Result<()> {
-        let content = match std::fs::read(file_path) {
-            Ok(c) => c,
-            Err(_) => {
-                let client = self.clients.get(&fd).unwrap();
-                let response = HttpResponse::error_page(
-                    404,
-                    client.server_config.error_pages.get(&404).map(|s| s.as_str()),
+    // Responses currently holding a file open to stream their body
+    fn open_file_streams(&self) -> usize {
+        self.clients
+            .values()
+            .filter(|client| matches!(client.state, ClientState::Writing { body: Some(_), .. }))
+            .count()
+    }
+
+    // Stream `len` bytes of `file`, or read them now and close it when
+    // `buffered`
+    fn attach_file(response: &mut HttpResponse, file: Box<dyn FileBody>, len: u64, buffered: bool) -> io::Result<()> {
+        if buffered {
+            let mut body = Vec::with_capacity(len as usize);
+            file.take(len).read_to_end(&mut body)?;
+            response.set_body(body);
+        } else {
+            response.set_body_file(file, len);
+        }
+        Ok(())
+    }
+
+    fn serve_file(&mut self, fd: RawFd, route: &Route, file_path: &str) -> io::Result<()> {
+        let precompressed = if route.gzip_static {
+            self.find_gzip_sibling(fd, file_path)
+        } else {
+            None
+        };
+
+        let read_path = precompressed.as_deref().unwrap_or(file_path);
+        let (mut file, metadata) = match self.files.metadata(read_path).and_then(|metadata| {
+            Ok((self.files.open(read_path)?, metadata))
+        }) {
+            Ok(opened) => opened,
+            Err(e) => return self.send_path_error(fd, read_path, &e),
+        };
+        let len = metadata.len;
+        let validators = Validators::from_info(&metadata);
+
+        let content_type = self.get_content_type(route, file_path);
+
+        // An error page is sent whole, whatever the failed request asked for
+        let client = self.clients.get(&fd).unwrap();
+        let serving_error_page = client.error_page.is_some();
+        let condition = if serving_error_page {
+            None
+        } else {
+            conditional::evaluate(&client.request.headers, &client.request.method, Some(&validators))
+        };
+        match condition {
+            Some(304) => {
+                let mut response = HttpResponse::not_modified(
+                    Some(&validators.etag),
+                    &validators.last_modified_header(),
                 );
+                if route.gzip_static {
+                    response.add_vary("Accept-Encoding");
+                }
+                return self.send_response(fd, response);
+            }
+            Some(code) => return self.send_error(fd, code),
+            None => {}
+        }
+
+        // At the open-file budget the body can't be left streaming from the file
+        let buffered = client.server_config.max_open_files
+            .is_some_and(|limit| self.open_file_streams() >= limit);
+
+        // Byte ranges apply to the representation actually being sent
+        let ranges = if !serving_error_page && conditional::range_applies(&client.request.headers, &validators) {
+            client.request.headers
+                .get("range")
+                .and_then(|range| parse_range(range, len as usize))
+        } else {
+            None
+        };
+
+        let mut response = match ranges {
+            Some(ranges) if ranges.is_empty() => {
+                // Content-Range must accompany the page, so a URI error_page
+                // can't stand in here
+                let custom_page = client.server_config.error_pages
+                    .get(&416)
+                    .filter(|page| !page.target.starts_with('/') && page.status.is_none())
+                    .map(|page| page.target.as_str());
+                let mut response = HttpResponse::error_page(416, custom_page);
+                response.add_header("Content-Range".to_string(), format!("bytes */{}", len));
                 return self.send_response(fd, response);
             }
+            Some(ranges) if ranges.len() == 1 => {
+                let (start, end) = ranges[0];
+                let body_len = (end - start + 1) as u64;
+                if buffered && body_len > MAX_BUFFERED_FILE {
+                    return self.send_error(fd, 503);
+                }
+                file.seek(SeekFrom::Start(start as u64))?;
+
+                let mut response = HttpResponse::new(206);
+                response.add_header("Content-Type".to_string(), content_type);
+                response.add_header(
+                    "Content-Range".to_string(),
+                    format!("bytes {}-{}/{}", start, end, len),
+                );
+                Self::attach_file(&mut response, file, body_len, buffered)?;
+                response
+            }
+            Some(ranges) => {
+                let mut content = Vec::new();
+                file.read_to_end(&mut content)?;
+                HttpResponse::byte_ranges(&content, &ranges, &content_type)
+            }
+            None => {
+                if buffered && len > MAX_BUFFERED_FILE {
+                    return self.send_error(fd, 503);
+                }
+                let mut response = HttpResponse::new(200);
+                response.add_header("Content-Type".to_string(), content_type);
+                Self::attach_file(&mut response, file, len, buffered)?;
+                response
+            }
         };
 
-        let mut response = HttpResponse::new(200);
-        let content_type = self.get_content_type(file_path);
-        response.add_header("Content-Type".to_string(), content_type);
-        response.set_body(content);
+        // `?download=1` asks for the same treatment as force_download
+        let client = self.clients.get(&fd).unwrap();
+        let download = route.force_download
+            || client.request.uri.query_params().get("download").is_some_and(|value| value == "1");
+        if download && !serving_error_page {
+            response.add_header(
+                "Content-Disposition".to_string(),
+                Self::attachment_disposition(&client.request.uri, file_path),
+            );
+        }
+
+        response.add_header("Accept-Ranges".to_string(), "bytes".to_string());
+        response.add_header("Last-Modified".to_string(), validators.last_modified_header());
+        response.add_header("ETag".to_string(), validators.etag);
+        if precompressed.is_some() {
+            response.add_header("Content-Encoding".to_string(), "gzip".to_string());
+        }
+        // Either variant may be picked depending on Accept-Encoding
+        if route.gzip_static {
+            response.add_vary("Accept-Encoding");
+        }
 
         self.send_response(fd, response)
     }
 
-    fn serve_directory_listing(&mut self, fd: RawFd, dir_path: &str, uri_path: &str) -> io::Result<()> {
-        let entries = match std::fs::read_dir(dir_path) {
-            Ok(entries) => entries,
-            Err(_) => {
-                let client = self.clients.get(&fd).unwrap();
-                let response = HttpResponse::error_page(
-                    500,
-                    client.server_config.error_pages.get(&500).map(|s| s.as_str()),
-                );
-                return self.send_response(fd, response);
-            }
+    // `attachment` named after the last segment of the request path, or the
+    // file itself when the path ends in a directory. Names that aren't plain
+    // ASCII also get an RFC 5987 filename*.
+    fn attachment_disposition(uri: &RequestUri, file_path: &str) -> String {
+        let path = decode_path(uri.path());
+        let name = match path.rsplit('/').next() {
+            Some(segment) if !segment.is_empty() && !path.ends_with('/') => segment.to_string(),
+            _ => file_path.rsplit('/').next().unwrap_or("download").to_string(),
         };
 
-        let mut file_names = Vec::new();
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Some(name) = entry.file_name().to_str() {
-                    file_names.push(name.to_string());
-                }
+        let fallback: String = name
+            .chars()
+            .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+            .collect();
+        let mut disposition = format!("attachment; filename=\"{}\"", fallback);
+
+        if fallback != name {
+            let encoded: String = name
+                .bytes()
+                .map(|b| if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                    (b as char).to_string()
+                } else {
+                    format!("%{:02X}", b)
+                })
+                .collect();
+            disposition.push_str(&format!("; filename*=UTF-8''{}", encoded));
+        }
+        disposition
+    }
+
+    // Use a precompressed `.gz` sibling when the client accepts gzip and the
+    // sibling is at least as new as the original
+    fn find_gzip_sibling(&self, fd: RawFd, file_path: &str) -> Option<String> {
+        let client = self.clients.get(&fd).unwrap();
+        let accept_encoding = client.request.headers.get("accept-encoding")?;
+        if !accepts_encoding(accept_encoding, "gzip") {
+            return None;
+        }
+
+        let gz_path = format!("{}.gz", file_path);
+        let original = self.files.metadata(file_path).ok()?;
+        let compressed = self.files.metadata(&gz_path).ok()?;
+        if !compressed.is_file() || compressed.modified < original.modified {
+            return None;
+        }
+
+        Some(gz_path)
+    }
+
+    fn serve_directory_listing(&mut self, fd: RawFd, dir_path: &str, uri_path: &str) -> io::Result<()> {
+        // Any entry added, removed or renamed bumps the directory mtime, so
+        // an unchanged mtime means the listing would render the same
+        let modified = self.files.metadata(dir_path)
+            .ok()
+            .map(|metadata| http_date::format(metadata.modified));
+
+        if let Some(ref last_modified) = modified {
+            let client = self.clients.get(&fd).unwrap();
+            let not_modified = client.request.headers
+                .get("if-modified-since")
+                .and_then(|since| http_date::parse(since))
+                .zip(http_date::parse(last_modified))
+                .is_some_and(|(since, mtime)| mtime <= since);
+
+            if not_modified {
+                let response = HttpResponse::not_modified(None, last_modified);
+                return self.send_response(fd, response);
             }
         }
 
+        let mut file_names = match self.files.read_dir(dir_path) {
+            Ok(names) => names,
+            Err(_) => return self.send_error(fd, 500),
+        };
+
         file_names.sort();
 
-        let response = HttpResponse::directory_listing(dir_path, uri_path, file_names);
+        let mut response = HttpResponse::directory_listing(dir_path, uri_path, file_names);
+        if let Some(last_modified) = modified {
+            response.add_header("Last-Modified".to_string(), last_modified);
+        }
         self.send_response(fd, response)
     }
 
@@ -494,15 +1699,14 @@ if metadata.is_dir() {
     let request = &client.request;
     let server_config = &client.server_config;
 
-    let cgi_path = route.cgi_path.as_ref().map(|s| s.as_str()).unwrap_or("/usr/bin/python3");
-    let query_string = request.uri.split('?').nth(1).unwrap_or("");
+    let cgi_path = route.cgi_path.as_deref().unwrap_or(DEFAULT_CGI_PATH);
+    // Everything after the first '?', still encoded, as CGI expects; a later
+    // '?' is part of the query
+    let query_string = request.uri.query().unwrap_or("");
 
-    // ADD THIS DEBUG LINE
-    eprintln!("DEBUG: Executing CGI: {} {}", cgi_path, script_path);
+    self.metrics.record_cgi_execution();
 
-    let remote_addr = client.stream.peer_addr()
-        .map(|a| a.ip().to_string())
-        .unwrap_or_else(|_| "0.0.0.0".to_string());
+    let remote_addr = client.remote_addr.ip().to_string();
 
     match CgiHandler::execute(
         cgi_path,
@@ -514,141 +1718,767 @@ if metadata.is_dir() {
         &server_config.host,
         server_config.port,
         &remote_addr,
+        &route.cgi_pass_env,
+        route.cgi_max_headers,
+        route.cgi_working_dir.as_deref(),
+        route.cgi_path_env.as_deref(),
     ) {
-        Ok(output) => {
-            // ADD THIS DEBUG LINE
-            eprintln!("DEBUG: CGI output length: {}", output.len());
-            
-            match CgiHandler::parse_cgi_output(&output) {
-                Ok((cgi_headers, body)) => {
-                    // ADD THIS DEBUG LINE
-                    eprintln!("DEBUG: CGI parsed successfully");
-                    
-                    let status_code = cgi_headers
-                        .get("status")
-                        .and_then(|s| s.split_whitespace().next())
-                        .and_then(|s| s.parse::<u16>().ok())
-                        .unwrap_or(200);
-
-                    let mut response = HttpResponse::new(status_code);
-
-                    for (key, value) in cgi_headers {
-                        if key != "status" {
-                            response.add_header(key, value);
-                        }
-                    }
+        Ok(mut child) => {
+            // Output is picked up from epoll as the script writes it
+            let stdout = child.stdout.take();
+            let stderr = child.stderr.take();
+            for pipe in [stdout.as_ref().map(|p| p.as_raw_fd()), stderr.as_ref().map(|p| p.as_raw_fd())]
+                .into_iter()
+                .flatten()
+            {
+                set_nonblocking(pipe)?;
+                self.epoll.add(pipe, libc::EPOLLIN as u32, pipe as u64)?;
+                self.cgi_pipes.insert(pipe, fd);
+            }
 
-                    if !response.headers.contains_key("Content-Type") {
-                        response.add_header("Content-Type".to_string(), "text/html".to_string());
+            // The body goes to stdin the same way, so a script slow to read
+            // it can't stall the server. Without a body stdin closes at once.
+            let stdin = child.stdin.take().filter(|_| !request.body.is_empty());
+            if let Some(pipe) = stdin.as_ref().map(|p| p.as_raw_fd()) {
+                set_nonblocking(pipe)?;
+                self.epoll.add(pipe, libc::EPOLLOUT as u32, pipe as u64)?;
+                self.cgi_pipes.insert(pipe, fd);
+            }
+
+            self.clients.get_mut(&fd).unwrap().cgi = Some(CgiProcess {
+                child,
+                stdin,
+                stdin_written: 0,
+                stdout,
+                stderr,
+                header_buf: Vec::new(),
+                stderr_buf: Vec::new(),
+                chunked: None,
+                paused: false,
+                started: self.clock.now(),
+                trailers: Vec::new(),
+                body_crc: 0,
+                debug: route.cgi_debug,
+            });
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("[{}] CGI execute error: {}", request.headers["x-request-id"], e);
+            self.send_error(fd, 500)
+        }
+    }
+}
+
+    // Read whatever a script's stdout or stderr has ready. The response
+    // headers go out as soon as the header block is complete and body bytes
+    // are forwarded as they arrive.
+    fn handle_cgi_output(&mut self, fd: RawFd, pipe: RawFd) -> io::Result<()> {
+        let client = self.clients.get_mut(&fd).unwrap();
+        client.last_activity = self.clock.now();
+        let cgi = client.cgi.as_mut().unwrap();
+
+        if cgi.stdin.as_ref().is_some_and(|stdin| stdin.as_raw_fd() == pipe) {
+            return self.write_cgi_input(fd);
+        }
+
+        let buffer = &mut self.read_buffer;
+        if cgi.stderr.as_ref().is_some_and(|stderr| stderr.as_raw_fd() == pipe) {
+            let stderr = cgi.stderr.as_mut().unwrap();
+            loop {
+                match stderr.read(buffer) {
+                    Ok(0) => {
+                        self.epoll.delete(pipe)?;
+                        self.cgi_pipes.remove(&pipe);
+                        self.closed_fds.insert(pipe);
+                        cgi.stderr = None;
+                        break;
+                    }
+                    Ok(n) => {
+                        let room = MAX_CGI_STDERR.saturating_sub(cgi.stderr_buf.len());
+                        cgi.stderr_buf.extend_from_slice(&buffer[..n.min(room)]);
                     }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            return self.finish_cgi_if_done(fd);
+        }
 
-                    response.set_body(body);
-                    self.send_response(fd, response)
+        let stdout = match cgi.stdout.as_mut() {
+            Some(stdout) => stdout,
+            None => return Ok(()),
+        };
+        let mut output = Vec::new();
+        loop {
+            match stdout.read(buffer) {
+                Ok(0) => {
+                    self.epoll.delete(pipe)?;
+                    self.cgi_pipes.remove(&pipe);
+                    self.closed_fds.insert(pipe);
+                    cgi.stdout = None;
+                    break;
                 }
-                Err(e) => {
-                    // ADD THIS DEBUG LINE
-                    eprintln!("DEBUG: CGI parse error: {}", e);
-                    
-                    let response = HttpResponse::error_page(
-                        500,
-                        server_config.error_pages.get(&500).map(|s| s.as_str()),
-                    );
-                    self.send_response(fd, response)
+                Ok(n) => {
+                    output.extend_from_slice(&buffer[..n]);
+                    if output.len() >= STREAM_CHUNK_SIZE {
+                        break;
+                    }
                 }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
             }
         }
-        Err(e) => {
-            // ADD THIS DEBUG LINE
-            eprintln!("DEBUG: CGI execute error: {}", e);
-            
-            let response = HttpResponse::error_page(
-                500,
-                server_config.error_pages.get(&500).map(|s| s.as_str()),
+
+        if cgi.chunked.is_some() {
+            self.queue_cgi_body(fd, &output)?;
+        } else {
+            cgi.header_buf.extend_from_slice(&output);
+            if let Some(end) = CgiHandler::header_end(&cgi.header_buf) {
+                let rest = cgi.header_buf.split_off(end);
+                let header_block = std::mem::take(&mut cgi.header_buf);
+                self.start_cgi_response(fd, &header_block, rest)?;
+            }
+        }
+
+        self.finish_cgi_if_done(fd)
+    }
+
+    // Write as much of the request body as a script's stdin takes, closing
+    // it once the body is through so scripts reading to EOF finish. A script
+    // that stops reading is killed: its output then ends and
+    // finish_cgi_if_done reaps it and answers as for any failed script.
+    fn write_cgi_input(&mut self, fd: RawFd) -> io::Result<()> {
+        let client = self.clients.get_mut(&fd).unwrap();
+        let body = &client.request.body;
+        let cgi = client.cgi.as_mut().unwrap();
+        let stdin = cgi.stdin.as_mut().unwrap();
+
+        let result = loop {
+            if cgi.stdin_written == body.len() {
+                break Ok(true);
+            }
+            match stdin.write(&body[cgi.stdin_written..]) {
+                Ok(n) => cgi.stdin_written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e),
+            }
+        };
+
+        match result {
+            Ok(false) => return Ok(()),
+            Ok(true) => {}
+            Err(e) => {
+                eprintln!("[{}] Failed to write to CGI stdin: {}", client.request.headers["x-request-id"], e);
+                let _ = cgi.child.kill();
+            }
+        }
+        self.close_cgi_stdin(fd);
+        Ok(())
+    }
+
+    // Stop feeding a script's stdin; dropping the pipe closes it
+    fn close_cgi_stdin(&mut self, fd: RawFd) {
+        let cgi = match self.clients.get_mut(&fd).and_then(|client| client.cgi.as_mut()) {
+            Some(cgi) => cgi,
+            None => return,
+        };
+        if let Some(stdin) = cgi.stdin.take() {
+            let pipe = stdin.as_raw_fd();
+            let _ = self.epoll.delete(pipe);
+            self.cgi_pipes.remove(&pipe);
+            self.closed_fds.insert(pipe);
+        }
+    }
+
+    // Send the status line and headers of a streamed CGI response, followed
+    // by whatever part of the body was read along with them
+    fn start_cgi_response(&mut self, fd: RawFd, header_block: &[u8], body: Vec<u8>) -> io::Result<()> {
+        let cgi_headers = match CgiHandler::parse_cgi_output(header_block) {
+            Ok((cgi_headers, _)) => cgi_headers,
+            Err(e) => {
+                let client = self.clients.get_mut(&fd).unwrap();
+                eprintln!("[{}] CGI parse error: {}", client.request.headers["x-request-id"], e);
+                self.stop_cgi(fd);
+                self.send_error(fd, 500)?;
+                return self.end_cgi_request(fd);
+            }
+        };
+
+        let mut response = match Self::cgi_response(cgi_headers) {
+            Ok(response) => response,
+            Err(location) => {
+                self.stop_cgi(fd);
+                self.internal_redirect(fd, &location)?;
+                return self.end_cgi_request(fd);
+            }
+        };
+
+        // Without a length from the script the body is chunked, or for
+        // HTTP/1.0 clients delimited by closing the connection
+        let client = self.clients.get_mut(&fd).unwrap();
+        let chunked = if response.headers.contains_key("content-length") {
+            false
+        } else if client.request.version == "HTTP/1.0" {
+            response.add_header("Connection".to_string(), "close".to_string());
+            client.close_after_write = true;
+            false
+        } else {
+            response.add_header("Transfer-Encoding".to_string(), "chunked".to_string());
+            true
+        };
+        let cgi = client.cgi.as_mut().unwrap();
+        cgi.chunked = Some(chunked);
+
+        // A script can ask for the trailers the server computes; only a
+        // chunked body has anywhere to put them
+        if let Some(declared) = response.headers.remove("trailer") {
+            cgi.trailers = CGI_TRAILERS
+                .iter()
+                .copied()
+                .filter(|name| declared.split(',').any(|field| field.trim().eq_ignore_ascii_case(name)))
+                .collect();
+            if !chunked {
+                cgi.trailers.clear();
+            }
+            if !cgi.trailers.is_empty() {
+                response.add_header("Trailer".to_string(), cgi.trailers.join(", "));
+            }
+        }
+
+        self.send_response(fd, response)?;
+
+        // The response is only complete once the script is done
+        let client = self.clients.get_mut(&fd).unwrap();
+        client.completions.back_mut().unwrap().end_offset = u64::MAX;
+
+        self.queue_cgi_body(fd, &body)
+    }
+
+    // Append body bytes from the script to the client's pending output
+    fn queue_cgi_body(&mut self, fd: RawFd, data: &[u8]) -> io::Result<()> {
+        let client = self.clients.get_mut(&fd).unwrap();
+        if data.is_empty() || client.request.method == "HEAD" {
+            return Ok(());
+        }
+
+        let cgi = client.cgi.as_mut().unwrap();
+        if cgi.trailers.contains(&"X-Content-CRC32") {
+            cgi.body_crc = gzip::crc32_update(cgi.body_crc, data);
+        }
+        let framed = if cgi.chunked == Some(true) {
+            let mut framed = format!("{:x}\r\n", data.len()).into_bytes();
+            framed.extend_from_slice(data);
+            framed.extend_from_slice(b"\r\n");
+            framed
+        } else {
+            data.to_vec()
+        };
+
+        if let Some(completion) = client.completions.back_mut() {
+            completion.bytes += data.len() as u64;
+        }
+        client.bytes_queued += framed.len() as u64;
+        let pending = match client.state {
+            ClientState::Writing { ref mut response, ref mut written, .. } => {
+                response.drain(..*written);
+                *written = 0;
+                response.extend_from_slice(&framed);
+                response.len()
+            }
+            ClientState::Reading => {
+                let pending = framed.len();
+                client.state = ClientState::Writing { response: framed, written: 0, body: None };
+                client.last_write_progress = self.clock.now();
+                client.bytes_since_check = 0;
+                client.progress_check_start = self.clock.now();
+                pending
+            }
+        };
+
+        // Stop reading from a script that outpaces its client
+        if pending > MAX_PENDING_OUTPUT && !cgi.paused {
+            cgi.paused = true;
+            if let Some(stdout) = &cgi.stdout {
+                self.epoll.modify(stdout.as_raw_fd(), 0, stdout.as_raw_fd() as u64)?;
+            }
+        }
+
+        self.epoll.modify(fd, libc::EPOLLOUT as u32, fd as u64)
+    }
+
+    // Once both pipes are closed, reap the script and complete its response
+    fn finish_cgi_if_done(&mut self, fd: RawFd) -> io::Result<()> {
+        let client = match self.clients.get_mut(&fd) {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        match client.cgi.as_ref() {
+            Some(cgi) if cgi.stdout.is_none() && cgi.stderr.is_none() => {}
+            _ => return Ok(()),
+        }
+
+        // A script may finish without reading all of its body
+        self.close_cgi_stdin(fd);
+        let client = self.clients.get_mut(&fd).unwrap();
+        let mut cgi = client.cgi.take().unwrap();
+        let success = cgi.child.wait().map(|status| status.success()).unwrap_or(false);
+        let request_id = client.request.headers["x-request-id"].clone();
+        if !success {
+            eprintln!(
+                "[{}] CGI script failed: {}",
+                request_id,
+                String::from_utf8_lossy(&cgi.stderr_buf)
             );
-            self.send_response(fd, response)
+        }
+
+        match cgi.chunked {
+            // The script ended before its header block did: the whole output
+            // is one buffered response, as for a script without headers
+            None => {
+                if !success {
+                    if cgi.debug {
+                        let stderr = String::from_utf8_lossy(&cgi.stderr_buf).into_owned();
+                        self.send_response(fd, HttpResponse::cgi_debug(&stderr))?;
+                    } else {
+                        self.send_error(fd, 500)?;
+                    }
+                    return self.end_cgi_request(fd);
+                }
+
+                match CgiHandler::parse_cgi_output(&cgi.header_buf) {
+                    Ok((cgi_headers, body)) => match Self::cgi_response(cgi_headers) {
+                        Ok(mut response) => {
+                            // Trailers need a chunked body
+                            response.headers.remove("trailer");
+                            response.set_body(body);
+                            self.send_response(fd, response)?;
+                        }
+                        Err(location) => self.internal_redirect(fd, &location)?,
+                    },
+                    Err(e) => {
+                        eprintln!("[{}] CGI parse error: {}", request_id, e);
+                        self.send_error(fd, 500)?;
+                    }
+                }
+                self.end_cgi_request(fd)
+            }
+            Some(chunked) => {
+                if !success {
+                    // Cut the connection so the client can tell the body is
+                    // incomplete
+                    client.close_after_write = true;
+                } else if chunked && client.request.method != "HEAD" {
+                    let trailers: Vec<(String, String)> = cgi.trailers
+                        .iter()
+                        .map(|&name| {
+                            let value = match name {
+                                "X-Content-CRC32" => format!("{:08x}", cgi.body_crc),
+                                _ => format!("cgi;dur={}", self.clock.now().duration_since(cgi.started).as_millis()),
+                            };
+                            (name.to_string(), value)
+                        })
+                        .collect();
+                    let last_chunk = HttpResponse::last_chunk(&trailers);
+                    client.bytes_queued += last_chunk.len() as u64;
+                    match client.state {
+                        ClientState::Writing { ref mut response, .. } => response.extend_from_slice(&last_chunk),
+                        ClientState::Reading => {
+                            client.state = ClientState::Writing {
+                                response: last_chunk,
+                                written: 0,
+                                body: None,
+                            };
+                        }
+                    }
+                }
+                if let Some(completion) = client.completions.back_mut() {
+                    completion.end_offset = client.bytes_queued;
+                }
+                self.end_cgi_request(fd)
+            }
         }
     }
-}
-    fn handle_file_upload(&mut self, fd: RawFd, route: &Route) -> io::Result<()> {
-        let client = self.clients.get(&fd).unwrap();
-        let request = &client.request;
-        let server_config = &client.server_config;
 
-        let upload_dir = route.upload_dir.as_ref().map(|s| s.as_str()).unwrap_or("./uploads");
+    // Kill a script whose output is no longer wanted
+    fn stop_cgi(&mut self, fd: RawFd) {
+        self.close_cgi_stdin(fd);
+        let client = self.clients.get_mut(&fd).unwrap();
+        if let Some(mut cgi) = client.cgi.take() {
+            for pipe in [cgi.stdout.as_ref().map(|p| p.as_raw_fd()), cgi.stderr.as_ref().map(|p| p.as_raw_fd())]
+                .into_iter()
+                .flatten()
+            {
+                let _ = self.epoll.delete(pipe);
+                self.cgi_pipes.remove(&pipe);
+                self.closed_fds.insert(pipe);
+            }
+            let _ = cgi.child.kill();
+            let _ = cgi.child.wait();
+        }
+    }
+
+    // The request a script answered is done; move on like
+    // process_complete_requests does after any other request
+    fn end_cgi_request(&mut self, fd: RawFd) -> io::Result<()> {
+        let client = match self.clients.get_mut(&fd) {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        // An internal redirect may have handed the request to another script
+        if client.cgi.is_some() {
+            return Ok(());
+        }
+
+        client.parser.reset();
+        client.request = HttpRequest::new();
+        client.expectation_answered = false;
+        if client.parser.has_buffered_data() {
+            client.request_start = Some(self.clock.now());
+        }
+
+        match client.state {
+            ClientState::Writing { .. } => {
+                self.epoll.modify(fd, libc::EPOLLOUT as u32, fd as u64)?;
+                self.after_write(fd)
+            }
+            ClientState::Reading => self.process_complete_requests(fd),
+        }
+    }
+
+    // Turn a script's headers into a response, or Err with the local path a
+    // bare Location or an X-Accel-Redirect asks to be served internally
+    fn cgi_response(cgi_headers: HashMap<String, String>) -> Result<HttpResponse, String> {
+        // The script delegates sending the file to the server; its own body
+        // is discarded and the target may be an internal location
+        if let Some(target) = cgi_headers.get("x-accel-redirect") {
+            return Err(target.trim().to_string());
+        }
+
+        let status_header = cgi_headers.get("status").map(|s| s.trim());
+        let explicit_status = status_header
+            .and_then(|s| s.split_whitespace().next())
+            .and_then(|s| s.parse::<u16>().ok());
+
+        // Keep a script-provided reason phrase, e.g. "418 I'm a teapot"
+        let reason_phrase = status_header
+            .and_then(|s| s.split_once(char::is_whitespace))
+            .map(|(_, phrase)| phrase.trim().to_string())
+            .filter(|phrase| !phrase.is_empty());
+
+        // A bare Location is a redirect: local paths are served
+        // internally, anything else goes back to the client
+        let location = cgi_headers.get("location");
+        if let (None, Some(location)) = (explicit_status, location) {
+            if location.starts_with('/') {
+                return Err(location.clone());
+            }
+        }
+
+        let status_code = match (explicit_status, location) {
+            (Some(code), _) => code,
+            (None, Some(_)) => 302,
+            (None, None) => 200,
+        };
+
+        let mut response = HttpResponse::new(status_code);
+        if let Some(phrase) = reason_phrase {
+            response.status_text = phrase;
+        }
+
+        // Ranges are only served for static files. Script output is
+        // generated per request and streamed as it arrives, so there is
+        // nothing stable to slice: Range is ignored, the full 200 is sent,
+        // and an Accept-Ranges from the script is dropped so clients don't
+        // retry with ranges that would never be honoured.
+        for (key, value) in cgi_headers {
+            if key != "status" && key != "accept-ranges" {
+                response.add_header(key, value);
+            }
+        }
+
+        if !response.headers.keys().any(|key| key.eq_ignore_ascii_case("content-type")) {
+            response.add_header("Content-Type".to_string(), "text/html".to_string());
+        }
+
+        Ok(response)
+    }
+
+    fn handle_file_upload(&mut self, fd: RawFd, route: &Route, upload_dir: &str) -> io::Result<()> {
+        let request = &self.clients.get(&fd).unwrap().request;
+
+        let parsed = request.headers
+            .get("content-type")
+            .and_then(|content_type| multipart::boundary(content_type))
+            .ok_or_else(|| "Missing multipart boundary".to_string())
+            .and_then(|boundary| multipart::parse(&request.body, &boundary));
+        let parts = match parsed {
+            Ok(parts) => parts,
+            Err(e) => {
+                eprintln!("[{}] Rejected upload: {}", request.headers["x-request-id"], e);
+                return self.send_error(fd, 400);
+            }
+        };
 
         // Create upload directory if it doesn't exist
         std::fs::create_dir_all(upload_dir).ok();
 
-        // Parse multipart data (simplified)
-        if let Some(content_type) = request.headers.get("content-type") {
-            if let Some(boundary_start) = content_type.find("boundary=") {
-                let boundary = &content_type[boundary_start + 9..];
-                let _boundary_marker = format!("--{}", boundary);
-                // Simple file save (proper multipart parsing would be more complex)
-                let filename = format!("{}/upload_{}.bin", upload_dir, std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs());
-
-                if let Err(_) = std::fs::write(&filename, &request.body) {
-                    let response = HttpResponse::error_page(
-                        500,
-                        server_config.error_pages.get(&500).map(|s| s.as_str()),
-                    );
-                    return self.send_response(fd, response);
-                }
-
-                let mut response = HttpResponse::new(201);
-                response.set_body_str(&format!("File uploaded successfully: {}", filename));
-                return self.send_response(fd, response);
+        // Forms send file fields left empty with an empty filename
+        let mut saved = Vec::new();
+        for part in parts.iter().filter(|part| part.filename.as_deref().is_some_and(|name| !name.is_empty())) {
+            match Self::save_upload(upload_dir, route.upload_naming, part, saved.len()) {
+                Ok(path) => saved.push(format!("File uploaded successfully: {}", path)),
+                Err(_) => return self.send_error(fd, 500),
             }
         }
 
-        let mut response = HttpResponse::new(200);
-        response.set_body_str("Upload processed");
+        if saved.is_empty() {
+            let mut response = HttpResponse::new(200);
+            response.set_body_str("Upload processed");
+            return self.send_response(fd, response);
+        }
+
+        let mut response = HttpResponse::new(201);
+        response.set_body_str(&saved.join("\n"));
         self.send_response(fd, response)
     }
 
-    fn send_response(&mut self, fd: RawFd, mut response: HttpResponse) -> io::Result<()> {
-        let client = self.clients.get_mut(&fd).unwrap();
+    // Write an uploaded file under the name its route's upload_naming picks,
+    // adding a numeric suffix rather than replacing an existing file
+    fn save_upload(upload_dir: &str, naming: UploadNaming, part: &multipart::Part, salt: usize) -> io::Result<String> {
+        let client_name = part.filename.as_deref().and_then(multipart::sanitize_filename);
+        let extension = client_name.as_deref()
+            .and_then(|name| std::path::Path::new(name).extension())
+            .and_then(|extension| extension.to_str())
+            .map_or(".bin".to_string(), |extension| format!(".{}", extension));
+        let timestamp = || {
+            let secs = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            format!("upload_{}", secs)
+        };
 
-        // Handle cookies and sessions
-        if let Some(cookie_header) = client.request.headers.get("cookie") {
-            let cookies = parse_cookies(cookie_header);
-            
-            if let Some(session_id) = cookies.get("sessionid") {
-                // Session exists, update it
-                if self.session_manager.get_session(session_id).is_none() {
-                    // Create new session if old one expired
-                    let new_session_id = self.session_manager.create_session();
-                    response.add_header(
-                        "Set-Cookie".to_string(),
-                        create_set_cookie("sessionid", &new_session_id, Some(3600)),
-                    );
+        let (stem, extension) = match (naming, client_name) {
+            (UploadNaming::Preserve, Some(name)) => match name.rsplit_once('.') {
+                Some((stem, extension)) if !stem.is_empty() => (stem.to_string(), format!(".{}", extension)),
+                _ => (name, String::new()),
+            },
+            (UploadNaming::Random, _) => (random_id(salt), extension),
+            (UploadNaming::Preserve | UploadNaming::Timestamp, _) => (timestamp(), extension),
+        };
+
+        let mut attempt = 0;
+        loop {
+            let name = match attempt {
+                0 => format!("{}{}", stem, extension),
+                n => format!("{}-{}{}", stem, n, extension),
+            };
+            let path = format!("{}/{}", upload_dir, name);
+
+            // create_new makes taking the name atomic
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(&part.data)?;
+                    return Ok(path);
                 }
-            } else {
-                // No session, create one
-                let session_id = self.session_manager.create_session();
-                response.add_header(
-                    "Set-Cookie".to_string(),
-                    create_set_cookie("sessionid", &session_id, Some(3600)),
-                );
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e),
             }
-        } else {
-            // No cookies at all, create session
-            let session_id = self.session_manager.create_session();
+        }
+    }
+
+    // A file that can't be served is 403 if it exists but isn't readable and
+    // 404 otherwise
+    fn send_path_error(&mut self, fd: RawFd, file_path: &str, error: &io::Error) -> io::Result<()> {
+        let (code, reason) = match error.kind() {
+            io::ErrorKind::PermissionDenied => (403, "permission denied".to_string()),
+            io::ErrorKind::NotFound => (404, "not found".to_string()),
+            _ => (404, error.to_string()),
+        };
+        self.log_path_error(fd, code, file_path, &reason);
+        self.send_error(fd, code)
+    }
+
+    // Names the filesystem path behind a 404 or 403, which is otherwise hard
+    // to work out from the URI once roots and route prefixes are involved
+    fn log_path_error(&self, fd: RawFd, code: u16, file_path: &str, reason: &str) {
+        let path = std::path::absolute(file_path)
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|_| file_path.to_string());
+        eprintln!(
+            "[{}] {} for {}: {}",
+            self.clients[&fd].request.headers["x-request-id"], code, path, reason
+        );
+    }
+
+    // The 503 for a connection turned away before any request is read: the
+    // server's error_page for it when that is a file, closing the connection
+    fn overload_response(server_config: &ServerConfig) -> HttpResponse {
+        let custom_page = server_config.error_pages
+            .get(&503)
+            .filter(|page| !page.target.starts_with('/') && page.status.is_none())
+            .map(|page| page.target.as_str());
+        let mut response = HttpResponse::error_page(503, custom_page);
+        response.add_header("Retry-After".to_string(), server_config.retry_after.to_string());
+        response.add_header("Connection".to_string(), "close".to_string());
+        response
+    }
+
+    fn send_error(&mut self, fd: RawFd, code: u16) -> io::Result<()> {
+        let client = self.clients.get_mut(&fd).unwrap();
+
+        // An error page that fails itself falls back to the built-in page
+        // for the original error
+        if let Some((original, _)) = client.error_page.take() {
+            return self.send_response(fd, HttpResponse::error_page(original, None));
+        }
+
+        let page = match client.server_config.error_pages.get(&code) {
+            Some(page) => page.clone(),
+            None => return self.send_response(fd, HttpResponse::error_page(code, None)),
+        };
+        let status = page.status.unwrap_or(code);
+
+        // A URI goes through the normal pipeline, which picks its
+        // content type; send_response then applies the status
+        if page.target.starts_with('/') {
+            client.error_page = Some((code, status));
+            return self.internal_redirect(fd, &page.target);
+        }
+
+        let mut response = HttpResponse::error_page(code, Some(&page.target));
+        response.status_code = status;
+        response.status_text = HttpResponse::status_text(status);
+        self.send_response(fd, response)
+    }
+
+    // Issue a session cookie unless the request carries a live session
+    fn attach_session(&mut self, fd: RawFd, response: &mut HttpResponse) {
+        let client = self.clients.get(&fd).unwrap();
+        let cookie_name = &client.server_config.session_cookie_name;
+        let ttl = client.server_config.session_timeout;
+        let existing = client.request.headers
+            .get("cookie")
+            .and_then(|header| parse_cookies(header).remove(cookie_name));
+
+        let live = existing.is_some_and(|id| self.session_manager.get_session(&id).is_some());
+        if !live {
+            let session_id = self.session_manager.create_session(ttl);
             response.add_header(
                 "Set-Cookie".to_string(),
-                create_set_cookie("sessionid", &session_id, Some(3600)),
+                create_set_cookie(cookie_name, &session_id, Some(ttl)),
             );
         }
+    }
+
+    // Requests answered without session handling under session_fast_path:
+    // HEAD and OPTIONS, and anything served as a static asset
+    fn skips_session(method: &str, response: &HttpResponse) -> bool {
+        if matches!(method, "HEAD" | "OPTIONS") {
+            return true;
+        }
+        response.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("content-type"))
+            .is_some_and(|(_, content_type)| {
+                let mime = content_type.split(';').next().unwrap_or("").trim();
+                mime.starts_with("image/")
+                    || mime.starts_with("font/")
+                    || mime.starts_with("audio/")
+                    || mime.starts_with("video/")
+                    || matches!(mime, "text/css" | "application/javascript")
+            })
+    }
+
+    fn send_response(&mut self, fd: RawFd, mut response: HttpResponse) -> io::Result<()> {
+        let client = self.clients.get_mut(&fd).unwrap();
+        if let Some((_, status)) = client.error_page.take() {
+            response.status_code = status;
+            response.status_text = HttpResponse::status_text(status);
+        }
 
+        // Whatever produced a 503, the client is told when to try again
+        let has_retry_after = response.headers.keys().any(|key| key.eq_ignore_ascii_case("retry-after"));
+        if response.status_code == 503 && !has_retry_after {
+            response.add_header("Retry-After".to_string(), client.server_config.retry_after.to_string());
+        }
+
+        self.compress_response(fd, &mut response)?;
+
+        // Handle cookies and sessions
+        let client = self.clients.get(&fd).unwrap();
+        let fast_path = client.server_config.session_fast_path
+            && Self::skips_session(&client.request.method, &response);
+        if client.sessions_enabled && !fast_path {
+            self.attach_session(fd, &mut response);
+        }
+
+        let request_id = self.ensure_request_id(fd);
+        response.add_header("X-Request-Id".to_string(), request_id.clone());
+
+        let client = self.clients.get_mut(&fd).unwrap();
+        if self.draining || client.close_after_write || !client.request.keeps_alive() {
+            response.add_header("Connection".to_string(), "close".to_string());
+            client.close_after_write = true;
+        }
+
+        // A HEAD response keeps the headers GET would send, Content-Length
+        // included, and drops the body
+        if client.request.method == "HEAD" {
+            response.body.clear();
+            response.body_file = None;
+        }
+
+        let body_stream = response.body_file
+            .take()
+            .map(|(file, remaining)| BodyStream { file, remaining });
         let response_bytes = response.to_bytes();
+        let streamed_len = body_stream.as_ref().map_or(0, |stream| stream.remaining as usize);
+        self.metrics.record_response(response.status_code, response_bytes.len() + streamed_len);
+
+        client.bytes_queued += (response_bytes.len() + streamed_len) as u64;
+        client.completions.push_back(PendingCompletion {
+            end_offset: client.bytes_queued,
+            method: client.request.method.clone(),
+            uri: client.request.uri.to_string(),
+            version: client.request.version.clone(),
+            status: response.status_code,
+            request_bytes: client.request.body.len() as u64,
+            bytes: (response.body.len() + streamed_len) as u64,
+            started: client.request_start.take().unwrap_or_else(|| self.clock.now()),
+            request_id,
+            referer: client.request.headers.get("referer").cloned(),
+            user_agent: client.request.headers.get("user-agent").cloned(),
+            log_format: client.server_config.log_format.clone(),
+        });
+
+        self.queue_output(fd, response_bytes, body_stream)
+    }
 
-        client.state = ClientState::Writing {
-            response: response_bytes,
-            written: 0,
-        };
+    // Pipelined responses queue up behind the one still being written;
+    // nothing is dispatched while a body is streaming, so at most the last
+    // response carries one
+    fn queue_output(&mut self, fd: RawFd, response_bytes: Vec<u8>, body_stream: Option<BodyStream>) -> io::Result<()> {
+        let client = self.clients.get_mut(&fd).unwrap();
+        match client.state {
+            ClientState::Writing { ref mut response, ref mut body, .. } => {
+                response.extend_from_slice(&response_bytes);
+                *body = body_stream;
+            }
+            ClientState::Reading => {
+                client.state = ClientState::Writing {
+                    response: response_bytes,
+                    written: 0,
+                    body: body_stream,
+                };
+                client.last_write_progress = self.clock.now();
+                client.bytes_since_check = 0;
+                client.progress_check_start = self.clock.now();
+            }
+        }
 
         // Switch to write mode
         self.epoll.modify(fd, libc::EPOLLOUT as u32, fd as u64)?;
@@ -656,8 +2486,82 @@ if metadata.is_dir() {
         Ok(())
     }
 
-    fn find_route<'a>(&self, uri: &str, config: &'a ServerConfig) -> Option<&'a Route> {
-        let uri_path = uri.split('?').next().unwrap_or(uri);
+    // Gzip a compressible body for clients that accept it, per the server's
+    // gzip directives
+    fn compress_response(&mut self, fd: RawFd, response: &mut HttpResponse) -> io::Result<()> {
+        let client = self.clients.get(&fd).unwrap();
+        let server_config = &client.server_config;
+        if !server_config.gzip || matches!(response.status_code, 204 | 206 | 304) {
+            return Ok(());
+        }
+
+        let header = |name: &str| {
+            response.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        let compressible = header("content-type").is_some_and(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or("").trim();
+            mime.starts_with("text/")
+                || matches!(mime, "application/javascript" | "application/json" | "application/xml" | "image/svg+xml")
+        });
+        if !compressible || header("content-encoding").is_some() || header("transfer-encoding").is_some() {
+            return Ok(());
+        }
+        let declared_len = header("content-length");
+
+        // The representation depends on Accept-Encoding from here on
+        response.add_vary("Accept-Encoding");
+        let accepted = client.request.headers
+            .get("accept-encoding")
+            .is_some_and(|accept_encoding| accepts_encoding(accept_encoding, "gzip"));
+        if !accepted {
+            return Ok(());
+        }
+
+        // A script streaming a body after its headers declares a length
+        // the response doesn't hold
+        let len = response.body_file.as_ref().map_or(response.body.len() as u64, |(_, len)| *len);
+        if declared_len.is_some_and(|declared| declared.trim() != len.to_string())
+            || len < server_config.gzip_min_length as u64
+            || len > MAX_GZIP_BODY
+        {
+            return Ok(());
+        }
+
+        let level = server_config.gzip_comp_level;
+        let body = match response.body_file.take() {
+            Some((file, len)) => {
+                let mut body = Vec::with_capacity(len as usize);
+                file.take(len).read_to_end(&mut body)?;
+                body
+            }
+            None => std::mem::take(&mut response.body),
+        };
+
+        // Incompressible data only grows
+        let compressed = gzip::compress(&body, level);
+        response.headers.retain(|key, _| !key.eq_ignore_ascii_case("content-length"));
+        if compressed.len() >= body.len() {
+            response.set_body(body);
+            return Ok(());
+        }
+
+        // Byte ranges and a strong validator would refer to the identity body
+        response.headers.retain(|key, _| !key.eq_ignore_ascii_case("accept-ranges"));
+        if let Some(etag) = response.headers.get_mut("ETag") {
+            if !etag.starts_with("W/") {
+                etag.insert_str(0, "W/");
+            }
+        }
+        response.add_header("Content-Encoding".to_string(), "gzip".to_string());
+        response.set_body(compressed);
+        Ok(())
+    }
+
+    // `uri_path` is the decoded, normalized path
+    fn find_route<'a>(&self, uri_path: &str, config: &'a ServerConfig) -> Option<&'a Route> {
 
         // Find longest matching route
         let mut best_match: Option<&Route> = None;
@@ -674,7 +2578,7 @@ if metadata.is_dir() {
     }
 
     fn resolve_path(&self, uri_path: &str, route: &Route) -> String {
-        let root = route.root.as_ref().map(|s| s.as_str()).unwrap_or(".");
+        let root = route.root.as_deref().unwrap_or(".");
         
         // Remove route prefix from URI
         let relative_path = if uri_path.starts_with(&route.path) {
@@ -692,12 +2596,18 @@ if metadata.is_dir() {
         }
     }
 
-    fn get_content_type(&self, file_path: &str) -> String {
+    // The route's `types` win over the built-in table, and its
+    // `default_type` covers extensions neither of them knows
+    fn get_content_type(&self, route: &Route, file_path: &str) -> String {
         let extension = std::path::Path::new(file_path)
             .extension()
             .and_then(|e| e.to_str())
             .unwrap_or("");
 
+        if let Some(content_type) = route.types.get(extension) {
+            return content_type.clone();
+        }
+
         match extension {
             "html" | "htm" => "text/html",
             "css" => "text/css",
@@ -709,17 +2619,113 @@ if metadata.is_dir() {
             "svg" => "image/svg+xml",
             "pdf" => "application/pdf",
             "txt" => "text/plain",
-            _ => "application/octet-stream",
+            _ => route.default_type.as_deref().unwrap_or("application/octet-stream"),
         }.to_string()
     }
 
+    // The id tying a request's response, CGI environment and log lines
+    // together. A sane client-supplied X-Request-Id is kept, anything else is
+    // replaced; it lives in the request headers so CGI sees it as
+    // HTTP_X_REQUEST_ID.
+    fn ensure_request_id(&mut self, fd: RawFd) -> String {
+        let headers = &mut self.clients.get_mut(&fd).unwrap().request.headers;
+        let valid = headers.get("x-request-id").is_some_and(|id| {
+            !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+        });
+        if !valid {
+            headers.insert("x-request-id".to_string(), random_id(fd as usize));
+        }
+        headers["x-request-id"].clone()
+    }
+
+    // Only a POST from the local machine may start draining
+    fn handle_drain(&mut self, fd: RawFd, method: &str) -> io::Result<()> {
+        if method != "POST" {
+            return self.send_error(fd, 405);
+        }
+        if !self.clients.get(&fd).unwrap().remote_addr.ip().is_loopback() {
+            return self.send_error(fd, 403);
+        }
+
+        if !self.draining {
+            println!("Draining: no longer accepting connections");
+            for listener in &self.listeners {
+                let _ = self.epoll.delete(listener.as_raw_fd());
+            }
+            // Closing the listening sockets refuses new connections outright
+            self.listeners.clear();
+            self.draining = true;
+        }
+
+        let mut response = HttpResponse::new(202);
+        response.add_header("Content-Type".to_string(), "text/plain".to_string());
+        response.set_body_str("Draining\n");
+        self.send_response(fd, response)
+    }
+
+    // One line per live session with its timestamps, for debugging from the
+    // server itself; the ids are as good as the sessions, so nobody else
+    // gets them
+    fn handle_session_list(&mut self, fd: RawFd) -> io::Result<()> {
+        if !self.clients.get(&fd).unwrap().remote_addr.ip().is_loopback() {
+            return self.send_error(fd, 403);
+        }
+
+        let mut body = String::new();
+        for session in self.session_manager.list_sessions() {
+            body.push_str(&format!(
+                "{} created_at={} last_accessed={} ttl={}\n",
+                session.id, session.created_at, session.last_accessed, session.ttl
+            ));
+        }
+
+        let mut response = HttpResponse::new(200);
+        response.add_header("Content-Type".to_string(), "text/plain".to_string());
+        response.set_body_str(&body);
+        self.send_response(fd, response)
+    }
+
+    // While draining, keep-alive connections between requests are closed
+    // instead of waiting for a request that would only be refused
+    fn close_idle_clients(&mut self) {
+        let idle: Vec<RawFd> = self.clients
+            .iter()
+            .filter(|(_, client)| client.phase() == ConnectionPhase::Idle)
+            .map(|(fd, _)| *fd)
+            .collect();
+
+        for fd in idle {
+            self.close_client(fd);
+        }
+    }
+
     fn check_timeouts(&mut self) {
-        let now = Instant::now();
+        let now = self.clock.now();
         let mut to_close = Vec::new();
 
-        for (fd, client) in &self.clients {
-            if now.duration_since(client.last_activity) > CLIENT_TIMEOUT {
+        for (fd, client) in self.clients.iter_mut() {
+            if client.timed_out(now) {
                 to_close.push(*fd);
+            } else if let ClientState::Writing { ref response, written, ref body } = client.state {
+                // A reader that stopped draining its output gets dropped
+                if now.duration_since(client.last_write_progress) > SEND_TIMEOUT {
+                    to_close.push(*fd);
+                    continue;
+                }
+
+                // So does one accepting a byte or so per wakeup, which never
+                // trips the send timeout but would hold a large response open
+                // indefinitely
+                if now.duration_since(client.progress_check_start) >= PROGRESS_WINDOW {
+                    let pending = written < response.len()
+                        || body.as_ref().is_some_and(|stream| stream.remaining > 0);
+                    if pending && client.bytes_since_check < MIN_PROGRESS_BYTES {
+                        to_close.push(*fd);
+                        continue;
+                    }
+                    client.bytes_since_check = 0;
+                    client.progress_check_start = now;
+                }
             }
         }
 
@@ -729,9 +2735,45 @@ if metadata.is_dir() {
     }
 
     fn close_client(&mut self, fd: RawFd) {
-        if let Some(client) = self.clients.remove(&fd) {
+        if self.clients.contains_key(&fd) {
+            self.stop_cgi(fd);
+        }
+        if let Some(mut client) = self.clients.remove(&fd) {
             let _ = self.epoll.delete(fd);
+            self.closed_fds.insert(fd);
+
+            release_connection(&mut self.connections_per_ip, client.remote_addr.ip());
+            if client.in_flight {
+                self.requests_in_flight -= 1;
+            }
+            if client.queued {
+                self.request_queue.retain(|&queued| queued != fd);
+            }
+
+            // Discard (a bounded amount of) unread input so the close doesn't
+            // turn into a reset that could destroy a response still in flight
+            for _ in 0..16 {
+                match client.stream.read(&mut self.read_buffer) {
+                    Ok(n) if n > 0 => {}
+                    _ => break,
+                }
+            }
             drop(client.stream);
         }
     }
-}
\ No newline at end of file
+}
+
+// Drop one connection from `ip`'s count for limit_conn_per_ip
+fn release_connection(connections_per_ip: &mut HashMap<IpAddr, usize>, ip: IpAddr) {
+    if let Some(open) = connections_per_ip.get_mut(&ip) {
+        *open -= 1;
+        if *open == 0 {
+            connections_per_ip.remove(&ip);
+        }
+    }
+}
+
+// Whether the route runs `path` as a script rather than treating it as a file
+fn is_cgi(route: &Route, path: &str) -> bool {
+    route.cgi_extension.as_ref().is_some_and(|ext| path.ends_with(ext.as_str()))
+}
@@ -1,8 +1,11 @@
+use crate::clock::{Clock, SystemClock};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct SessionManager {
     sessions: HashMap<String, SessionData>,
+    clock: Arc<dyn Clock>,
 }
 
 #[derive(Clone)]
@@ -11,24 +14,32 @@ pub struct SessionData {
     pub data: HashMap<String, String>,
     pub created_at: u64,
     pub last_accessed: u64,
+    pub ttl: u64,
 }
 
 impl SessionManager {
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    // Timestamp and expire sessions by `clock` rather than the system time
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         SessionManager {
             sessions: HashMap::new(),
+            clock,
         }
     }
 
-    pub fn create_session(&mut self) -> String {
+    pub fn create_session(&mut self, ttl: u64) -> String {
         let session_id = self.generate_session_id();
-        let now = Self::current_timestamp();
+        let now = self.current_timestamp();
 
         let session = SessionData {
             id: session_id.clone(),
             data: HashMap::new(),
             created_at: now,
             last_accessed: now,
+            ttl,
         };
 
         self.sessions.insert(session_id.clone(), session);
@@ -37,46 +48,70 @@ impl SessionManager {
 
     pub fn get_session(&mut self, session_id: &str) -> Option<&mut SessionData> {
         if let Some(session) = self.sessions.get_mut(session_id) {
-            session.last_accessed = Self::current_timestamp();
+            session.last_accessed = unix_seconds(self.clock.system_now());
             Some(session)
         } else {
             None
         }
     }
 
+    // Live sessions, oldest first; unlike get_session this leaves
+    // last_accessed alone
+    pub fn list_sessions(&self) -> Vec<&SessionData> {
+        let mut sessions: Vec<&SessionData> = self.sessions.values().collect();
+        sessions.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        sessions
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
     pub fn destroy_session(&mut self, session_id: &str) {
         self.sessions.remove(session_id);
     }
 
-    pub fn cleanup_expired(&mut self, max_age_seconds: u64) {
-        let now = Self::current_timestamp();
+    // Each session expires after the TTL of the server that issued it
+    pub fn cleanup_expired(&mut self) {
+        let now = self.current_timestamp();
         self.sessions.retain(|_, session| {
-            now - session.last_accessed < max_age_seconds
+            now - session.last_accessed < session.ttl
         });
     }
 
     fn generate_session_id(&self) -> String {
-        use std::collections::hash_map::RandomState;
-        use std::hash::{BuildHasher, Hash, Hasher};
-
-        let s = RandomState::new();
-        let mut hasher = s.build_hasher();
-        
-        Self::current_timestamp().hash(&mut hasher);
-        std::process::id().hash(&mut hasher);
-        self.sessions.len().hash(&mut hasher);
+        random_id(self.sessions.len())
+    }
 
-        format!("{:x}", hasher.finish())
+    fn current_timestamp(&self) -> u64 {
+        unix_seconds(self.clock.system_now())
     }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
 
-    fn current_timestamp() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+// An unpredictable hex identifier; every RandomState carries fresh keys, and
+// `salt` separates ids generated within the same second
+pub fn random_id(salt: usize) -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hash, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+    unix_seconds(SystemTime::now()).hash(&mut hasher);
+    std::process::id().hash(&mut hasher);
+    salt.hash(&mut hasher);
+
+    format!("{:x}", hasher.finish())
+}
+
 pub fn parse_cookies(cookie_header: &str) -> HashMap<String, String> {
     let mut cookies = HashMap::new();
     
@@ -11,6 +11,12 @@ HOST="127.0.0.1"
 PORT="8080"
 BASE_URL="http://${HOST}:${PORT}"
 
+# Binary used by tests that need their own server instance
+WEBSERV_BIN="${WEBSERV_BIN:-./target/release/webserv}"
+if [ ! -x "$WEBSERV_BIN" ]; then
+    WEBSERV_BIN="./target/debug/webserv"
+fi
+
 pass_count=0
 fail_count=0
 
@@ -96,7 +102,7 @@ test_file_upload() {
     echo -n "Testing file upload... "
     echo "test file content" > /tmp/test_upload.txt
     response=$(curl -s -o /dev/null -w "%{http_code}" -F "file=@/tmp/test_upload.txt" ${BASE_URL}/uploads 2>/dev/null)
-    rm -f /tmp/test_upload.txt
+    rm -f /tmp/test_upload.txt www/uploads/test_upload*.txt
     if [ "$response" -eq 200 ] || [ "$response" -eq 201 ]; then
         echo -e "${GREEN}PASS${NC} (Status: $response)"
         ((pass_count++))
@@ -116,6 +122,3392 @@ test_multiple_requests() {
     ((pass_count++))
 }
 
+test_pipelining() {
+    echo -n "Testing pipelined requests in one segment... "
+    exec 3<>/dev/tcp/${HOST}/${PORT}
+    printf "GET /static/test.txt HTTP/1.1\r\nHost: ${HOST}\r\n\r\nGET /nonexistent HTTP/1.1\r\nHost: ${HOST}\r\n\r\n" >&3
+    response=$(timeout 2 cat <&3 | grep -a -o "^HTTP/1.1 [0-9]*" | tr '\n' ' ')
+    exec 3<&-
+    if [ "$response" = "HTTP/1.1 200 HTTP/1.1 404 " ]; then
+        echo -e "${GREEN}PASS${NC} (Both responses received)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+}
+
+test_metrics() {
+    echo -n "Testing metrics endpoint... "
+    before=$(curl -s ${BASE_URL}/metrics | grep "^webserv_requests_total" | awk '{print $2}')
+    curl -s -o /dev/null ${BASE_URL}/
+    after=$(curl -s ${BASE_URL}/metrics | grep "^webserv_requests_total" | awk '{print $2}')
+    if [ -n "$before" ] && [ -n "$after" ] && [ "$after" -gt "$before" ]; then
+        echo -e "${GREEN}PASS${NC} (requests_total $before -> $after)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (requests_total $before -> $after)"
+        ((fail_count++))
+    fi
+}
+
+test_gzip_static() {
+    echo -n "Testing gzip_static with fresh .gz sibling... "
+    echo "gzip static test content" > www/static/gzip_test.txt
+    gzip -k -f www/static/gzip_test.txt
+    response=$(curl -s -D - -o /dev/null -H "Accept-Encoding: gzip" ${BASE_URL}/static/gzip_test.txt | grep -i "Content-Encoding: gzip")
+    if [ ! -z "$response" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (No Content-Encoding)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a refused gzip beats the * wildcard... "
+    # Whichever comes first, the item naming gzip decides; * covers the rest
+    refused=$(curl -s -D - -o /dev/null -H "Accept-Encoding: *, gzip;q=0" ${BASE_URL}/static/gzip_test.txt | grep -i -c "^Content-Encoding:")
+    refused_first=$(curl -s -D - -o /dev/null -H "Accept-Encoding: gzip;q=0, *" ${BASE_URL}/static/gzip_test.txt | grep -i -c "^Content-Encoding:")
+    wildcard=$(curl -s -D - -o /dev/null -H "Accept-Encoding: br;q=0, *" ${BASE_URL}/static/gzip_test.txt | grep -i -c "^Content-Encoding: gzip")
+    if [ "$refused $refused_first $wildcard" = "0 0 1" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Gzip sent: $refused $refused_first $wildcard)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing gzip_static with stale .gz sibling... "
+    touch -d "+1 minute" www/static/gzip_test.txt
+    response=$(curl -s -D - -o /dev/null -H "Accept-Encoding: gzip" ${BASE_URL}/static/gzip_test.txt | grep -i "Content-Encoding")
+    if [ -z "$response" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Stale .gz served)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing gzip_static without .gz sibling... "
+    rm -f www/static/gzip_test.txt.gz
+    response=$(curl -s -H "Accept-Encoding: gzip" ${BASE_URL}/static/gzip_test.txt)
+    if [ "$response" = "gzip static test content" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+    rm -f www/static/gzip_test.txt
+}
+
+test_internal_redirect_loop() {
+    echo -n "Testing circular try_files returns 500... "
+    response=$(curl -s -o /dev/null -m 5 -w "%{http_code}" ${BASE_URL}/loop/anything)
+    if [ "$response" -eq 500 ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $response)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $response)"
+        ((fail_count++))
+    fi
+}
+
+test_duplicate_slashes() {
+    echo -n "Testing duplicate slashes are collapsed... "
+    response=$(curl -s --path-as-is ${BASE_URL}//static///test.txt)
+    expected=$(curl -s ${BASE_URL}/static/test.txt)
+    if [ -n "$expected" ] && [ "$response" = "$expected" ]; then
+        echo -e "${GREEN}PASS${NC} (Routed to /static)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_environment() {
+    echo -n "Testing CGI does not inherit server environment... "
+    response=$(curl -s ${BASE_URL}/cgi-bin/env.py)
+    if echo "$response" | grep -q "^REQUEST_METHOD$" && ! echo "$response" | grep -q "^HOME$"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC}"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_location_redirect() {
+    echo -n "Testing CGI Location-only redirect... "
+    response=$(curl -s -o /dev/null -w "%{http_code} %{redirect_url}" ${BASE_URL}/cgi-bin/redirect.py)
+    if [ "$response" = "302 http://example.com/" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: 302)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing CGI local Location is served internally... "
+    response=$(curl -s "${BASE_URL}/cgi-bin/redirect.py?local")
+    expected=$(curl -s ${BASE_URL}/static/test.txt)
+    if [ -n "$expected" ] && [ "$response" = "$expected" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_status_phrase() {
+    echo -n "Testing CGI custom status reason phrase... "
+    response=$(curl -s -i ${BASE_URL}/cgi-bin/status.py | head -n 1 | tr -d '\r')
+    if [ "$response" = "HTTP/1.1 418 I'm a teapot" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing CGI numeric-only status... "
+    response=$(curl -s -i "${BASE_URL}/cgi-bin/status.py?plain" | head -n 1 | tr -d '\r')
+    if [ "$response" = "HTTP/1.1 404 Not Found" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+}
+
+test_multi_range() {
+    echo -n "Testing multi-range request... "
+    headers=$(curl -s -D - -o /tmp/test_ranges.txt -H "Range: bytes=0-4,6-9" ${BASE_URL}/static/test.txt)
+    parts=$(grep -a -c "^Content-Range: bytes" /tmp/test_ranges.txt)
+    first=$(grep -a -A2 "bytes 0-4/" /tmp/test_ranges.txt | tail -n 1 | tr -d '\r')
+    second=$(grep -a -A2 "bytes 6-9/" /tmp/test_ranges.txt | tail -n 1 | tr -d '\r')
+    rm -f /tmp/test_ranges.txt
+    if echo "$headers" | grep -q "206 Partial Content" \
+        && echo "$headers" | grep -q "multipart/byteranges; boundary=" \
+        && [ "$parts" -eq 2 ] && [ "$first" = "Hello" ] && [ "$second" = "from" ]; then
+        echo -e "${GREEN}PASS${NC} (2 parts)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Parts: $parts, got '$first' '$second')"
+        ((fail_count++))
+    fi
+}
+
+test_large_file() {
+    echo -n "Testing large file download... "
+    head -c 5000000 /dev/urandom > www/static/large.bin
+    expected=$(md5sum < www/static/large.bin)
+    actual=$(curl -s ${BASE_URL}/static/large.bin | md5sum)
+    rm -f www/static/large.bin
+    if [ "$expected" = "$actual" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Body differs from file on disk)"
+        ((fail_count++))
+    fi
+}
+
+test_listing_not_modified() {
+    echo -n "Testing directory listing If-Modified-Since... "
+    last_modified=$(curl -s -D - -o /dev/null ${BASE_URL}/static/ | grep -i "^Last-Modified:" | cut -d' ' -f2- | tr -d '\r')
+    status=$(curl -s -o /dev/null -w "%{http_code}" -H "If-Modified-Since: $last_modified" ${BASE_URL}/static/)
+    if [ -n "$last_modified" ] && [ "$status" = "304" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Last-Modified: '$last_modified', Status: $status)"
+        ((fail_count++))
+    fi
+}
+
+test_index_options() {
+    echo -n "Testing index precedence... "
+    response=$(curl -s ${BASE_URL}/docs/)
+    if echo "$response" | grep -q "Docs home"; then
+        echo -e "${GREEN}PASS${NC} (First index entry served)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Unexpected index document)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing index off with autoindex on... "
+    response=$(curl -s ${BASE_URL}/docs-listing/)
+    if echo "$response" | grep -q "Index of" && echo "$response" | grep -q "home.html"; then
+        echo -e "${GREEN}PASS${NC} (Listing served)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Expected directory listing)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing index off with autoindex off... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" ${BASE_URL}/docs-private/)
+    if [ "$status" = "403" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_empty_post() {
+    echo -n "Testing CGI zero-length POST... "
+    response=$(curl -s --max-time 5 -X POST -d "" ${BASE_URL}/cgi-bin/echo_stdin.py)
+    if echo "$response" | grep -q "CONTENT_LENGTH=0" && echo "$response" | grep -q "read=0"; then
+        echo -e "${GREEN}PASS${NC} (Script saw EOF)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Response: $response)"
+        ((fail_count++))
+    fi
+}
+
+test_unlimited_body_size() {
+    echo -n "Testing client_max_body_size 0 (unlimited)... "
+    head -c 8000000 /dev/zero > /tmp/test_large_body.bin
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X POST --data-binary @/tmp/test_large_body.bin http://localhost:9001/)
+    rm -f /tmp/test_large_body.bin
+    if [ "$status" = "200" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+}
+
+test_client_abort() {
+    echo -n "Testing client disconnect mid-response... "
+    head -c 20000000 /dev/zero > www/static/abort.bin
+    exec 3<>/dev/tcp/localhost/8080
+    printf "GET /static/abort.bin HTTP/1.1\r\nHost: localhost\r\n\r\n" >&3
+    head -c 1000 <&3 > /dev/null
+    exec 3<&-
+    exec 3>&-
+    sleep 0.5
+    rm -f www/static/abort.bin
+    status=$(curl -s -o /dev/null -w "%{http_code}" ${BASE_URL}/)
+    if [ "$status" = "200" ]; then
+        echo -e "${GREEN}PASS${NC} (Server still responding)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+}
+
+test_listen_all_interfaces() {
+    for listen in "*:9100" "0.0.0.0:9101"; do
+        echo -n "Testing listen ${listen}... "
+        if [ ! -x "$WEBSERV_BIN" ]; then
+            echo -e "${YELLOW}SKIP${NC} (No webserv binary built)"
+            continue
+        fi
+
+        port=${listen##*:}
+        printf "server {\n    listen %s;\n    location / {\n        root ./www;\n    }\n}\n" "$listen" > /tmp/test_listen.conf
+        "$WEBSERV_BIN" /tmp/test_listen.conf > /dev/null 2>&1 &
+        pid=$!
+        sleep 0.5
+        status=$(curl -s -o /dev/null -w "%{http_code}" http://127.0.0.1:${port}/)
+        kill $pid 2>/dev/null
+        wait $pid 2>/dev/null
+        rm -f /tmp/test_listen.conf
+        if [ "$status" = "200" ]; then
+            echo -e "${GREEN}PASS${NC} (Status: $status)"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Status: $status)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_conditional_precedence() {
+    headers=$(curl -s -D - -o /dev/null ${BASE_URL}/static/test.txt)
+    etag=$(echo "$headers" | grep -i "^ETag:" | cut -d' ' -f2- | tr -d '\r')
+    last_modified=$(echo "$headers" | grep -i "^Last-Modified:" | cut -d' ' -f2- | tr -d '\r')
+
+    echo -n "Testing If-None-Match mismatch beats If-Modified-Since... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -H 'If-None-Match: "stale"' \
+        -H "If-Modified-Since: $last_modified" ${BASE_URL}/static/test.txt)
+    if [ -n "$etag" ] && [ "$status" = "200" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (ETag: '$etag', Status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing If-None-Match match beats If-Modified-Since... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -H "If-None-Match: $etag" \
+        -H "If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT" ${BASE_URL}/static/test.txt)
+    if [ "$status" = "304" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing DELETE with failing If-Match... "
+    echo "keep me" > www/uploads/precondition.txt
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE -H 'If-Match: "stale"' ${BASE_URL}/uploads/precondition.txt)
+    if [ "$status" = "412" ] && [ -f www/uploads/precondition.txt ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+    rm -f www/uploads/precondition.txt
+
+    echo -n "Testing If-Match on a missing target... "
+    # With no current representation nothing matches, not even *
+    put_status=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H 'If-Match: *' -d "new" ${BASE_URL}/uploads/precondition.txt)
+    delete_status=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE -H 'If-Match: "x"' ${BASE_URL}/uploads/precondition.txt)
+    if [ "$put_status $delete_status" = "412 412" ] && [ ! -e www/uploads/precondition.txt ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (PUT: $put_status, DELETE: $delete_status)"
+        ((fail_count++))
+    fi
+    rm -f www/uploads/precondition.txt
+}
+
+test_overlong_body() {
+    echo -n "Testing body longer than Content-Length... "
+    exec 3<>/dev/tcp/localhost/8080
+    printf "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello world\x01\x02garbage" >&3
+    # The server must answer 400 and close, so cat returns before the timeout
+    statuses=$(timeout 5 cat <&3 | grep -a -o "HTTP/1.1 [0-9]*" | cut -d' ' -f2 | tr '\n' ' ')
+    exec 3<&-
+    exec 3>&-
+    if [ "$statuses" = "200 400 " ]; then
+        echo -e "${GREEN}PASS${NC} (400 and connection closed)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Statuses: $statuses)"
+        ((fail_count++))
+    fi
+}
+
+test_leading_crlf() {
+    echo -n "Testing empty lines before a request are ignored... "
+    exec 3<>/dev/tcp/localhost/8080
+    # A stray CRLF after the POST body, then a keep-alive request that
+    # follows in its own write
+    printf "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\r\n" >&3
+    sleep 0.2
+    # A server that rejects the stray CRLF has closed by now
+    (printf "\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n") >&3 2> /dev/null
+    statuses=$(timeout 5 cat <&3 | grep -a -o "HTTP/1.1 [0-9][0-9][0-9]" | cut -d' ' -f2 | tr '\n' ' ')
+    exec 3<&-
+    exec 3>&-
+    if [ "$statuses" = "200 200 " ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Statuses: $statuses)"
+        ((fail_count++))
+    fi
+}
+
+test_pipelining_fairness() {
+    echo -n "Testing deep pipelining doesn't starve other clients... "
+    exec 3<>/dev/tcp/localhost/8080
+    for i in $(seq 1 200); do
+        printf "GET /static/test.txt HTTP/1.1\r\nHost: localhost\r\n\r\n"
+    done >&3
+    status=$(curl -s --max-time 2 -o /dev/null -w "%{http_code}" ${BASE_URL}/)
+    responses=$(timeout 5 grep -a -m 200 "^HTTP/1.1 200" <&3 | wc -l)
+    exec 3<&-
+    exec 3>&-
+    if [ "$status" = "200" ] && [ "$responses" -eq 200 ]; then
+        echo -e "${GREEN}PASS${NC} (Second client served, 200 pipelined responses)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status, Pipelined responses: $responses)"
+        ((fail_count++))
+    fi
+}
+
+test_request_duration() {
+    echo -n "Testing request duration metric... "
+    curl -s -o /dev/null ${BASE_URL}/static/test.txt
+    metrics=$(curl -s ${BASE_URL}/metrics)
+    count=$(echo "$metrics" | grep "^webserv_request_duration_seconds_count" | awk '{print $2}')
+    sum=$(echo "$metrics" | grep "^webserv_request_duration_seconds_sum" | awk '{print $2}')
+    # Every request so far was local, so the total must be positive but small
+    if [ -n "$count" ] && [ "$count" -gt 0 ] && awk "BEGIN { exit !($sum > 0 && $sum < $count) }"; then
+        echo -e "${GREEN}PASS${NC} ($count requests, ${sum}s total)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Count: '$count', Sum: '$sum')"
+        ((fail_count++))
+    fi
+}
+
+test_session_cookie_config() {
+    echo -n "Testing custom session cookie name and TTL... "
+    cookie=$(curl -s -D - -o /dev/null http://localhost:9000/ | grep -i "^Set-Cookie:" | tr -d '\r')
+    session=$(echo "$cookie" | sed -n 's/.*\(webserv_sid=[0-9a-f]*\).*/\1/p')
+    if [ -n "$session" ] && echo "$cookie" | grep -q "Max-Age=600"; then
+        echo -e "${GREEN}PASS${NC} ($session)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Set-Cookie: $cookie)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing custom session cookie is honored... "
+    reissued=$(curl -s -D - -o /dev/null -H "Cookie: $session" http://localhost:9000/ | grep -i -c "^Set-Cookie:")
+    if [ "$reissued" -eq 0 ]; then
+        echo -e "${GREEN}PASS${NC} (No new session issued)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Session was reissued)"
+        ((fail_count++))
+    fi
+}
+
+test_sessions_off() {
+    echo -n "Testing sessions off for a server... "
+    cookies=$(curl -s -D - -o /dev/null http://localhost:9001/ | grep -i -c "^Set-Cookie:")
+    if [ "$cookies" -eq 0 ]; then
+        echo -e "${GREEN}PASS${NC} (No Set-Cookie)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Set-Cookie emitted)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing sessions off for a location... "
+    cookies=$(curl -s -D - -o /dev/null ${BASE_URL}/static/test.txt | grep -i -c "^Set-Cookie:")
+    if [ "$cookies" -eq 0 ]; then
+        echo -e "${GREEN}PASS${NC} (No Set-Cookie)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Set-Cookie emitted)"
+        ((fail_count++))
+    fi
+}
+
+test_vary_header() {
+    echo -n "Testing Vary on gzip-negotiated response... "
+    echo "vary test content" > www/static/vary_test.txt
+    gzip -k -f www/static/vary_test.txt
+    vary=$(curl -s -D - -o /dev/null -H "Accept-Encoding: gzip" ${BASE_URL}/static/vary_test.txt | grep -i "^Vary:" | tr -d '\r')
+    rm -f www/static/vary_test.txt www/static/vary_test.txt.gz
+    if echo "$vary" | grep -q "Accept-Encoding"; then
+        echo -e "${GREEN}PASS${NC} ($vary)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Vary: '$vary')"
+        ((fail_count++))
+    fi
+}
+
+test_directory_delete() {
+    echo -n "Testing DELETE of an empty directory... "
+    mkdir -p www/uploads/empty_dir
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE ${BASE_URL}/uploads/empty_dir)
+    if [ "$status" = "204" ] && [ ! -e www/uploads/empty_dir ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing DELETE of a nested directory... "
+    mkdir -p www/uploads/nested_dir/a/b
+    echo "data" > www/uploads/nested_dir/a/b/file.txt
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE ${BASE_URL}/uploads/nested_dir/)
+    if [ "$status" = "204" ] && [ ! -e www/uploads/nested_dir ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing DELETE of a directory without allow_dir_delete... "
+    mkdir -p www/delete_me
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE ${BASE_URL}/delete_me)
+    if [ "$status" = "403" ] && [ -d www/delete_me ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+    rm -rf www/delete_me www/uploads/empty_dir www/uploads/nested_dir
+}
+
+test_config_check() {
+    echo -n "Testing -t on a valid config... "
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    output=$("$WEBSERV_BIN" -t config.conf 2>&1)
+    if [ $? -eq 0 ] && echo "$output" | grep -q "configuration OK"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($output)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing -t on an invalid config... "
+    printf "server {\n    listen 127.0.0.1:9102;\n    location / {\n        root ./does-not-exist;\n    }\n}\n" > /tmp/test_check.conf
+    output=$("$WEBSERV_BIN" -t /tmp/test_check.conf 2>&1)
+    status=$?
+    rm -f /tmp/test_check.conf
+    if [ $status -ne 0 ] && echo "$output" | grep -q "line 3"; then
+        echo -e "${GREEN}PASS${NC} (Exit: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Exit: $status, $output)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing -t on a listen without an address... "
+    printf "server {\n    listen ;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_check.conf
+    output=$("$WEBSERV_BIN" -t /tmp/test_check.conf 2>&1)
+    status=$?
+    rm -f /tmp/test_check.conf
+    if [ $status -eq 1 ] && echo "$output" | grep -q "line 2" && echo "$output" | grep -q "listen needs an address"; then
+        echo -e "${GREEN}PASS${NC} (Exit: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Exit: $status, $output)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing -t on a malformed write_chunk_size... "
+    printf "server {\n    listen 8080;\n    write_chunk_size 64x;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_check.conf
+    output=$("$WEBSERV_BIN" -t /tmp/test_check.conf 2>&1)
+    status=$?
+    printf '{"servers": [{"listen": "8080", "write_chunk_size": "64x", "locations": [{"path": "/", "root": "./www"}]}]}' > /tmp/test_check.json
+    json_output=$("$WEBSERV_BIN" -t /tmp/test_check.json 2>&1)
+    json_status=$?
+    rm -f /tmp/test_check.conf /tmp/test_check.json
+    if [ $status -eq 1 ] && echo "$output" | grep -q "line 3: Invalid write_chunk_size '64x'" \
+        && [ $json_status -eq 1 ] && echo "$json_output" | grep -q "Invalid write_chunk_size '64x'"; then
+        echo -e "${GREEN}PASS${NC} (Exit: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Exit: $status, $output; JSON exit: $json_status, $json_output)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing -t on a malformed gzip_min_length... "
+    printf "server {\n    listen 8080;\n    gzip on;\n    gzip_min_length 1k0;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_check.conf
+    output=$("$WEBSERV_BIN" -t /tmp/test_check.conf 2>&1)
+    status=$?
+    printf '{"servers": [{"listen": "8080", "gzip_min_length": "1k0", "locations": [{"path": "/", "root": "./www"}]}]}' > /tmp/test_check.json
+    json_output=$("$WEBSERV_BIN" -t /tmp/test_check.json 2>&1)
+    json_status=$?
+    rm -f /tmp/test_check.conf /tmp/test_check.json
+    if [ $status -eq 1 ] && echo "$output" | grep -q "line 4: Invalid gzip_min_length '1k0'" \
+        && [ $json_status -eq 1 ] && echo "$json_output" | grep -q "Invalid gzip_min_length '1k0'"; then
+        echo -e "${GREEN}PASS${NC} (Exit: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Exit: $status, $output; JSON exit: $json_status, $json_output)"
+        ((fail_count++))
+    fi
+}
+
+test_quoted_values() {
+    echo -n "Testing quoted multi-word return text... "
+    body=$(curl -s ${BASE_URL}/hello)
+    if [ "$body" = "hello world" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Body: $body)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing quoted return text with escaped quotes... "
+    body=$(curl -s ${BASE_URL}/quote)
+    if [ "$body" = 'say "hi"; bye' ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Body: $body)"
+        ((fail_count++))
+    fi
+}
+
+test_default_server() {
+    echo -n "Testing Host routing to a named server... "
+    body=$(curl -s -H "Host: gamma.test" http://127.0.0.1:9002/)
+    if [ "$body" = "gamma" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Body: $body)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing unknown Host falls back to default_server... "
+    body=$(curl -s -H "Host: unknown.test" http://127.0.0.1:9002/)
+    if [ "$body" = "beta" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Body: $body)"
+        ((fail_count++))
+    fi
+}
+
+test_status_reasons() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing status reason phrases... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    reasons=(
+        "408 Request Timeout"
+        "409 Conflict"
+        "412 Precondition Failed"
+        "414 URI Too Long"
+        "415 Unsupported Media Type"
+        "416 Range Not Satisfiable"
+        "417 Expectation Failed"
+        "429 Too Many Requests"
+        "431 Request Header Fields Too Large"
+        "502 Bad Gateway"
+        "503 Service Unavailable"
+        "504 Gateway Timeout"
+        "505 HTTP Version Not Supported"
+    )
+    {
+        echo "server {"
+        echo "    listen 127.0.0.1:9103;"
+        for reason in "${reasons[@]}"; do
+            code=${reason%% *}
+            echo "    location /$code {"
+            echo "        return $code \"$code\";"
+            echo "    }"
+        done
+        echo "}"
+    } > /tmp/test_reasons.conf
+    "$WEBSERV_BIN" /tmp/test_reasons.conf > /dev/null 2>&1 &
+    reasons_pid=$!
+    sleep 0.5
+
+    for reason in "${reasons[@]}"; do
+        code=${reason%% *}
+        echo -n "Testing reason phrase for $code... "
+        status_line=$(curl -s -i http://127.0.0.1:9103/$code | head -1 | tr -d '\r')
+        if [ "$status_line" = "HTTP/1.1 $reason" ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Status line: $status_line)"
+            ((fail_count++))
+        fi
+    done
+
+    kill $reasons_pid 2>/dev/null
+    wait $reasons_pid 2>/dev/null
+    rm -f /tmp/test_reasons.conf
+}
+
+test_drain() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing drain control... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    printf "server {\n    listen 127.0.0.1:9104;\n    location / {\n        root ./www;\n    }\n    location /admin/drain {\n        allow_methods POST;\n        drain_control on;\n    }\n}\n" > /tmp/test_drain.conf
+    "$WEBSERV_BIN" /tmp/test_drain.conf > /dev/null 2>&1 &
+    drain_pid=$!
+    sleep 0.5
+
+    # A request still being sent when draining starts
+    exec 3<>/dev/tcp/127.0.0.1/9104
+    printf "GET / HTTP/1.1\r\nHost: localhost\r\n" >&3
+    sleep 0.2
+
+    echo -n "Testing drain request is accepted... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X POST http://127.0.0.1:9104/admin/drain)
+    if [ "$status" = "202" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing new connections are refused while draining... "
+    curl -s -o /dev/null --max-time 2 http://127.0.0.1:9104/
+    curl_exit=$?
+    if [ $curl_exit -eq 7 ]; then
+        echo -e "${GREEN}PASS${NC} (curl exit: $curl_exit)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (curl exit: $curl_exit)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing in-flight request completes while draining... "
+    printf "\r\n" >&3
+    response=$(timeout 5 cat <&3 | tr -d '\r')
+    exec 3<&-
+    if echo "$response" | head -1 | grep -q "200" && echo "$response" | grep -qi "^Connection: close"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($(echo "$response" | head -1))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing server exits once drained... "
+    sleep 1.5
+    if ! kill -0 $drain_pid 2>/dev/null; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Server still running)"
+        ((fail_count++))
+        kill $drain_pid 2>/dev/null
+    fi
+    wait $drain_pid 2>/dev/null
+    rm -f /tmp/test_drain.conf
+}
+
+test_method_override() {
+    echo -n "Testing POST overridden to DELETE... "
+    echo "override me" > www/uploads/override_test.txt
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X POST -H "X-HTTP-Method-Override: DELETE" ${BASE_URL}/uploads/override_test.txt)
+    if [ "$status" = "204" ] && [ ! -e www/uploads/override_test.txt ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+        rm -f www/uploads/override_test.txt
+    fi
+
+    echo -n "Testing override to a method the route forbids... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X POST -H "X-HTTP-Method-Override: PATCH" -d "x" ${BASE_URL}/uploads/override_test.txt)
+    if [ "$status" = "405" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+}
+
+test_websocket_upgrade() {
+    echo -n "Testing WebSocket upgrade is refused with 501... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" \
+        -H "Connection: Upgrade" -H "Upgrade: websocket" \
+        -H "Sec-WebSocket-Version: 13" -H "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==" \
+        ${BASE_URL}/index.html)
+    if [ "$status" = "501" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+}
+
+test_request_id() {
+    echo -n "Testing client-supplied X-Request-Id is echoed... "
+    header=$(curl -s -i -H "X-Request-Id: trace-abc123" ${BASE_URL}/ | grep -i "^X-Request-Id:" | tr -d '\r')
+    if [ "$header" = "X-Request-Id: trace-abc123" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($header)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing request id is passed to CGI... "
+    response=$(curl -s -i ${BASE_URL}/cgi-bin/request_id.py | tr -d '\r')
+    id=$(echo "$response" | grep -i "^X-Request-Id:" | awk '{print $2}')
+    body=$(echo "$response" | tail -1)
+    if [ -n "$id" ] && [ "$body" = "$id" ]; then
+        echo -e "${GREEN}PASS${NC} ($id)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Header: $id, CGI saw: $body)"
+        ((fail_count++))
+    fi
+
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing request id in the access log... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing request id in the access log... "
+    printf "server {\n    listen 127.0.0.1:9105;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_request_id.conf
+    "$WEBSERV_BIN" /tmp/test_request_id.conf > /tmp/test_request_id.log 2>&1 &
+    request_id_pid=$!
+    sleep 0.5
+    id=$(curl -s -i http://127.0.0.1:9105/index.html | grep -i "^X-Request-Id:" | awk '{print $2}' | tr -d '\r')
+    sleep 0.2
+    kill $request_id_pid 2>/dev/null
+    wait $request_id_pid 2>/dev/null
+    if [ -n "$id" ] && grep "GET /index.html HTTP/1.1" /tmp/test_request_id.log | grep -q "\"$id\""; then
+        echo -e "${GREEN}PASS${NC} ($id)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Header: $id)"
+        ((fail_count++))
+    fi
+    rm -f /tmp/test_request_id.conf /tmp/test_request_id.log
+}
+
+test_cgi_streaming() {
+    echo -n "Testing CGI output is streamed as it is produced... "
+    early=$(timeout 0.7 curl -sN ${BASE_URL}/cgi-bin/stream.py)
+    if [ "$early" = "part1" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Received early: $early)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing streamed CGI response is chunked and complete... "
+    response=$(curl -s -i ${BASE_URL}/cgi-bin/stream.py | tr -d '\r')
+    if echo "$response" | grep -qi "^Transfer-Encoding: chunked" && [ "$(echo "$response" | tail -2 | tr '\n' ' ')" = "part1 part2 " ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC}"
+        ((fail_count++))
+    fi
+}
+
+test_internal_location() {
+    echo -n "Testing direct request to an internal location... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" ${BASE_URL}/fallback/app.html)
+    if [ "$status" = "404" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing try_files fallback into an internal location... "
+    body=$(curl -s ${BASE_URL}/app/some/route)
+    if [ "$body" = "App shell" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Body: $body)"
+        ((fail_count++))
+    fi
+
+    for case in "/exact/missing.html|404" "/guarded/missing.html|403" "/exact/index.html|200"; do
+        IFS='|' read -r path expected <<< "$case"
+        echo -n "Testing try_files =code for $path... "
+        status=$(curl -s -o /dev/null -w "%{http_code}" ${BASE_URL}$path)
+        if [ "$status" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC} (Status: $status)"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Status: $status, expected $expected)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_chunked_keep_alive() {
+    echo -n "Testing request pipelined after a chunked body... "
+    exec 3<>/dev/tcp/${HOST}/${PORT}
+    printf "POST /cgi-bin/echo_stdin.py HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nGET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n" >&3
+    response=$(timeout 5 cat <&3 | tr -d '\r')
+    exec 3<&-
+    statuses=$(echo "$response" | grep "^HTTP/1.1" | awk '{print $2}' | tr '\n' ' ')
+    if [ "$statuses" = "200 200 " ] && echo "$response" | grep -q "^read=5$"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Statuses: $statuses)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_accel_redirect() {
+    echo -n "Testing CGI X-Accel-Redirect serves the referenced file... "
+    response=$(curl -s -i ${BASE_URL}/cgi-bin/accel.py | tr -d '\r')
+    body=$(echo "$response" | tail -1)
+    if [ "$body" = "App shell" ] && echo "$response" | grep -qi "^Content-Type: text/html" && echo "$response" | grep -qi "^ETag:"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Body: $body)"
+        ((fail_count++))
+    fi
+}
+
+test_tcp_keepalive() {
+    if ! command -v ss > /dev/null; then
+        echo -e "Testing TCP keepalive... ${YELLOW}SKIP${NC} (ss not available)"
+        return
+    fi
+    echo -n "Testing TCP keepalive is enabled on accepted sockets... "
+    exec 3<>/dev/tcp/${HOST}/${PORT}
+    sleep 0.2
+    timer=$(ss -tnoH state established "( sport = :${PORT} )" | grep -o "timer:(keepalive[^)]*)")
+    exec 3<&-
+    if [ -n "$timer" ]; then
+        echo -e "${GREEN}PASS${NC} ($timer)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (No keepalive timer)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing TCP keepalive stays off where not configured... "
+    exec 3<>/dev/tcp/127.0.0.1/9001
+    sleep 0.2
+    timer=$(ss -tnoH state established "( sport = :9001 )" | grep -o "timer:(keepalive[^)]*)")
+    exec 3<&-
+    if [ -z "$timer" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($timer)"
+        ((fail_count++))
+    fi
+}
+
+test_slow_reader() {
+    if ! command -v python3 > /dev/null; then
+        echo -e "Testing slow reader watchdog... ${YELLOW}SKIP${NC} (python3 not available)"
+        return
+    fi
+    echo -n "Testing a one-byte-at-a-time reader is disconnected... "
+    head -c 5000000 /dev/urandom > www/static/trickle.bin
+    received=$(python3 - "$HOST" "$PORT" <<'PYEOF'
+import socket, sys, time
+
+sock = socket.socket()
+sock.setsockopt(socket.SOL_SOCKET, socket.SO_RCVBUF, 4096)
+sock.connect((sys.argv[1], int(sys.argv[2])))
+sock.sendall(b"GET /static/trickle.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+
+# Trickle one byte at a time, then drain whatever is left
+total = 0
+deadline = time.time() + 12
+while time.time() < deadline:
+    total += len(sock.recv(1))
+    time.sleep(0.05)
+
+sock.settimeout(5)
+try:
+    while True:
+        data = sock.recv(65536)
+        if not data:
+            break
+        total += len(data)
+except socket.timeout:
+    pass
+print(total)
+PYEOF
+)
+    rm -f www/static/trickle.bin
+    if [ -n "$received" ] && [ "$received" -lt 5000000 ]; then
+        echo -e "${GREEN}PASS${NC} (Received $received bytes)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Received $received bytes)"
+        ((fail_count++))
+    fi
+}
+
+test_embedded_config() {
+    embedded_bin="$(dirname "$WEBSERV_BIN")/examples/embedded"
+    if [ ! -x "$embedded_bin" ]; then
+        echo -e "Testing a programmatically built config... ${YELLOW}SKIP${NC} (Build it with cargo build --examples)"
+        return
+    fi
+    echo -n "Testing a programmatically built config... "
+    "$embedded_bin" > /tmp/test_embedded.log 2>&1 &
+    embedded_pid=$!
+    sleep 0.5
+    body=$(curl -s http://127.0.0.1:9106/index.html)
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X POST http://127.0.0.1:9106/index.html)
+    api_type=$(curl -s -D - -o /dev/null http://127.0.0.1:9106/api/status | grep -i "^Content-Type:" | tr -d '\r')
+    api_body=$(curl -s -X POST -d "hello" http://127.0.0.1:9106/api/status)
+    large_body=$(head -c 300000 /dev/zero | tr '\0' 'x' | curl -s -X POST --data-binary @- http://127.0.0.1:9106/api/status)
+    # Server directives set through the builder: gzip, method_override,
+    # keepalive_timeout 2 and log_format "$request $status"
+    encoding=$(curl -s -D - -o /dev/null -H "Accept-Encoding: gzip" http://127.0.0.1:9106/index.html | grep -i "^Content-Encoding:" | tr -d '\r')
+    override_status=$(curl -s -o /dev/null -w "%{http_code}" -X POST -H "X-HTTP-Method-Override: GET" http://127.0.0.1:9106/index.html)
+    idle_close=$(python3 -c "
+import socket, time
+sock = socket.create_connection(('127.0.0.1', 9106), timeout=10)
+sock.sendall(b'GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n')
+time.sleep(0.2)
+sock.recv(65536)
+start = time.time()
+print(sock.recv(1) == b'' and time.time() - start < 4)
+" 2>/dev/null)
+    kill $embedded_pid 2>/dev/null
+    wait $embedded_pid 2>/dev/null
+    if [ "$body" = "$(cat www/index.html)" ] && [ "$status" = "405" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (POST status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a Rust handler answers its location... "
+    if [ "$api_type" = "Content-Type: application/json" ] && [ "$api_body" = '{"method":"POST","body_length":5}' ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($api_type, $api_body)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a body read 4K at a time arrives whole... "
+    if [ "$large_body" = '{"method":"POST","body_length":300000}' ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($large_body)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing server directives set through the builder... "
+    logged=$(grep -c "^POST /index.html HTTP/1.1 405$" /tmp/test_embedded.log)
+    rm -f /tmp/test_embedded.log
+    if [ "$encoding" = "Content-Encoding: gzip" ] && [ "$override_status" = "200" ] && [ "$idle_close" = "True" ] && [ "$logged" = "1" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($encoding, override $override_status, idle close $idle_close, logged $logged)"
+        ((fail_count++))
+    fi
+}
+
+test_shared_error_page() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing error_page with several codes... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing error_page with several codes... "
+    printf "server {\n    listen 127.0.0.1:9107;\n    error_page 404 405 ./errors/404.html;\n    error_page 500 502 503 504 ./errors/500.html;\n    location / {\n        root ./www;\n        allow_methods GET;\n    }\n}\n" > /tmp/test_error_page.conf
+    "$WEBSERV_BIN" /tmp/test_error_page.conf > /dev/null 2>&1 &
+    error_page_pid=$!
+    sleep 0.5
+    not_found=$(curl -s http://127.0.0.1:9107/missing.html)
+    not_allowed=$(curl -s -X DELETE http://127.0.0.1:9107/index.html)
+    kill $error_page_pid 2>/dev/null
+    wait $error_page_pid 2>/dev/null
+    expected=$(cat errors/404.html)
+    if [ "$not_found" = "$expected" ] && [ "$not_allowed" = "$expected" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (404 and 405 did not both serve errors/404.html)"
+        ((fail_count++))
+    fi
+}
+
+test_error_page_redirect() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing error_page internal redirects... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    printf "server {\n    listen 127.0.0.1:9108;\n    error_page 404 /pages/not_found.txt;\n    error_page 405 =200 /pages/maintenance.html;\n    error_page 403 /pages/missing.html;\n    location / {\n        root ./www;\n        allow_methods GET;\n    }\n    location /pages {\n        root ./errors;\n        internal;\n    }\n}\n" > /tmp/test_error_redirect.conf
+    "$WEBSERV_BIN" /tmp/test_error_redirect.conf > /dev/null 2>&1 &
+    error_redirect_pid=$!
+    sleep 0.5
+
+    echo -n "Testing error_page URI keeps the status and content type... "
+    response=$(curl -s -i http://127.0.0.1:9108/missing.html | tr -d '\r')
+    if echo "$response" | head -1 | grep -q "404" && echo "$response" | grep -qi "^Content-Type: text/plain" && echo "$response" | tail -1 | grep -q "Nothing here"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($(echo "$response" | head -1))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing error_page with a status override... "
+    response=$(curl -s -i -X DELETE http://127.0.0.1:9108/index.html | tr -d '\r')
+    if echo "$response" | head -1 | grep -q "200 OK" && echo "$response" | grep -q "Back soon"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($(echo "$response" | head -1))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a missing error_page falls back to the built-in page... "
+    response=$(curl -s -i --path-as-is http://127.0.0.1:9108/../index.html | tr -d '\r')
+    if echo "$response" | head -1 | grep -q "403" && echo "$response" | grep -q "<h1>403 Forbidden</h1>"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($(echo "$response" | head -1))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing error_page targets stay internal... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" http://127.0.0.1:9108/pages/not_found.txt)
+    kill $error_redirect_pid 2>/dev/null
+    wait $error_redirect_pid 2>/dev/null
+    if [ "$status" = "404" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_header_filtering() {
+    echo -n "Testing CGI skips unsafe header names... "
+    response=$(curl -s -H "X_Spoofed: 1" -H "X-Normal: 1" -H "Proxy: http://evil.test" ${BASE_URL}/cgi-bin/env.py)
+    if echo "$response" | grep -q "^HTTP_X_NORMAL$" && ! echo "$response" | grep -q "^HTTP_X_SPOOFED$" && ! echo "$response" | grep -q "^HTTP_PROXY$"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC}"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing CGI header count is capped... "
+    header_args=()
+    for i in $(seq 1 80); do
+        header_args+=(-H "X-Pad-$i: $i")
+    done
+    count=$(curl -s "${header_args[@]}" ${BASE_URL}/cgi-bin/env.py | grep -c "^HTTP_")
+    if [ "$count" = "50" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($count HTTP_* variables)"
+        ((fail_count++))
+    fi
+}
+
+test_gzip_compression() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing on-the-fly gzip... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    # Repetitive but not trivially so, so the levels find different matches
+    python3 -c "
+import random
+random.seed(7)
+words = 'the quick brown fox jumps over lazy dog server request response header body'.split()
+print(' '.join(random.choice(words) for _ in range(30000)))" > www/static/words.txt
+    printf "server {\n    listen 127.0.0.1:9109;\n    gzip on;\n    gzip_comp_level 1;\n    gzip_min_length 1000;\n    location / {\n        root ./www;\n    }\n}\nserver {\n    listen 127.0.0.1:9110;\n    gzip on;\n    gzip_comp_level 9;\n    gzip_min_length 1000;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_gzip.conf
+    "$WEBSERV_BIN" /tmp/test_gzip.conf > /dev/null 2>&1 &
+    gzip_pid=$!
+    sleep 0.5
+
+    echo -n "Testing gzip_min_length skips small bodies... "
+    headers=$(curl -s -D - -o /dev/null -H "Accept-Encoding: gzip" http://127.0.0.1:9109/fallback/app.html | tr -d '\r')
+    if ! echo "$headers" | grep -qi "^Content-Encoding:" && echo "$headers" | grep -qi "^Vary: Accept-Encoding"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC}"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing compressed bodies decode to the original... "
+    if curl -s --compressed http://127.0.0.1:9109/static/words.txt | cmp -s - www/static/words.txt \
+        && curl -s --compressed http://127.0.0.1:9110/static/words.txt | cmp -s - www/static/words.txt; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC}"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing gzip_comp_level is honored... "
+    fast=$(curl -s -H "Accept-Encoding: gzip" http://127.0.0.1:9109/static/words.txt | wc -c)
+    small=$(curl -s -H "Accept-Encoding: gzip" http://127.0.0.1:9110/static/words.txt | wc -c)
+    identity=$(curl -s http://127.0.0.1:9110/static/words.txt | wc -c)
+    kill $gzip_pid 2>/dev/null
+    wait $gzip_pid 2>/dev/null
+    if [ "$small" -lt "$fast" ] && [ "$fast" -lt "$identity" ] && [ "$identity" = "$(wc -c < www/static/words.txt)" ]; then
+        echo -e "${GREEN}PASS${NC} (Level 1: $fast bytes, level 9: $small bytes)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Level 1: $fast bytes, level 9: $small bytes, identity: $identity bytes)"
+        ((fail_count++))
+    fi
+    rm -f www/static/words.txt
+}
+
+test_connection_semantics() {
+    if ! command -v python3 > /dev/null; then
+        echo -e "Testing keep-alive by version and Connection header... ${YELLOW}SKIP${NC} (python3 not available)"
+        return
+    fi
+    # Expected Connection header and whether the socket stays open afterwards
+    for case in "HTTP/1.0||close|closed" "HTTP/1.0|keep-alive|keep-alive|open" \
+                "HTTP/1.1||keep-alive|open" "HTTP/1.1|close|close|closed"; do
+        IFS='|' read -r version connection expected_header expected_state <<< "$case"
+        echo -n "Testing $version with Connection: ${connection:-(none)}... "
+        result=$(python3 - "$HOST" "$PORT" "$version" "$connection" <<'PYEOF'
+import socket, sys
+
+host, port, version, connection = sys.argv[1], int(sys.argv[2]), sys.argv[3], sys.argv[4]
+sock = socket.create_connection((host, port))
+request = "GET /index.html %s\r\nHost: localhost\r\n" % version
+if connection:
+    request += "Connection: %s\r\n" % connection
+sock.sendall((request + "\r\n").encode())
+
+# Read exactly one response, then see whether the server hangs up
+sock.settimeout(5)
+data = b""
+while b"\r\n\r\n" not in data:
+    data += sock.recv(65536)
+head, body = data.split(b"\r\n\r\n", 1)
+headers = {}
+for line in head.decode().split("\r\n")[1:]:
+    name, _, value = line.partition(":")
+    headers[name.strip().lower()] = value.strip()
+while len(body) < int(headers["content-length"]):
+    body += sock.recv(65536)
+
+sock.settimeout(1)
+try:
+    state = "closed" if sock.recv(1) == b"" else "open"
+except socket.timeout:
+    state = "open"
+print("%s|%s" % (headers.get("connection", ""), state))
+PYEOF
+)
+        if [ "$result" = "$expected_header|$expected_state" ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Got $result, expected $expected_header|$expected_state)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_upload_naming() {
+    echo "first upload" > /tmp/naming_test.txt
+    rm -f www/uploads/naming_test*.txt
+
+    echo -n "Testing upload keeps the client filename... "
+    curl -s -o /dev/null -F "file=@/tmp/naming_test.txt" ${BASE_URL}/uploads
+    if cmp -s www/uploads/naming_test.txt /tmp/naming_test.txt; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($(ls www/uploads | grep naming_test))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing upload name collision adds a suffix... "
+    echo "second upload" > /tmp/naming_test.txt
+    body=$(curl -s -F "file=@/tmp/naming_test.txt" ${BASE_URL}/uploads)
+    if [ "$(cat www/uploads/naming_test.txt)" = "first upload" ] && cmp -s www/uploads/naming_test-1.txt /tmp/naming_test.txt \
+        && echo "$body" | grep -q "naming_test-1.txt"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($body)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing upload filename is sanitized... "
+    curl -s -o /dev/null -F "file=@/tmp/naming_test.txt;filename=../../naming_test evil.txt" ${BASE_URL}/uploads
+    if [ -f "www/uploads/naming_test_evil.txt" ] && [ ! -e "naming_test evil.txt" ] && [ ! -e "www/naming_test evil.txt" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC}"
+        ((fail_count++))
+    fi
+    rm -f /tmp/naming_test.txt www/uploads/naming_test*.txt
+}
+
+test_upload_requires_upload_dir() {
+    echo "form data" > /tmp/upload_dir_test.txt
+    rm -f www/uploads/upload_dir_test*.txt
+
+    echo -n "Testing multipart POST to an upload route... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -F "file=@/tmp/upload_dir_test.txt" ${BASE_URL}/uploads)
+    if [ "$status" = "201" ] && [ -f www/uploads/upload_dir_test.txt ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing multipart POST to a route without upload_dir... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -F "file=@/tmp/upload_dir_test.txt" ${BASE_URL}/)
+    if [ "$status" = "403" ] && [ ! -e uploads/upload_dir_test.txt ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing multipart POST reaches a CGI script... "
+    response=$(curl -s -F "file=@/tmp/upload_dir_test.txt" ${BASE_URL}/cgi-bin/echo_stdin.py)
+    if echo "$response" | grep -q "^read=[1-9]"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($response)"
+        ((fail_count++))
+    fi
+    rm -f /tmp/upload_dir_test.txt www/uploads/upload_dir_test*.txt
+}
+
+test_force_download() {
+    echo -n "Testing force_download sends an attachment... "
+    header=$(curl -s -D - -o /dev/null ${BASE_URL}/downloads/test.txt | grep -i "^Content-Disposition:" | tr -d '\r')
+    if [ "$header" = 'Content-Disposition: attachment; filename="test.txt"' ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($header)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing ?download=1 sends an attachment... "
+    header=$(curl -s -D - -o /dev/null "${BASE_URL}/static/test.txt?download=1" | grep -i "^Content-Disposition:" | tr -d '\r')
+    if [ "$header" = 'Content-Disposition: attachment; filename="test.txt"' ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($header)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing files render inline by default... "
+    if ! curl -s -D - -o /dev/null ${BASE_URL}/static/test.txt | grep -qi "^Content-Disposition:"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC}"
+        ((fail_count++))
+    fi
+}
+
+test_read_coalescing() {
+    echo -n "Testing large bodies are read in few syscalls... "
+    body=$(mktemp)
+    head -c 900000 /dev/zero | tr '\0' 'a' > "$body"
+    before=$(curl -s ${BASE_URL}/metrics | grep "^webserv_socket_reads_total" | awk '{print $2}')
+    curl -s -o /dev/null -H "Expect:" --data-binary @"$body" ${BASE_URL}/
+    after=$(curl -s ${BASE_URL}/metrics | grep "^webserv_socket_reads_total" | awk '{print $2}')
+    rm -f "$body"
+    # One 8K read per wakeup would need at least 110 reads for this body
+    if [ -n "$before" ] && [ -n "$after" ] && [ $((after - before)) -lt 60 ]; then
+        echo -e "${GREEN}PASS${NC} ($((after - before)) reads for 900K)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (socket_reads $before -> $after)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing bodies over client_max_body_size are refused... "
+    body=$(mktemp)
+    head -c 2000000 /dev/zero > "$body"
+    status=$(curl -s -o /dev/null -w "%{http_code}" -H "Expect:" --data-binary @"$body" ${BASE_URL}/)
+    rm -f "$body"
+    if [ "$status" = "413" ] && curl -s -o /dev/null -f ${BASE_URL}/; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (got $status)"
+        ((fail_count++))
+    fi
+}
+
+test_not_modified_headers() {
+    echo -n "Testing 304 has no body or Content-Length... "
+    headers=$(curl -s -D - -o /dev/null ${BASE_URL}/static/test.txt | tr -d '\r')
+    etag=$(echo "$headers" | grep -i "^ETag:" | cut -d' ' -f2-)
+    not_modified=$(curl -s -D - -o /tmp/webserv_304_body -H "If-None-Match: $etag" ${BASE_URL}/static/test.txt | tr -d '\r')
+    if echo "$not_modified" | head -1 | grep -q " 304 " \
+        && ! echo "$not_modified" | grep -qi "^Content-Length:" \
+        && [ ! -s /tmp/webserv_304_body ] \
+        && echo "$not_modified" | grep -qi "^ETag: $etag$" \
+        && echo "$not_modified" | grep -qi "^Date: " \
+        && [ "$(echo "$not_modified" | grep -i "^Last-Modified:")" = "$(echo "$headers" | grep -i "^Last-Modified:")" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC}"
+        ((fail_count++))
+    fi
+    rm -f /tmp/webserv_304_body
+}
+
+test_log_sizes() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing log_format sizes... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing log_format sizes... "
+    printf "server {\n    listen 127.0.0.1:9111;\n    log_format sizes;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_log_sizes.conf
+    "$WEBSERV_BIN" /tmp/test_log_sizes.conf > /tmp/test_log_sizes.log 2>&1 &
+    log_sizes_pid=$!
+    sleep 0.5
+    get_size=$(curl -s -o /dev/null -w "%{size_download}" http://127.0.0.1:9111/index.html)
+    post_size=$(curl -s -o /dev/null -w "%{size_download}" -d "hello world" http://127.0.0.1:9111/index.html)
+    sleep 0.2
+    kill $log_sizes_pid 2>/dev/null
+    wait $log_sizes_pid 2>/dev/null
+    get_line=$(grep "\"GET /index.html HTTP/1.1\"" /tmp/test_log_sizes.log)
+    post_line=$(grep "\"POST /index.html HTTP/1.1\"" /tmp/test_log_sizes.log)
+    if [ "$get_size" = "$(wc -c < www/index.html)" ] \
+        && [[ "$get_line" == *" request_bytes=0 response_bytes=$get_size" ]] \
+        && [[ "$post_line" == *" request_bytes=11 response_bytes=$post_size" ]]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($get_line / $post_line)"
+        ((fail_count++))
+    fi
+    rm -f /tmp/test_log_sizes.conf /tmp/test_log_sizes.log
+}
+
+test_fd_reuse_stress() {
+    if ! command -v python3 > /dev/null; then
+        echo -e "Testing rapid connect/close churn... ${YELLOW}SKIP${NC} (python3 not available)"
+        return
+    fi
+    echo -n "Testing rapid connect/close churn... "
+    # Abrupt closes free fd numbers that the next accept in the same epoll
+    # batch hands out again; the full requests must never see a stale event
+    result=$(python3 - "$HOST" "$PORT" <<'PYEOF'
+import socket, struct, sys, threading
+
+host, port = sys.argv[1], int(sys.argv[2])
+expected = open("www/index.html", "rb").read()
+failures = []
+
+def churn():
+    for i in range(200):
+        sock = socket.create_connection((host, port))
+        if i % 2:
+            sock.sendall(b"GET /index.html HTTP/1.1\r\nHo")
+        # Close with a reset rather than a FIN
+        sock.setsockopt(socket.SOL_SOCKET, socket.SO_LINGER, struct.pack("ii", 1, 0))
+        sock.close()
+
+def fetch():
+    sock = socket.create_connection((host, port))
+    sock.settimeout(5)
+    try:
+        for _ in range(100):
+            sock.sendall(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            data = b""
+            while b"\r\n\r\n" not in data:
+                chunk = sock.recv(65536)
+                if not chunk:
+                    raise Exception("connection closed")
+                data += chunk
+            head, body = data.split(b"\r\n\r\n", 1)
+            length = [int(line.split(b":")[1]) for line in head.split(b"\r\n")
+                      if line.lower().startswith(b"content-length:")][0]
+            while len(body) < length:
+                body += sock.recv(65536)
+            if not head.startswith(b"HTTP/1.1 200") or body != expected:
+                raise Exception(head.split(b"\r\n")[0].decode())
+    except Exception as e:
+        failures.append(str(e))
+    sock.close()
+
+threads = [threading.Thread(target=churn) for _ in range(4)]
+threads += [threading.Thread(target=fetch) for _ in range(4)]
+for t in threads:
+    t.start()
+for t in threads:
+    t.join()
+print("ok" if not failures else failures[0])
+PYEOF
+)
+    status=$(curl -s -o /dev/null -w "%{http_code}" ${BASE_URL}/index.html)
+    if [ "$result" = "ok" ] && [ "$status" = "200" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result, then status $status)"
+        ((fail_count++))
+    fi
+}
+
+test_expect_continue() {
+    if ! command -v python3 > /dev/null; then
+        echo -e "Testing Expect: 100-continue... ${YELLOW}SKIP${NC} (python3 not available)"
+        return
+    fi
+    # Request headers, whether to send the body after a 100, and the status
+    # lines expected in order
+    for case in "Content-Length: 2000000|no|413" "Content-Length: 5|yes|100 200" \
+                "Content-Length: 5|no|417|unknown"; do
+        IFS='|' read -r length send_body expected expectation <<< "$case"
+        expectation=${expectation:-100-continue}
+        echo -n "Testing Expect: $expectation with $length... "
+        result=$(python3 - "$HOST" "$PORT" "$length" "$send_body" "$expectation" <<'PYEOF'
+import socket, sys
+
+host, port, length, send_body, expectation = sys.argv[1], int(sys.argv[2]), sys.argv[3], sys.argv[4], sys.argv[5]
+sock = socket.create_connection((host, port))
+sock.sendall(("POST /index.html HTTP/1.1\r\nHost: localhost\r\n%s\r\nExpect: %s\r\n\r\n"
+              % (length, expectation)).encode())
+
+# Everything the server says within a second, without any body being sent
+sock.settimeout(1)
+statuses = []
+data = b""
+try:
+    while True:
+        chunk = sock.recv(65536)
+        if not chunk:
+            break
+        data += chunk
+        if b"100 Continue\r\n\r\n" in data and send_body == "yes":
+            data = data.split(b"100 Continue\r\n\r\n", 1)[1]
+            statuses.append("100")
+            sock.sendall(b"hello")
+            send_body = "sent"
+except socket.timeout:
+    pass
+if data:
+    statuses.append(data.split(b"\r\n", 1)[0].split(b" ")[1].decode())
+print(" ".join(statuses))
+PYEOF
+)
+        if [ "$result" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Got '$result', expected '$expected')"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_session_list() {
+    echo -n "Testing the session list reflects new sessions... "
+    start=$(date +%s)
+    ids=""
+    for _ in 1 2 3; do
+        id=$(curl -s -D - -o /dev/null ${BASE_URL}/ | sed -n 's/^Set-Cookie: sessionid=\([0-9a-f]*\).*/\1/p')
+        ids="$ids $id"
+    done
+    end=$(date +%s)
+    listing=$(curl -s ${BASE_URL}/sessions)
+    ok=1
+    for id in $ids; do
+        line=$(echo "$listing" | grep "^$id ")
+        created=$(echo "$line" | sed -n 's/.* created_at=\([0-9]*\) .*/\1/p')
+        accessed=$(echo "$line" | sed -n 's/.* last_accessed=\([0-9]*\) .*/\1/p')
+        if [ -z "$created" ] || [ "$created" -lt "$start" ] || [ "$created" -gt "$end" ] || [ "$accessed" != "$created" ]; then
+            ok=0
+        fi
+    done
+    if [ "$(echo $ids | wc -w)" = "3" ] && [ "$ok" = "1" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (ids:$ids)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing listing sessions leaves last_accessed alone... "
+    first=$(echo $ids | awk '{print $1}')
+    sleep 1.1
+    again=$(curl -s ${BASE_URL}/sessions | grep "^$first ")
+    curl -s -o /dev/null -H "Cookie: sessionid=$first" ${BASE_URL}/
+    used=$(curl -s ${BASE_URL}/sessions | grep "^$first ")
+    used_accessed=$(echo "$used" | sed -n 's/.* last_accessed=\([0-9]*\) .*/\1/p')
+    used_created=$(echo "$used" | sed -n 's/.* created_at=\([0-9]*\) .*/\1/p')
+    if [ -n "$again" ] && [ "$again" = "$(echo "$listing" | grep "^$first ")" ] \
+        && [ -n "$used_accessed" ] && [ "$used_accessed" -gt "$used_created" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($again / $used)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_trailers() {
+    if ! command -v python3 > /dev/null; then
+        echo -e "Testing CGI trailers... ${YELLOW}SKIP${NC} (python3 not available)"
+        return
+    fi
+    echo -n "Testing CGI trailers after the last chunk... "
+    result=$(python3 - "$HOST" "$PORT" <<'PYEOF'
+import socket, sys, zlib
+
+host, port = sys.argv[1], int(sys.argv[2])
+sock = socket.create_connection((host, port))
+sock.sendall(b"GET /cgi-bin/trailer.py HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+sock.settimeout(5)
+data = b""
+while True:
+    chunk = sock.recv(65536)
+    if not chunk:
+        break
+    data += chunk
+
+head, rest = data.split(b"\r\n\r\n", 1)
+headers = {}
+for line in head.decode().split("\r\n")[1:]:
+    name, _, value = line.partition(":")
+    headers[name.strip().lower()] = value.strip()
+
+# Decode the chunks, then read the trailer section up to the blank line
+body = b""
+while True:
+    size_line, rest = rest.split(b"\r\n", 1)
+    size = int(size_line, 16)
+    if size == 0:
+        break
+    body += rest[:size]
+    rest = rest[size + 2:]
+trailer_block, rest = rest.split(b"\r\n\r\n", 1) if not rest.startswith(b"\r\n") else (b"", rest[2:])
+trailers = {}
+for line in trailer_block.decode().split("\r\n"):
+    if line:
+        name, _, value = line.partition(":")
+        trailers[name.strip().lower()] = value.strip()
+
+checks = [
+    headers.get("transfer-encoding") == "chunked",
+    headers.get("trailer") == "X-Content-CRC32, Server-Timing",
+    body == b"first line\nsecond line\n",
+    trailers.get("x-content-crc32") == "%08x" % zlib.crc32(body),
+    trailers.get("server-timing", "").startswith("cgi;dur="),
+    "x-unknown" not in trailers and rest == b"",
+]
+print("ok" if all(checks) else "%s %s %s" % (checks, headers.get("trailer"), trailers))
+PYEOF
+)
+    if [ "$result" = "ok" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing HTTP/1.0 CGI responses drop the Trailer header... "
+    header=$(curl -s -0 -D - -o /dev/null ${BASE_URL}/cgi-bin/trailer.py | grep -i "^Trailer:")
+    if [ -z "$header" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($header)"
+        ((fail_count++))
+    fi
+}
+
+test_invalid_content_length() {
+    for length in "abc" "-5" "+5"; do
+        echo -n "Testing Content-Length: $length is rejected... "
+        exec 3<>/dev/tcp/localhost/8080
+        printf -v request "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: %s\r\n\r\nhello" "$length"
+        printf "%s" "$request" >&3
+        statuses=$(timeout 5 cat <&3 | grep -a -o "HTTP/1.1 [0-9]*" | cut -d' ' -f2 | tr '\n' ' ')
+        exec 3<&-
+        exec 3>&-
+        if [ "$statuses" = "400 " ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Statuses: $statuses)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_duplicate_content_length() {
+    # Headers to send, then the statuses expected before the server closes
+    # or the read times out
+    for case in "Content-Length: 5|Content-Length: 7|400 " "Content-Length: 5|Content-Length: 5|200 " \
+                "Content-Length: 5, 7||400 " "Content-Length: 5, 5||200 "; do
+        IFS='|' read -r first second expected <<< "$case"
+        echo -n "Testing ${first}${second:+ and $second}... "
+        exec 3<>/dev/tcp/localhost/8080
+        # One write, so a server closing after the 400 can't fail the rest
+        printf -v request "POST /index.html HTTP/1.1\r\nHost: localhost\r\n%s\r\n%bConnection: close\r\n\r\nhello" \
+            "$first" "${second:+$second\r\n}"
+        printf "%s" "$request" >&3
+        statuses=$(timeout 5 cat <&3 | grep -a -o "HTTP/1.1 [0-9]*" | cut -d' ' -f2 | tr '\n' ' ')
+        exec 3<&-
+        exec 3>&-
+        if [ "$statuses" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Statuses: $statuses)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_log_format_template() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing log_format templates... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing log_format templates... "
+    cat > /tmp/test_log_format.conf <<'CONFEOF'
+server {
+    listen 127.0.0.1:9112;
+    log_format "$remote_addr \"$request\" $status $body_bytes_sent \"$http_referer\" \"$http_user_agent\" $request_time";
+    location / {
+        root ./www;
+    }
+}
+CONFEOF
+    "$WEBSERV_BIN" /tmp/test_log_format.conf > /tmp/test_log_format.log 2>&1 &
+    log_format_pid=$!
+    sleep 0.5
+    size=$(curl -s -o /dev/null -w "%{size_download}" -e "http://example.com/from" -A "webserv-test/1.0" \
+        http://127.0.0.1:9112/index.html)
+    sleep 0.2
+    kill $log_format_pid 2>/dev/null
+    wait $log_format_pid 2>/dev/null
+    line=$(grep "GET /index.html" /tmp/test_log_format.log)
+    expected="^127\.0\.0\.1 \"GET /index\.html HTTP/1\.1\" 200 $size \"http://example\.com/from\" \"webserv-test/1\.0\" [0-9]+\.[0-9]{3}$"
+    if [[ "$line" =~ $expected ]]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($line)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing unknown log_format variables are rejected... "
+    sed -i 's/\$status/$bogus/' /tmp/test_log_format.conf
+    output=$("$WEBSERV_BIN" -t /tmp/test_log_format.conf 2>&1)
+    if [ $? -ne 0 ] && echo "$output" | grep -q "Unknown log_format variable '\$bogus'"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($output)"
+        ((fail_count++))
+    fi
+    rm -f /tmp/test_log_format.conf /tmp/test_log_format.log
+}
+
+test_fragmented_headers() {
+    if ! command -v python3 > /dev/null; then
+        echo -e "Testing headers sent a byte at a time... ${YELLOW}SKIP${NC} (python3 not available)"
+        return
+    fi
+    # Header value length and the expected status; the second one pushes the
+    # header block past the 32K limit
+    for case in "4000|200" "40000|431"; do
+        IFS='|' read -r length expected <<< "$case"
+        echo -n "Testing a $length byte header sent a byte at a time... "
+        result=$(python3 - "$HOST" "$PORT" "$length" <<'PYEOF'
+import socket, sys
+
+host, port, length = sys.argv[1], int(sys.argv[2]), int(sys.argv[3])
+value = "".join(chr(ord("a") + i % 26) for i in range(length))
+request = ("GET /cgi-bin/echo_header.py HTTP/1.1\r\nHost: localhost\r\nX-Echo: %s\r\n"
+           "Connection: close\r\n\r\n" % value).encode()
+
+sock = socket.create_connection((host, port))
+sock.setsockopt(socket.IPPROTO_TCP, socket.TCP_NODELAY, 1)
+sock.settimeout(10)
+try:
+    for i in range(len(request)):
+        sock.send(request[i:i + 1])
+except (BrokenPipeError, ConnectionResetError):
+    pass
+
+data = b""
+try:
+    while True:
+        chunk = sock.recv(65536)
+        if not chunk:
+            break
+        data += chunk
+except (socket.timeout, ConnectionResetError):
+    pass
+status = data.split(b" ", 2)[1].decode() if data else "none"
+# A 200 must carry the value back intact, within the script's chunked body
+intact = status != "200" or (value + "\n").encode() in data
+print(status if intact else "%s (value mangled)" % status)
+PYEOF
+)
+        if [ "$result" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Got $result, expected $expected)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_directory_slash_redirect() {
+    echo -n "Testing a directory without a trailing slash redirects... "
+    result=$(curl -s -o /dev/null -w "%{http_code} %{redirect_url}" "${BASE_URL}/docs?page=2")
+    if [ "$result" = "301 ${BASE_URL}/docs/?page=2" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result)"
+        ((fail_count++))
+    fi
+
+    for case in "/docs/missing|404" "/docs/home.html|200"; do
+        IFS='|' read -r path expected <<< "$case"
+        echo -n "Testing $path is not redirected... "
+        status=$(curl -s -o /dev/null -w "%{http_code}" ${BASE_URL}$path)
+        if [ "$status" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC} (Status: $status)"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Status: $status, expected $expected)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_return_444() {
+    echo -n "Testing return 444 closes without a response... "
+    exec 3<>/dev/tcp/localhost/8080
+    printf -v request "GET /drop HTTP/1.1\r\nHost: localhost\r\n\r\n"
+    printf "%s" "$request" >&3
+    # timeout exits 124 if the server left the connection open
+    bytes=$(timeout 5 cat <&3 | wc -c; exit "${PIPESTATUS[0]}")
+    closed=$?
+    exec 3<&-
+    exec 3>&-
+    if [ "$closed" = "0" ] && [ "$bytes" = "0" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Bytes: $bytes, cat exit: $closed)"
+        ((fail_count++))
+    fi
+}
+
+test_chunk_buffer_limit() {
+    echo -n "Testing an unterminated oversized chunk-size line is rejected... "
+    exec 3<>/dev/tcp/localhost/8080
+    printf -v request "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n%s" \
+        "$(head -c 5000 /dev/zero | tr '\0' '0')"
+    printf "%s" "$request" >&3
+    statuses=$(timeout 5 cat <&3 | grep -a -o "HTTP/1.1 [0-9]*" | cut -d' ' -f2 | tr '\n' ' ')
+    exec 3<&-
+    exec 3>&-
+    if [ "$statuses" = "400 " ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Statuses: $statuses)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a single chunk larger than the body limit gets 413... "
+    request=$(mktemp)
+    {
+        printf "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n%x\r\n" 2000000
+        head -c 2000000 /dev/zero
+        printf "\r\n0\r\n\r\n"
+    } > "$request"
+    exec 3<>/dev/tcp/localhost/8080
+    cat "$request" >&3
+    statuses=$(timeout 5 cat <&3 | grep -a -o "HTTP/1.1 [0-9]*" | cut -d' ' -f2 | tr '\n' ' ')
+    exec 3<&-
+    exec 3>&-
+    rm -f "$request"
+    if [ "$statuses" = "413 " ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Statuses: $statuses)"
+        ((fail_count++))
+    fi
+}
+
+test_path_error_log() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing 404 and 403 log the attempted path... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing 404 and 403 log the attempted path... "
+    printf "server {\n    listen 127.0.0.1:9113;\n    location /files {\n        root ./www;\n    }\n    location /cgi-bin {\n        root ./cgi-bin;\n        cgi_extension .py;\n        cgi_path /usr/bin/python3;\n    }\n}\n" > /tmp/test_path_error.conf
+    "$WEBSERV_BIN" /tmp/test_path_error.conf > /tmp/test_path_error.log 2>&1 &
+    path_error_pid=$!
+    sleep 0.5
+    missing=$(curl -s -o /dev/null -w "%{http_code}" http://127.0.0.1:9113/files/missing.html)
+    forbidden=$(curl -s -o /dev/null -w "%{http_code}" http://127.0.0.1:9113/files/fallback/)
+    failed=$(curl -s -o /dev/null -w "%{http_code}" http://127.0.0.1:9113/cgi-bin/fail.py)
+    sleep 0.2
+    kill $path_error_pid 2>/dev/null
+    wait $path_error_pid 2>/dev/null
+    if [ "$missing" = "404" ] && [ "$forbidden" = "403" ] \
+        && grep -q "^\[[0-9a-f]*\] 404 for $PWD/www/missing.html: not found" /tmp/test_path_error.log \
+        && grep -q "^\[[0-9a-f]*\] 403 for $PWD/www/fallback: directory has no index" /tmp/test_path_error.log; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($missing/$forbidden: $(grep " for " /tmp/test_path_error.log | tr '\n' ' '))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a failed script logs with the request id... "
+    # One line for the failure, and nothing for running a script at all
+    if [ "$failed" = "500" ] && grep -q "^\[[0-9a-f]*\] CGI script failed: boom" /tmp/test_path_error.log \
+        && ! grep -q -e "DEBUG" -e "Executing CGI" /tmp/test_path_error.log; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($failed: $(grep "CGI" /tmp/test_path_error.log | tr '\n' ' '))"
+        ((fail_count++))
+    fi
+    rm -f /tmp/test_path_error.conf /tmp/test_path_error.log
+}
+
+test_location_types() {
+    for case in "/templates/page.tpl|text/html" "/templates/about|text/html" "/templates/notes.txt|text/plain"; do
+        IFS='|' read -r path expected <<< "$case"
+        echo -n "Testing Content-Type of $path... "
+        content_type=$(curl -s -o /dev/null -w "%{content_type}" ${BASE_URL}$path)
+        if [ "$content_type" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC} ($content_type)"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (got '$content_type', expected $expected)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_http_date_formats() {
+    if ! command -v python3 > /dev/null; then
+        echo -e "Testing legacy If-Modified-Since formats... ${YELLOW}SKIP${NC} (python3 not available)"
+        return
+    fi
+    # The file's modification time, and one second before it, in the
+    # IMF-fixdate, RFC 850 and asctime forms
+    dates=$(python3 - <<'PYEOF'
+import os, time
+mtime = int(os.stat("www/static/test.txt").st_mtime)
+for secs, expected in ((mtime, 304), (mtime - 1, 200)):
+    t = time.gmtime(secs)
+    for fmt in ("%a, %d %b %Y %H:%M:%S GMT", "%A, %d-%b-%y %H:%M:%S GMT", "%a %b {day} %H:%M:%S %Y"):
+        print("%s|%d" % (time.strftime(fmt, t).replace("{day}", "%2d" % t.tm_mday), expected))
+PYEOF
+)
+    while IFS='|' read -r date expected; do
+        echo -n "Testing If-Modified-Since: $date... "
+        status=$(curl -s -o /dev/null -w "%{http_code}" -H "If-Modified-Since: $date" ${BASE_URL}/static/test.txt)
+        if [ "$status" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC} (Status: $status)"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Status: $status, expected $expected)"
+            ((fail_count++))
+        fi
+    done <<< "$dates"
+}
+
+test_default_favicon() {
+    echo -n "Testing built-in favicon when none exists... "
+    result=$(curl -s -o /tmp/webserv_favicon -w "%{http_code} %{content_type}" ${BASE_URL}/favicon.ico)
+    size=$(wc -c < /tmp/webserv_favicon)
+    rm -f /tmp/webserv_favicon
+    if [ "$result" = "200 image/x-icon" ] && [ "$size" -gt 0 ] \
+        && curl -s -D - -o /dev/null ${BASE_URL}/favicon.ico | grep -qi "^Cache-Control: public, max-age="; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result, $size bytes)"
+        ((fail_count++))
+    fi
+
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing a favicon on disk wins over the built-in one... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing a favicon on disk wins over the built-in one... "
+    mkdir -p /tmp/webserv_favicon_root
+    printf "real icon" > /tmp/webserv_favicon_root/favicon.ico
+    printf "server {\n    listen 127.0.0.1:9114;\n    default_favicon on;\n    location / {\n        root /tmp/webserv_favicon_root;\n    }\n}\n" > /tmp/test_favicon.conf
+    "$WEBSERV_BIN" /tmp/test_favicon.conf > /dev/null 2>&1 &
+    favicon_pid=$!
+    sleep 0.5
+    body=$(curl -s http://127.0.0.1:9114/favicon.ico)
+    kill $favicon_pid 2>/dev/null
+    wait $favicon_pid 2>/dev/null
+    rm -rf /tmp/webserv_favicon_root /tmp/test_favicon.conf
+    if [ "$body" = "real icon" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (got '$body')"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_query_string() {
+    for query in "path=%2Fa%3Fb%3Dc" "a=1&b=2=3&c" "q=why?&x=%3F?"; do
+        echo -n "Testing QUERY_STRING $query is passed verbatim... "
+        response=$(curl -s "${BASE_URL}/cgi-bin/query.py?$query")
+        if [ "$response" = "$query" ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (got '$response')"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_limit_except() {
+    # /restricted only lets 10.0.0.0/8 past GET, /local-writes lets localhost
+    for case in "GET|/restricted/index.html|200" "DELETE|/restricted/missing.txt|403" \
+                "GET|/local-writes/index.html|200" "DELETE|/local-writes/missing.txt|404"; do
+        IFS='|' read -r method path expected <<< "$case"
+        echo -n "Testing limit_except: $method $path... "
+        status=$(curl -s -o /dev/null -w "%{http_code}" -X "$method" ${BASE_URL}$path)
+        if [ "$status" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC} (Status: $status)"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Status: $status, expected $expected)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_options_asterisk() {
+    echo -n "Testing OPTIONS * lists the server's methods... "
+    headers=$(curl -s -D - -o /dev/null -X OPTIONS --request-target '*' ${BASE_URL}/ | tr -d '\r')
+    if echo "$headers" | head -1 | grep -q " 204 " \
+        && echo "$headers" | grep -qx "Allow: GET, POST, PUT, DELETE, OPTIONS"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($(echo "$headers" | grep -i "^Allow:\|^HTTP" | tr '\n' ' '))"
+        ((fail_count++))
+    fi
+
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing OPTIONS * on a read-only server... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing OPTIONS * on a read-only server... "
+    printf "server {\n    listen 127.0.0.1:9115;\n    location / {\n        root ./www;\n        allow_methods GET;\n    }\n}\n" > /tmp/test_options.conf
+    "$WEBSERV_BIN" /tmp/test_options.conf > /dev/null 2>&1 &
+    options_pid=$!
+    sleep 0.5
+    allow=$(curl -s -D - -o /dev/null -X OPTIONS --request-target '*' http://127.0.0.1:9115/ | tr -d '\r' | grep -i "^Allow:")
+    kill $options_pid 2>/dev/null
+    wait $options_pid 2>/dev/null
+    rm -f /tmp/test_options.conf
+    if [ "$allow" = "Allow: GET, OPTIONS" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($allow)"
+        ((fail_count++))
+    fi
+}
+
+test_body_round_trip() {
+    echo -n "Testing a large body round-trips through CGI intact... "
+    body=$(mktemp)
+    head -c 900000 /dev/urandom > "$body"
+    sent=$(sha256sum < "$body" | cut -d' ' -f1)
+    received=$(curl -s -H "Expect:" --data-binary @"$body" ${BASE_URL}/cgi-bin/echo_body.py | sha256sum | cut -d' ' -f1)
+    rm -f "$body"
+    if [ "$sent" = "$received" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (checksum mismatch)"
+        ((fail_count++))
+    fi
+}
+
+test_accept_throttling() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing max_accepts_per_wakeup... ${YELLOW}SKIP${NC} (Needs the webserv binary and python3)"
+        return
+    fi
+    echo -n "Testing max_accepts_per_wakeup... "
+    printf "server {\n    listen 127.0.0.1:9116;\n    max_accepts_per_wakeup 2;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_accepts.conf
+    "$WEBSERV_BIN" /tmp/test_accepts.conf > /dev/null 2>&1 &
+    accepts_pid=$!
+    sleep 0.5
+    # A connected client asks again while 100 connections wait in the listen
+    # queue; it must be answered, and every queued one served in turn
+    result=$(python3 - <<'PYEOF'
+import socket
+
+def request(sock):
+    sock.sendall(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+
+def status(sock):
+    # Read one whole response, so the next one starts on a clean stream
+    data = b""
+    while b"\r\n\r\n" not in data:
+        chunk = sock.recv(4096)
+        if not chunk:
+            return "none"
+        data += chunk
+    head, body = data.split(b"\r\n\r\n", 1)
+    length = next(int(line.split(b":")[1]) for line in head.split(b"\r\n")
+                  if line.lower().startswith(b"content-length:"))
+    while len(body) < length:
+        body += sock.recv(4096)
+    return head.split(b" ")[1].decode()
+
+existing = socket.create_connection(("127.0.0.1", 9116), timeout=5)
+request(existing)
+first = status(existing)
+
+queued = [socket.create_connection(("127.0.0.1", 9116), timeout=5) for _ in range(100)]
+for sock in queued:
+    request(sock)
+request(existing)
+again = status(existing)
+served = sum(status(sock) == "200" for sock in queued)
+print(first, again, served)
+PYEOF
+)
+    rejected=$(printf "server {\n    listen 127.0.0.1:9117;\n    max_accepts_per_wakeup 0;\n}\n" > /tmp/test_accepts_zero.conf; \
+        "$WEBSERV_BIN" -t /tmp/test_accepts_zero.conf > /dev/null 2>&1 || echo rejected)
+    kill $accepts_pid 2>/dev/null
+    wait $accepts_pid 2>/dev/null
+    rm -f /tmp/test_accepts.conf /tmp/test_accepts_zero.conf
+    if [ "$result" = "200 200 100" ] && [ "$rejected" = "rejected" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result, max_accepts_per_wakeup 0: ${rejected:-accepted})"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_debug() {
+    echo -n "Testing cgi_debug shows a failed script's stderr... "
+    response=$(curl -s -w "\n%{http_code}" ${BASE_URL}/cgi-debug/fail.py)
+    if [ "$(echo "$response" | tail -1)" = "500" ] \
+        && echo "$response" | grep -qF "boom: &lt;missing&gt; &amp; &quot;broken&quot;"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $(echo "$response" | tail -1))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a failed script's stderr is hidden by default... "
+    response=$(curl -s -w "\n%{http_code}" ${BASE_URL}/cgi-bin/fail.py)
+    if [ "$(echo "$response" | tail -1)" = "500" ] && ! echo "$response" | grep -q "boom"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $(echo "$response" | tail -1))"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_binary_body() {
+    echo -n "Testing a binary CGI body is delivered intact... "
+    received=$(curl -s -D /tmp/webserv_image_headers ${BASE_URL}/cgi-bin/image.py | sha256sum | cut -d' ' -f1)
+    # The script's output less its "Content-Type: image/png" header block
+    expected=$(python3 cgi-bin/image.py | tail -c +28 | sha256sum | cut -d' ' -f1)
+    if head -1 /tmp/webserv_image_headers | grep -q " 200 " \
+        && grep -qi "^Content-Type: image/png" /tmp/webserv_image_headers \
+        && [ "$received" = "$expected" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($(head -1 /tmp/webserv_image_headers | tr -d '\r'))"
+        ((fail_count++))
+    fi
+    rm -f /tmp/webserv_image_headers
+}
+
+test_limit_conn_per_ip() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing limit_conn_per_ip... ${YELLOW}SKIP${NC} (Needs the webserv binary and python3)"
+        return
+    fi
+    echo -n "Testing limit_conn_per_ip... "
+    printf "server {\n    listen 127.0.0.1:9118;\n    limit_conn_per_ip 3;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_conn_limit.conf
+    "$WEBSERV_BIN" /tmp/test_conn_limit.conf > /dev/null 2>&1 &
+    conn_limit_pid=$!
+    sleep 0.5
+    # Three connections are served, a fourth gets 503, and closing one makes
+    # room for another
+    result=$(python3 - <<'PYEOF'
+import socket, time
+
+def status(sock):
+    sock.sendall(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    data = sock.recv(65536)
+    return data.split(b" ")[1].decode() if data else "closed"
+
+def connect():
+    return socket.create_connection(("127.0.0.1", 9118), timeout=5)
+
+held = [connect() for _ in range(3)]
+statuses = [status(sock) for sock in held]
+
+extra = connect()
+refused = extra.recv(65536).split(b" ")[1].decode()
+extra.close()
+
+held.pop().close()
+time.sleep(0.3)
+statuses.append(status(connect()))
+print(" ".join(statuses), refused)
+PYEOF
+)
+    kill $conn_limit_pid 2>/dev/null
+    wait $conn_limit_pid 2>/dev/null
+    rm -f /tmp/test_conn_limit.conf
+    if [ "$result" = "200 200 200 200 503" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result)"
+        ((fail_count++))
+    fi
+}
+
+test_catch_all_server_name() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing server_name _... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    cat > /tmp/test_catch_all.conf <<'EOF'
+server {
+    listen 127.0.0.1:9119 default_server;
+    server_name default.test;
+    location / {
+        return 200 "default";
+    }
+}
+
+server {
+    listen 127.0.0.1:9119;
+    server_name _;
+    location / {
+        return 200 "catch-all";
+    }
+}
+
+server {
+    listen 127.0.0.1:9119;
+    server_name named.test;
+    location / {
+        return 200 "named";
+    }
+}
+EOF
+    "$WEBSERV_BIN" /tmp/test_catch_all.conf > /dev/null 2>&1 &
+    catch_all_pid=$!
+    sleep 0.5
+    for case in "unknown.test|catch-all" "named.test|named" "default.test|default"; do
+        IFS='|' read -r host expected <<< "$case"
+        echo -n "Testing server_name _ with Host: $host... "
+        body=$(curl -s -H "Host: $host" http://127.0.0.1:9119/)
+        if [ "$body" = "$expected" ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Body: $body, expected $expected)"
+            ((fail_count++))
+        fi
+    done
+    kill $catch_all_pid 2>/dev/null
+    wait $catch_all_pid 2>/dev/null
+    rm -f /tmp/test_catch_all.conf
+}
+
+test_obs_fold() {
+    for fold in " " "\t"; do
+        echo -n "Testing a header folded with $([ "$fold" = " " ] && echo a space || echo a tab) is rejected... "
+        exec 3<>/dev/tcp/localhost/8080
+        printf -v request "GET /index.html HTTP/1.1\r\nHost: localhost\r\nX-Folded: first\r\n%bsecond\r\nConnection: close\r\n\r\n" "$fold"
+        printf "%s" "$request" >&3
+        statuses=$(timeout 5 cat <&3 | grep -a -o "HTTP/1.1 [0-9]*" | cut -d' ' -f2 | tr '\n' ' ')
+        exec 3<&-
+        exec 3>&-
+        if [ "$statuses" = "400 " ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Statuses: $statuses)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_max_open_files() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null || [ ! -d /proc/self/fd ]; then
+        echo -e "Testing max_open_files... ${YELLOW}SKIP${NC} (Needs the webserv binary, python3 and /proc)"
+        return
+    fi
+    echo -n "Testing max_open_files... "
+    rm -rf /tmp/test_open_files && mkdir -p /tmp/test_open_files
+    head -c 16777216 /dev/zero > /tmp/test_open_files/big.bin
+    printf "small file\n" > /tmp/test_open_files/small.txt
+    printf "server {\n    listen 127.0.0.1:9120;\n    max_open_files 3;\n    location / {\n        root /tmp/test_open_files;\n    }\n}\n" > /tmp/test_open_files.conf
+    "$WEBSERV_BIN" /tmp/test_open_files.conf > /dev/null 2>&1 &
+    open_files_pid=$!
+    sleep 0.5
+    # Eight slow readers ask for the large file: three stream it and the rest
+    # get 503, small files are still served, and every stream is complete
+    result=$(WEBSERV_PID=$open_files_pid python3 - <<'PYEOF'
+import os, socket, time
+from concurrent.futures import ThreadPoolExecutor
+
+def connect():
+    sock = socket.socket()
+    sock.setsockopt(socket.SOL_SOCKET, socket.SO_RCVBUF, 4096)
+    sock.settimeout(10)
+    sock.connect(("127.0.0.1", 9120))
+    return sock
+
+def read_response(sock, head=b""):
+    while b"\r\n\r\n" not in head:
+        chunk = sock.recv(65536)
+        if not chunk:
+            return "closed", 0
+        head += chunk
+    head, body = head.split(b"\r\n\r\n", 1)
+    status = head.split(b" ")[1].decode()
+    length = next(int(line.split(b":")[1]) for line in head.split(b"\r\n")
+                  if line.lower().startswith(b"content-length:"))
+    received = len(body)
+    while received < length:
+        chunk = sock.recv(1 << 20)
+        if not chunk:
+            break
+        received += len(chunk)
+    return status, received
+
+def open_big_files():
+    fd_dir = "/proc/%s/fd" % os.environ["WEBSERV_PID"]
+    count = 0
+    for name in os.listdir(fd_dir):
+        try:
+            count += os.readlink(os.path.join(fd_dir, name)).endswith("big.bin")
+        except OSError:
+            pass
+    return count
+
+readers = []
+for _ in range(8):
+    sock = connect()
+    sock.sendall(b"GET /big.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    readers.append((sock, sock.recv(4096)))
+    time.sleep(0.05)
+
+time.sleep(0.3)
+peak = open_big_files()
+
+small = connect()
+small.sendall(b"GET /small.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+small_status = read_response(small)
+
+# Drained together so none stalls long enough to trip the slow reader check
+with ThreadPoolExecutor(len(readers)) as pool:
+    results = list(pool.map(lambda reader: read_response(*reader), readers))
+streamed = sum(1 for status, length in results if status == "200" and length == 16777216)
+refused = sum(1 for status, _ in results if status == "503")
+for sock, _ in readers:
+    sock.close()
+time.sleep(0.3)
+
+again = connect()
+again.sendall(b"GET /big.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+print(peak <= 3, streamed, refused, small_status == ("200", 11), read_response(again) == ("200", 16777216))
+PYEOF
+)
+    kill $open_files_pid 2>/dev/null
+    wait $open_files_pid 2>/dev/null
+    rm -rf /tmp/test_open_files /tmp/test_open_files.conf
+    if [ "$result" = "True 3 5 True True" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result)"
+        ((fail_count++))
+    fi
+}
+
+test_proxy_protocol() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing PROXY protocol... ${YELLOW}SKIP${NC} (Needs the webserv binary and python3)"
+        return
+    fi
+    echo -n "Testing PROXY protocol... "
+    cat > /tmp/test_proxy_protocol.conf <<'EOF'
+server {
+    listen 127.0.0.1:9121 proxy_protocol;
+    location /cgi-bin {
+        root ./cgi-bin;
+        cgi_extension .py;
+        cgi_path /usr/bin/python3;
+    }
+}
+EOF
+    "$WEBSERV_BIN" /tmp/test_proxy_protocol.conf > /tmp/test_proxy_protocol.log 2>&1 &
+    proxy_pid=$!
+    sleep 0.5
+    # The address from the PROXY line reaches the script and the access log;
+    # a malformed line or a connection without one is closed unanswered
+    result=$(python3 - <<'PYEOF'
+import socket
+
+def exchange(payload):
+    sock = socket.create_connection(("127.0.0.1", 9121), timeout=5)
+    sock.sendall(payload)
+    data = b""
+    while True:
+        chunk = sock.recv(65536)
+        if not chunk:
+            return data.decode(errors="replace")
+        data += chunk
+
+request = b"GET /cgi-bin/test.py HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+proxied = exchange(b"PROXY TCP4 203.0.113.7 127.0.0.1 56324 9121\r\n" + request)
+print("<td><strong>REMOTE_ADDR</strong></td><td>203.0.113.7</td>" in proxied,
+      exchange(b"PROXY TCP4 203.0.113.7 not-an-address 56324 9121\r\n" + request) == "",
+      exchange(request) == "")
+PYEOF
+)
+    sleep 0.2
+    kill $proxy_pid 2>/dev/null
+    wait $proxy_pid 2>/dev/null
+    if grep -q '^203\.0\.113\.7 .*"GET /cgi-bin/test.py HTTP/1.1" 200' /tmp/test_proxy_protocol.log; then
+        result="$result logged"
+    fi
+    rm -f /tmp/test_proxy_protocol.conf /tmp/test_proxy_protocol.log
+    if [ "$result" = "True True True logged" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result)"
+        ((fail_count++))
+    fi
+}
+
+test_retry_after() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing 503 Retry-After... ${YELLOW}SKIP${NC} (Needs the webserv binary and python3)"
+        return
+    fi
+    echo -n "Testing 503 Retry-After... "
+    rm -rf /tmp/test_retry_after && mkdir -p /tmp/test_retry_after
+    head -c 16777216 /dev/zero > /tmp/test_retry_after/big.bin
+    printf "server {\n    listen 127.0.0.1:9122;\n    limit_conn_per_ip 2;\n    max_open_files 1;\n    retry_after 7;\n    location / {\n        root /tmp/test_retry_after;\n    }\n}\n" > /tmp/test_retry_after.conf
+    "$WEBSERV_BIN" /tmp/test_retry_after.conf > /dev/null 2>&1 &
+    retry_pid=$!
+    sleep 0.5
+    # One slow download holds the only file stream, so a second download is
+    # refused, and a third connection is over the per-address limit; both
+    # refusals are 503s carrying the configured Retry-After
+    result=$(python3 - <<'PYEOF'
+import socket
+
+def connect():
+    sock = socket.socket()
+    sock.setsockopt(socket.SOL_SOCKET, socket.SO_RCVBUF, 4096)
+    sock.settimeout(5)
+    sock.connect(("127.0.0.1", 9122))
+    return sock
+
+def head(sock):
+    data = b""
+    while b"\r\n\r\n" not in data:
+        chunk = sock.recv(4096)
+        if not chunk:
+            break
+        data += chunk
+    lines = data.split(b"\r\n\r\n")[0].decode().split("\r\n")
+    retry = next((line.split(":", 1)[1].strip() for line in lines if line.lower().startswith("retry-after:")), "-")
+    return lines[0].split(" ")[1] + "/" + retry
+
+def get(sock):
+    sock.sendall(b"GET /big.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    return head(sock)
+
+streaming, second, third = connect(), connect(), connect()
+print(get(streaming), get(second), head(third))
+PYEOF
+)
+    kill $retry_pid 2>/dev/null
+    wait $retry_pid 2>/dev/null
+    rm -rf /tmp/test_retry_after /tmp/test_retry_after.conf
+    if [ "$result" = "200/- 503/7 503/7" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result)"
+        ((fail_count++))
+    fi
+}
+
+test_session_fast_path() {
+    echo -n "Testing HEAD is a GET without the body... "
+    get_length=$(curl -s -D - -o /dev/null ${BASE_URL}/ | tr -d '\r' | grep -i "^Content-Length:")
+    head_result=$(curl -s -I -w "%{http_code} %{size_download}" ${BASE_URL}/ | tr -d '\r')
+    if echo "$head_result" | tail -n 1 | grep -qx "200 0" && echo "$head_result" | grep -qix "$get_length"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $(echo "$head_result" | tail -n 1), GET $get_length)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing session fast path... "
+    # A HEAD, a CORS preflight and static assets get no session; an HTML GET
+    # still does. Each is a real success response, not an error page.
+    get=$(curl -s -D - -o /dev/null ${BASE_URL}/ | grep -i -c "^Set-Cookie:")
+    head=$(curl -s -I -w "%{http_code}" ${BASE_URL}/ | tr -d '\r' | grep -i -c -e "^Set-Cookie:" -e "^200$")
+    options=$(curl -s -D - -o /dev/null -w "%{http_code}" -X OPTIONS -H "Origin: https://app.example.com" \
+        -H "Access-Control-Request-Method: PUT" ${BASE_URL}/cors/index.html | tr -d '\r' | grep -i -c -e "^Set-Cookie:" -e "^204$")
+    asset=$(curl -s -D - -o /dev/null -w "%{http_code}" ${BASE_URL}/favicon.ico | tr -d '\r' | grep -i -c -e "^Set-Cookie:" -e "^200$")
+    # Each count is 1 for the status line match alone
+    result="$get $((head - 1)) $((options - 1)) $((asset - 1))"
+
+    # Turned off, a HEAD request is handled like any other
+    if [ -x "$WEBSERV_BIN" ]; then
+        printf "server {\n    listen 127.0.0.1:9123;\n    session_fast_path off;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_session_fast_path.conf
+        "$WEBSERV_BIN" /tmp/test_session_fast_path.conf > /dev/null 2>&1 &
+        fast_path_pid=$!
+        sleep 0.5
+        result="$result $(curl -s -I -w "%{http_code}" http://127.0.0.1:9123/ | tr -d '\r' | grep -i -c -e "^Set-Cookie:" -e "^200$")"
+        kill $fast_path_pid 2>/dev/null
+        wait $fast_path_pid 2>/dev/null
+        rm -f /tmp/test_session_fast_path.conf
+    else
+        result="$result 2"
+    fi
+
+    if [ "$result" = "1 0 0 0 2" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Set-Cookie counts: $result)"
+        ((fail_count++))
+    fi
+}
+
+test_resolution_precedence() {
+    # One location with try_files, index and autoindex: the named file wins,
+    # then a try_files candidate, then the index, then the listing, then =404
+    for case in "both|200|exact file" "guide|200|guide candidate" "indexed/|200|indexed index" \
+                "listed/|200|item.txt" "nothing|404|"; do
+        IFS='|' read -r path expected_status expected_body <<< "$case"
+        echo -n "Testing resolution precedence for /precedence/$path... "
+        body=$(curl -s -w "\n%{http_code}" ${BASE_URL}/precedence/$path)
+        status=$(echo "$body" | tail -n 1)
+        if [ "$status" = "$expected_status" ] && echo "$body" | grep -q "$expected_body"; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Got $status)"
+            ((fail_count++))
+        fi
+    done
+}
+
+test_write_chunk_size() {
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing write_chunk_size... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing write_chunk_size... "
+    rm -rf /tmp/test_write_chunk && mkdir -p /tmp/test_write_chunk
+    head -c 1048576 /dev/urandom > /tmp/test_write_chunk/large.bin
+    cat > /tmp/test_write_chunk.conf <<'EOF'
+server {
+    listen 127.0.0.1:9124;
+    write_chunk_size 16k;
+    tcp_nodelay on;
+    location / {
+        root /tmp/test_write_chunk;
+    }
+    location /metrics {
+        metrics on;
+    }
+}
+
+server {
+    listen 127.0.0.1:9125;
+    write_chunk_size 1m;
+    location / {
+        root /tmp/test_write_chunk;
+    }
+}
+EOF
+    "$WEBSERV_BIN" /tmp/test_write_chunk.conf > /dev/null 2>&1 &
+    chunk_pid=$!
+    sleep 0.5
+    writes() {
+        curl -s http://127.0.0.1:9124/metrics | sed -n 's/^webserv_socket_writes_total //p'
+    }
+    # A 1MB body in 16KB writes takes at least 64 of them; with 1MB writes
+    # it takes far fewer
+    expected=$(sha256sum < /tmp/test_write_chunk/large.bin)
+    before=$(writes)
+    small_sum=$(curl -s http://127.0.0.1:9124/large.bin | sha256sum)
+    middle=$(writes)
+    large_sum=$(curl -s http://127.0.0.1:9125/large.bin | sha256sum)
+    after=$(writes)
+    kill $chunk_pid 2>/dev/null
+    wait $chunk_pid 2>/dev/null
+    rm -rf /tmp/test_write_chunk /tmp/test_write_chunk.conf
+    # Each metrics scrape is one write of its own
+    small_writes=$((middle - before - 1))
+    large_writes=$((after - middle - 1))
+    if [ "$small_sum" = "$expected" ] && [ "$large_sum" = "$expected" ] \
+        && [ "$small_writes" -ge 64 ] && [ "$large_writes" -lt 64 ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($small_writes writes at 16k, $large_writes at 1m)"
+        ((fail_count++))
+    fi
+}
+
+test_put_unmodified_since() {
+    rm -f www/uploads/put_test.txt
+    last_modified() {
+        curl -s -D - -o /dev/null ${BASE_URL}/uploads/put_test.txt | tr -d '\r' | sed -n 's/^Last-Modified: //p'
+    }
+    check() {
+        if [ "$1" = "$2" ] && [ "$(cat www/uploads/put_test.txt 2>/dev/null)" = "$3" ]; then
+            echo -e "${GREEN}PASS${NC} (Status: $1)"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} (Status: $1, expected $2)"
+            ((fail_count++))
+        fi
+    }
+
+    echo -n "Testing PUT creating a file... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X PUT --data-binary "first" ${BASE_URL}/uploads/put_test.txt)
+    check "$status" 201 "first"
+
+    echo -n "Testing PUT with If-Unmodified-Since on an unchanged file... "
+    since=$(last_modified)
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "If-Unmodified-Since: $since" \
+        --data-binary "second" ${BASE_URL}/uploads/put_test.txt)
+    check "$status" 204 "second"
+
+    # Someone else changes the file after the client read its date
+    echo -n "Testing PUT with If-Unmodified-Since on a changed file... "
+    since=$(last_modified)
+    sleep 1.1
+    printf "changed elsewhere" > www/uploads/put_test.txt
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X PUT -H "If-Unmodified-Since: $since" \
+        --data-binary "third" ${BASE_URL}/uploads/put_test.txt)
+    check "$status" 412 "changed elsewhere"
+
+    echo -n "Testing DELETE with If-Unmodified-Since on a changed file... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE -H "If-Unmodified-Since: $since" \
+        ${BASE_URL}/uploads/put_test.txt)
+    check "$status" 412 "changed elsewhere"
+
+    echo -n "Testing DELETE with If-Unmodified-Since on an unchanged file... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X DELETE -H "If-Unmodified-Since: $(last_modified)" \
+        ${BASE_URL}/uploads/put_test.txt)
+    if [ "$status" = "204" ] && [ ! -e www/uploads/put_test.txt ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $status)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status)"
+        ((fail_count++))
+    fi
+    rm -f www/uploads/put_test.txt
+}
+
+test_request_uri_forms() {
+    check() {
+        if [ "$2" = "$3" ]; then
+            echo -e "${GREEN}PASS${NC}"
+            ((pass_count++))
+        else
+            echo -e "${RED}FAIL${NC} ($1: got '$2', expected '$3')"
+            ((fail_count++))
+        fi
+    }
+
+    echo -n "Testing a URI without a query... "
+    check "QUERY_STRING" "$(curl -s -w '|%{http_code}' ${BASE_URL}/cgi-bin/query.py | tr -d '\n')" "|200"
+
+    echo -n "Testing a URI with an empty query... "
+    check "QUERY_STRING" "$(curl -s -w '|%{http_code}' "${BASE_URL}/cgi-bin/query.py?" | tr -d '\n')" "|200"
+    location=$(curl -s -D - -o /dev/null "${BASE_URL}/docs?" | tr -d '\r' | sed -n 's/^Location: //p')
+    echo -n "Testing a directory redirect keeps an empty query... "
+    check "Location" "$location" "/docs/?"
+
+    # Only the first '?' starts the query, so download=1 is still a parameter
+    echo -n "Testing query parameters after a second '?'... "
+    disposition=$(curl -s -D - -o /dev/null "${BASE_URL}/index.html?a=1?b=2&download=1" | tr -d '\r' \
+        | grep -i "^Content-Disposition:" | cut -d';' -f1)
+    check "Content-Disposition" "$disposition" "Content-Disposition: attachment"
+
+    # Clients never send a fragment, so one in the request line is malformed
+    echo -n "Testing a fragment in the request target... "
+    exec 3<>/dev/tcp/localhost/8080
+    # The server may refuse the request line before the rest is written; the
+    # subshell keeps that broken pipe from ending the suite
+    (printf "GET /index.html#top HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n" >&3) 2> /dev/null
+    status=$(timeout 5 head -n 1 <&3 | tr -d '\r' | cut -d' ' -f2)
+    exec 3<&-
+    check "status" "$status" "400"
+}
+
+test_keepalive_timeout() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing keepalive_timeout... ${YELLOW}SKIP${NC} (Needs the webserv binary and python3)"
+        return
+    fi
+    echo -n "Testing keepalive_timeout... "
+    printf "server {\n    listen 127.0.0.1:9126;\n    keepalive_timeout 1;\n    client_header_timeout 4;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_keepalive_timeout.conf
+    "$WEBSERV_BIN" /tmp/test_keepalive_timeout.conf > /dev/null 2>&1 &
+    timeout_pid=$!
+    sleep 0.5
+    # An idle connection goes after keepalive_timeout; one part way through
+    # its headers outlives that but not client_header_timeout, however
+    # slowly it keeps sending
+    result=$(python3 - <<'PYEOF'
+import select, socket, time
+
+def connect():
+    return socket.create_connection(("127.0.0.1", 9126), timeout=10)
+
+def closed_after(sock):
+    start = time.time()
+    while sock.recv(65536):
+        pass
+    return time.time() - start
+
+idle = connect()
+idle.sendall(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+data = b""
+while b"</html>" not in data:
+    data += idle.recv(65536)
+idle_secs = closed_after(idle)
+
+slow = connect()
+slow.sendall(b"GET /index.html HTTP/1.1\r\n")
+time.sleep(2.5)
+still_open = not select.select([slow], [], [], 0)[0]
+slow.sendall(b"Host: localhost\r\n\r\n")
+answered = slow.recv(65536).startswith(b"HTTP/1.1 200")
+
+trickle = connect()
+start = time.time()
+closed = False
+for byte in b"GET /index.html HTTP/1.1\r\nX-Slow: " + b"a" * 20:
+    if select.select([trickle], [], [], 0)[0]:
+        closed = True
+        break
+    trickle.sendall(bytes([byte]))
+    time.sleep(0.5)
+trickle_secs = time.time() - start
+
+print(1 <= idle_secs < 3, still_open and answered, closed and 4 <= trickle_secs < 7)
+PYEOF
+)
+    kill $timeout_pid 2>/dev/null
+    wait $timeout_pid 2>/dev/null
+    rm -f /tmp/test_keepalive_timeout.conf
+    if [ "$result" = "True True True" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_range_ignored() {
+    echo -n "Testing Range on CGI returns the full 200... "
+    headers=$(curl -s -D - -o /tmp/test_cgi_range.txt -H "Range: bytes=0-3" ${BASE_URL}/cgi-bin/ranges.py | tr -d '\r')
+    status=$(echo "$headers" | head -n 1)
+    body=$(cat /tmp/test_cgi_range.txt)
+    if [ "$status" = "HTTP/1.1 200 OK" ] && [ "$body" = "0123456789abcdef" ] \
+        && ! echo "$headers" | grep -qi "^accept-ranges:" && ! echo "$headers" | grep -qi "^content-range:"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $status, body '$body')"
+        ((fail_count++))
+    fi
+    rm -f /tmp/test_cgi_range.txt
+}
+
+test_strict_bind() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing strict_bind... ${YELLOW}SKIP${NC} (Needs the webserv binary and python3)"
+        return
+    fi
+    echo -n "Testing strict_bind off skips a taken port... "
+    # Something else already holds 9127
+    python3 -c "import socket, time; s = socket.socket(); s.bind(('127.0.0.1', 9127)); s.listen(); time.sleep(30)" &
+    holder_pid=$!
+    sleep 0.5
+    printf "server {\n    listen 127.0.0.1:9127;\n    strict_bind off;\n    location / {\n        root ./www;\n    }\n}\nserver {\n    listen 127.0.0.1:9128;\n    strict_bind off;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_strict_bind.conf
+    "$WEBSERV_BIN" /tmp/test_strict_bind.conf > /tmp/test_strict_bind.log 2>&1 &
+    bind_pid=$!
+    sleep 0.5
+    status=$(curl -s -o /dev/null -w "%{http_code}" --max-time 5 http://127.0.0.1:9128/index.html)
+    kill $bind_pid 2>/dev/null
+    wait $bind_pid 2>/dev/null
+    if [ "$status" = "200" ] && grep -q "Failed to bind 127.0.0.1:9127" /tmp/test_strict_bind.log \
+        && grep -q "Bound 1 of 2 addresses; skipped 127.0.0.1:9127" /tmp/test_strict_bind.log; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $status)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing strict_bind on refuses to start... "
+    sed -i '/strict_bind/d' /tmp/test_strict_bind.conf
+    timeout 5 "$WEBSERV_BIN" /tmp/test_strict_bind.conf > /tmp/test_strict_bind.log 2>&1
+    code=$?
+    if [ "$code" = "1" ] && grep -q "Failed to bind 127.0.0.1:9127" /tmp/test_strict_bind.log; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Exit: $code)"
+        ((fail_count++))
+    fi
+    kill $holder_pid 2>/dev/null
+    wait $holder_pid 2>/dev/null
+    rm -f /tmp/test_strict_bind.conf /tmp/test_strict_bind.log
+}
+
+test_cgi_delete_put() {
+    echo -n "Testing DELETE runs a CGI script... "
+    response=$(curl -s -X DELETE ${BASE_URL}/cgi-bin/method.py | tr -d '\n')
+    if [ "$response" = "DELETE " ] && [ -f ./cgi-bin/method.py ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing PUT runs a CGI script... "
+    response=$(curl -s -X PUT --data-binary "payload" ${BASE_URL}/cgi-bin/method.py | tr -d '\n')
+    if [ "$response" = "PUT payload" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_working_dir_path() {
+    echo -n "Testing CGI runs in its own directory by default... "
+    response=$(curl -s ${BASE_URL}/cgi-bin/shell_env.py)
+    expected=$(printf "%s\n/usr/local/bin:/usr/bin:/bin" "$(cd ./cgi-bin && pwd -P)")
+    if [ "$response" = "$expected" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing cgi_working_dir and cgi_path_env... "
+    response=$(curl -s ${BASE_URL}/cgi-env/shell_env.py)
+    expected=$(printf "%s\n/opt/webserv/bin:/usr/bin:/bin" "$(cd ./www && pwd -P)")
+    if [ "$response" = "$expected" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $response)"
+        ((fail_count++))
+    fi
+}
+
+test_interrupted_io() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v cc > /dev/null; then
+        echo -e "Testing reads and writes retried after EINTR... ${YELLOW}SKIP${NC} (Needs the webserv binary and a C compiler)"
+        return
+    fi
+    echo -n "Testing reads and writes retried after EINTR... "
+    # Preloaded over the server so every other socket send and recv fails
+    # with EINTR, as if a signal arrived mid-call
+    cat > /tmp/test_eintr.c <<'EOF'
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <errno.h>
+#include <sys/socket.h>
+
+static unsigned long calls;
+
+ssize_t send(int fd, const void *buf, size_t len, int flags) {
+    static ssize_t (*real)(int, const void *, size_t, int);
+    if (!real) real = dlsym(RTLD_NEXT, "send");
+    if (calls++ % 2 == 0) { errno = EINTR; return -1; }
+    return real(fd, buf, len, flags);
+}
+
+ssize_t recv(int fd, void *buf, size_t len, int flags) {
+    static ssize_t (*real)(int, void *, size_t, int);
+    if (!real) real = dlsym(RTLD_NEXT, "recv");
+    if (calls++ % 2 == 0) { errno = EINTR; return -1; }
+    return real(fd, buf, len, flags);
+}
+EOF
+    if ! cc -shared -fPIC -o /tmp/test_eintr.so /tmp/test_eintr.c -ldl 2> /dev/null; then
+        echo -e "${YELLOW}SKIP${NC} (Could not build the preload library)"
+        rm -f /tmp/test_eintr.c
+        return
+    fi
+    printf "server {\n    listen 127.0.0.1:9129;\n    write_chunk_size 1k;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_eintr.conf
+    LD_PRELOAD=/tmp/test_eintr.so "$WEBSERV_BIN" /tmp/test_eintr.conf > /dev/null 2>&1 &
+    eintr_pid=$!
+    sleep 0.5
+    # Both responses go out over one connection, each in many writes
+    result=$(curl -s --max-time 5 -w "%{http_code} %{num_connects}\n" -o /tmp/test_eintr_1.html http://127.0.0.1:9129/index.html \
+        -o /tmp/test_eintr_2.html http://127.0.0.1:9129/index.html | tr '\n' ' ')
+    kill $eintr_pid 2>/dev/null
+    wait $eintr_pid 2>/dev/null
+    if [ "$result" = "200 1 200 0 " ] && cmp -s /tmp/test_eintr_1.html ./www/index.html \
+        && cmp -s /tmp/test_eintr_2.html ./www/index.html; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $result)"
+        ((fail_count++))
+    fi
+    rm -f /tmp/test_eintr.c /tmp/test_eintr.so /tmp/test_eintr.conf /tmp/test_eintr_1.html /tmp/test_eintr_2.html
+}
+
+test_max_concurrent_requests() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing max_concurrent_requests... ${YELLOW}SKIP${NC} (Needs the webserv binary and python3)"
+        return
+    fi
+    echo -n "Testing max_concurrent_requests queues and then refuses... "
+    printf "server {\n    listen 127.0.0.1:9130;\n    max_concurrent_requests 2;\n    request_queue_size 2;\n    location / {\n        root ./cgi-bin;\n        cgi_extension .py;\n        cgi_path /usr/bin/python3;\n    }\n}\n" > /tmp/test_max_concurrent.conf
+    "$WEBSERV_BIN" /tmp/test_max_concurrent.conf > /dev/null 2>&1 &
+    concurrent_pid=$!
+    sleep 0.5
+    # Six requests for a script taking a second: two run, two wait for a
+    # slot and the last two find the queue full
+    result=$(python3 - <<'PYEOF'
+import socket, time
+
+socks = []
+for _ in range(6):
+    sock = socket.create_connection(("127.0.0.1", 9130), timeout=10)
+    sock.sendall(b"GET /stream.py HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+    socks.append(sock)
+    time.sleep(0.1)
+
+results = []
+for sock in socks:
+    data = b""
+    while True:
+        chunk = sock.recv(65536)
+        if not chunk:
+            break
+        data += chunk
+    status = data.split(b" ", 2)[1].decode() if data else "none"
+    results.append(status + ("+" if b"part2" in data else ""))
+print(" ".join(results))
+PYEOF
+)
+    kill $concurrent_pid 2>/dev/null
+    wait $concurrent_pid 2>/dev/null
+    rm -f /tmp/test_max_concurrent.conf
+    if [ "$result" = "200+ 200+ 200+ 200+ 503 503" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $result)"
+        ((fail_count++))
+    fi
+}
+
+test_cors_preflight() {
+    echo -n "Testing CORS preflight headers... "
+    headers=$(curl -s -D - -o /dev/null -X OPTIONS -H "Origin: https://app.example.com" \
+        -H "Access-Control-Request-Method: PUT" -H "Access-Control-Request-Headers: X-Api-Key" \
+        ${BASE_URL}/cors/index.html | tr -d '\r')
+    if echo "$headers" | head -n 1 | grep -q "^HTTP/1.1 204" \
+        && echo "$headers" | grep -qx "Access-Control-Allow-Origin: https://app.example.com" \
+        && echo "$headers" | grep -qx "Access-Control-Allow-Methods: GET, POST, PUT" \
+        && echo "$headers" | grep -qx "Access-Control-Allow-Headers: Content-Type, X-Api-Key" \
+        && echo "$headers" | grep -qx "Access-Control-Max-Age: 600" \
+        && echo "$headers" | grep -qi "^Vary:.*Origin"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $(echo "$headers" | head -n 1))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing CORS preflight from another origin... "
+    headers=$(curl -s -D - -o /dev/null -X OPTIONS -H "Origin: https://evil.example.com" \
+        -H "Access-Control-Request-Method: PUT" ${BASE_URL}/cors/index.html | tr -d '\r')
+    if echo "$headers" | head -n 1 | grep -q "^HTTP/1.1 204" && ! echo "$headers" | grep -qi "^Access-Control-"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $(echo "$headers" | grep -i "^Access-Control-" | head -n 1))"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing plain OPTIONS is not a preflight... "
+    status=$(curl -s -o /dev/null -w "%{http_code}" -X OPTIONS -H "Origin: https://app.example.com" ${BASE_URL}/cors/index.html)
+    if [ "$status" = "405" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $status)"
+        ((fail_count++))
+    fi
+}
+
+test_manual_clock() {
+    clock_bin="$(dirname "$WEBSERV_BIN")/examples/manual_clock"
+    if [ ! -x "$clock_bin" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing timeouts under a manual clock... ${YELLOW}SKIP${NC} (Build it with cargo build --examples; needs python3)"
+        return
+    fi
+    echo -n "Testing keepalive_timeout expires exactly at its boundary... "
+    "$clock_bin" > /dev/null 2>&1 &
+    clock_pid=$!
+    sleep 0.5
+    # Time only moves through /advance, so the idle connection survives
+    # exactly 30s and goes a millisecond later
+    result=$(python3 - <<'PYEOF'
+import select, socket, subprocess, time
+
+def advance(ms):
+    subprocess.run(["curl", "-s", "-o", "/dev/null", "http://127.0.0.1:9131/advance?ms=%d" % ms], check=True)
+    # Timeouts are checked at least once a second
+    time.sleep(1.5)
+
+def closed(sock):
+    if not select.select([sock], [], [], 0)[0]:
+        return False
+    return sock.recv(65536) == b""
+
+sock = socket.create_connection(("127.0.0.1", 9131), timeout=10)
+sock.sendall(b"GET /advance?ms=0 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+data = b""
+while b"advanced\n" not in data:
+    data += sock.recv(65536)
+
+advance(30000)
+at_boundary = closed(sock)
+advance(1)
+past_boundary = closed(sock)
+print(at_boundary, past_boundary)
+PYEOF
+)
+    if [ "$result" = "False True" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} ($result)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing sessions expire exactly at session_timeout... "
+    cookie=$(curl -s -D - -o /dev/null http://127.0.0.1:9131/index.html | tr -d '\r' | sed -n 's/^Set-Cookie: \(sessionid=[^;]*\).*/\1/p')
+    curl -s -o /dev/null "http://127.0.0.1:9131/advance?ms=3599000"
+    before=$(curl -s -D - -o /dev/null -H "Cookie: $cookie" http://127.0.0.1:9131/index.html | grep -ci "^Set-Cookie:")
+    # The request above renewed the session, so a full timeout from now
+    curl -s -o /dev/null "http://127.0.0.1:9131/advance?ms=3600000"
+    after=$(curl -s -D - -o /dev/null -H "Cookie: $cookie" http://127.0.0.1:9131/index.html | grep -ci "^Set-Cookie:")
+    kill $clock_pid 2>/dev/null
+    wait $clock_pid 2>/dev/null
+    if [ -n "$cookie" ] && [ "$before" = "0" ] && [ "$after" = "1" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Cookie: $cookie, renewed: $before, reissued: $after)"
+        ((fail_count++))
+    fi
+}
+
+test_return_targets() {
+    echo -n "Testing return keeps a relative target as written... "
+    result=$(curl -s -D - -o /dev/null ${BASE_URL}/redirect | tr -d '\r' | grep -i "^Location:")
+    if [ "$result" = "Location: /" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $result)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing return to an absolute URL... "
+    result=$(curl -s -o /dev/null -w "%{http_code} %{redirect_url}" ${BASE_URL}/moved)
+    if [ "$result" = "302 https://example.org/moved" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $result)"
+        ((fail_count++))
+    fi
+
+    if [ ! -x "$WEBSERV_BIN" ]; then
+        echo -e "Testing absolute_redirect... ${YELLOW}SKIP${NC} (No webserv binary built)"
+        return
+    fi
+    echo -n "Testing absolute_redirect expands a relative target... "
+    printf "server {\n    listen 127.0.0.1:9132;\n    absolute_redirect on;\n    location /old {\n        return 301 /new?x=1;\n    }\n    location /away {\n        return 302 https://example.org/;\n    }\n}\n" > /tmp/test_absolute_redirect.conf
+    "$WEBSERV_BIN" /tmp/test_absolute_redirect.conf > /dev/null 2>&1 &
+    absolute_pid=$!
+    sleep 0.5
+    expanded=$(curl -s -D - -o /dev/null http://127.0.0.1:9132/old | tr -d '\r' | grep -i "^Location:")
+    named=$(curl -s -D - -o /dev/null -H "Host: www.example.com" http://127.0.0.1:9132/old | tr -d '\r' | grep -i "^Location:")
+    away=$(curl -s -D - -o /dev/null http://127.0.0.1:9132/away | tr -d '\r' | grep -i "^Location:")
+    kill $absolute_pid 2>/dev/null
+    wait $absolute_pid 2>/dev/null
+    if [ "$expanded" = "Location: http://127.0.0.1:9132/new?x=1" ] \
+        && [ "$named" = "Location: http://www.example.com/new?x=1" ] \
+        && [ "$away" = "Location: https://example.org/" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $expanded, $named, $away)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a return target that is neither a path nor a URL is refused... "
+    printf "server {\n    listen 127.0.0.1:9132;\n    location /old {\n        return 301 elsewhere;\n    }\n}\n" > /tmp/test_absolute_redirect.conf
+    output=$(timeout 5 "$WEBSERV_BIN" /tmp/test_absolute_redirect.conf 2>&1)
+    code=$?
+    rm -f /tmp/test_absolute_redirect.conf
+    if [ "$code" = "1" ] && echo "$output" | grep -q "Invalid return 301 target 'elsewhere'"; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Exit: $code)"
+        ((fail_count++))
+    fi
+}
+
+test_quiet_close() {
+    if [ ! -x "$WEBSERV_BIN" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing an immediate close logs nothing... ${YELLOW}SKIP${NC} (Needs the webserv binary and python3)"
+        return
+    fi
+    echo -n "Testing an immediate close logs nothing... "
+    printf "server {\n    listen 127.0.0.1:9133;\n    location / {\n        root ./www;\n    }\n}\n" > /tmp/test_quiet_close.conf
+    "$WEBSERV_BIN" /tmp/test_quiet_close.conf > /dev/null 2> /tmp/test_quiet_close.log &
+    quiet_pid=$!
+    sleep 0.5
+    # Scanner-style connects, a reset and a keep-alive client leaving
+    # between requests are all routine
+    python3 - <<'PYEOF'
+import socket, struct, time
+
+def connect():
+    return socket.create_connection(("127.0.0.1", 9133), timeout=5)
+
+for _ in range(5):
+    connect().close()
+
+reset = connect()
+reset.setsockopt(socket.SOL_SOCKET, socket.SO_LINGER, struct.pack("ii", 1, 0))
+reset.close()
+
+kept = connect()
+kept.sendall(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+data = b""
+while b"</html>" not in data:
+    data += kept.recv(65536)
+kept.close()
+time.sleep(0.5)
+PYEOF
+    quiet=$(cat /tmp/test_quiet_close.log)
+    # A client vanishing part way through its headers is worth a warning
+    python3 -c "
+import socket, time
+sock = socket.create_connection(('127.0.0.1', 9133), timeout=5)
+sock.sendall(b'GET /index.html HTTP/1.1\r\nHost: loc')
+time.sleep(0.2)
+sock.close()
+time.sleep(0.5)
+"
+    warned=$(grep -c "closed mid-request" /tmp/test_quiet_close.log)
+    kill $quiet_pid 2>/dev/null
+    wait $quiet_pid 2>/dev/null
+    rm -f /tmp/test_quiet_close.conf /tmp/test_quiet_close.log
+    if [ -z "$quiet" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Logged: $quiet)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a close mid-request is logged... "
+    if [ "$warned" = "1" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Warnings: $warned)"
+        ((fail_count++))
+    fi
+}
+
+test_cgi_stdin_nonblocking() {
+    echo -n "Testing a script slow to read its body doesn't stall the server... "
+    head -c 900000 /dev/zero | tr '\0' 'x' > /tmp/test_cgi_stdin.bin
+    curl -s --max-time 10 -X POST --data-binary @/tmp/test_cgi_stdin.bin ${BASE_URL}/cgi-bin/slow_reader.py > /tmp/test_cgi_stdin.out &
+    slow_pid=$!
+    sleep 0.3
+    # The body is far bigger than a pipe holds, so a blocking write would
+    # still be waiting on the script
+    status=$(curl -s --max-time 0.5 -o /dev/null -w "%{http_code}" ${BASE_URL}/)
+    wait $slow_pid
+    response=$(tr -d '\n' < /tmp/test_cgi_stdin.out)
+    if [ "$status" = "200" ] && [ "$response" = "read=900000" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Other client: $status, script: $response)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a script that never reads its body... "
+    status=$(curl -s --max-time 5 -o /dev/null -w "%{http_code}" -X POST --data-binary @/tmp/test_cgi_stdin.bin ${BASE_URL}/cgi-bin/fail.py)
+    after=$(curl -s --max-time 2 -o /dev/null -w "%{http_code}" ${BASE_URL}/)
+    rm -f /tmp/test_cgi_stdin.bin /tmp/test_cgi_stdin.out
+    if [ "$status" = "500" ] && [ "$after" = "200" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $status, then $after)"
+        ((fail_count++))
+    fi
+}
+
+test_memory_files() {
+    memory_bin="$(dirname "$WEBSERV_BIN")/examples/memory_files"
+    if [ ! -x "$memory_bin" ]; then
+        echo -e "Testing files served from memory... ${YELLOW}SKIP${NC} (Build it with cargo build --examples)"
+        return
+    fi
+    echo -n "Testing files served from memory... "
+    "$memory_bin" > /dev/null 2>&1 &
+    memory_pid=$!
+    sleep 0.5
+    # /memory doesn't exist on disk; every answer comes from the provider
+    index=$(curl -s http://127.0.0.1:9134/)
+    file=$(curl -s http://127.0.0.1:9134/docs/a.txt)
+    listing=$(curl -s http://127.0.0.1:9134/docs/)
+    missing=$(curl -s -o /dev/null -w "%{http_code}" http://127.0.0.1:9134/docs/c.txt)
+    kill $memory_pid 2>/dev/null
+    wait $memory_pid 2>/dev/null
+    if [ "$index" = "<h1>from memory</h1>" ] && [ "$file" = "first file" ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Got: $index, $file)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing a directory listing from memory... "
+    if echo "$listing" | grep -q 'href="/docs/a.txt"' && echo "$listing" | grep -q 'href="/docs/b.txt"'; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Listing: $listing)"
+        ((fail_count++))
+    fi
+
+    echo -n "Testing an unregistered path gets 404 from memory... "
+    if [ "$missing" = "404" ]; then
+        echo -e "${GREEN}PASS${NC} (Status: $missing)"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Status: $missing)"
+        ((fail_count++))
+    fi
+}
+
+test_capturing_observer() {
+    observer_bin="$(dirname "$WEBSERV_BIN")/examples/capturing_observer"
+    if [ ! -x "$observer_bin" ] || ! command -v python3 > /dev/null; then
+        echo -e "Testing an observer sees a served request... ${YELLOW}SKIP${NC} (Build it with cargo build --examples; needs python3)"
+        return
+    fi
+    echo -n "Testing an observer sees a served request... "
+    "$observer_bin" > /dev/null 2>&1 &
+    observer_pid=$!
+    sleep 0.5
+    # The headers arrive 300ms apart, which the duration has to cover
+    local_addr=$(python3 -c "
+import socket, time
+sock = socket.create_connection(('127.0.0.1', 9135), timeout=5)
+sock.sendall(b'GET /index.html HTTP/1.1\r\nHost: loc')
+time.sleep(0.3)
+sock.sendall(b'alhost\r\nConnection: close\r\n\r\n')
+while sock.recv(65536):
+    pass
+print('%s:%d' % sock.getsockname())
+")
+    captured=$(curl -s http://127.0.0.1:9135/captured)
+    kill $observer_pid 2>/dev/null
+    wait $observer_pid 2>/dev/null
+    expected="remote=$local_addr method=GET uri=/index.html status=200 bytes=$(stat -c %s www/index.html)"
+    duration=$(echo "$captured" | sed -n 's/.* duration_ms=\([0-9]*\)$/\1/p')
+    if [ "$(echo "$captured" | sed 's/ duration_ms=.*//')" = "$expected" ] \
+        && [ -n "$duration" ] && [ "$duration" -ge 300 ] && [ "$duration" -lt 2000 ]; then
+        echo -e "${GREEN}PASS${NC}"
+        ((pass_count++))
+    else
+        echo -e "${RED}FAIL${NC} (Captured: $captured)"
+        ((fail_count++))
+    fi
+}
+
 test_keep_alive() {
     echo -n "Testing keep-alive connection... "
     response=$(curl -s -i ${BASE_URL}/ | grep -i "Connection: keep-alive")
@@ -139,6 +3531,104 @@ test_method_not_allowed
 test_cookies
 test_file_upload
 test_multiple_requests
+test_pipelining
+test_metrics
+test_gzip_static
+test_internal_redirect_loop
+test_duplicate_slashes
+test_cgi_environment
+test_cgi_location_redirect
+test_cgi_status_phrase
+test_multi_range
+test_large_file
+test_listing_not_modified
+test_index_options
+test_cgi_empty_post
+test_unlimited_body_size
+test_client_abort
+test_listen_all_interfaces
+test_conditional_precedence
+test_overlong_body
+test_leading_crlf
+test_pipelining_fairness
+test_request_duration
+test_session_cookie_config
+test_sessions_off
+test_vary_header
+test_directory_delete
+test_config_check
+test_quoted_values
+test_default_server
+test_status_reasons
+test_drain
+test_method_override
+test_websocket_upgrade
+test_request_id
+test_cgi_streaming
+test_internal_location
+test_chunked_keep_alive
+test_cgi_accel_redirect
+test_tcp_keepalive
+test_slow_reader
+test_embedded_config
+test_shared_error_page
+test_error_page_redirect
+test_cgi_header_filtering
+test_gzip_compression
+test_connection_semantics
+test_upload_naming
+test_upload_requires_upload_dir
+test_force_download
+test_read_coalescing
+test_not_modified_headers
+test_log_sizes
+test_fd_reuse_stress
+test_expect_continue
+test_session_list
+test_cgi_trailers
+test_invalid_content_length
+test_duplicate_content_length
+test_log_format_template
+test_fragmented_headers
+test_directory_slash_redirect
+test_return_444
+test_chunk_buffer_limit
+test_path_error_log
+test_location_types
+test_http_date_formats
+test_default_favicon
+test_cgi_query_string
+test_limit_except
+test_options_asterisk
+test_body_round_trip
+test_accept_throttling
+test_cgi_debug
+test_cgi_binary_body
+test_limit_conn_per_ip
+test_catch_all_server_name
+test_obs_fold
+test_max_open_files
+test_proxy_protocol
+test_retry_after
+test_session_fast_path
+test_resolution_precedence
+test_write_chunk_size
+test_put_unmodified_since
+test_request_uri_forms
+test_keepalive_timeout
+test_cgi_range_ignored
+test_strict_bind
+test_cgi_delete_put
+test_cgi_working_dir_path
+test_interrupted_io
+test_max_concurrent_requests
+test_cors_preflight
+test_manual_clock
+test_return_targets
+test_quiet_close
+test_cgi_stdin_nonblocking
+test_memory_files
+test_capturing_observer
 test_keep_alive
 
 echo